@@ -0,0 +1,438 @@
+// Buddy allocator with per-order free lists.
+//
+// A zone [start, end) is divided into blocks of min_size << order bytes,
+// and every block is aligned to its size by its physical address.
+// The buddy of a block is found by flipping the bit of its size,
+//
+//   buddy = addr ^ (min_size << order)
+//
+// and two free buddies of the same order are combined into a block of order + 1.
+//
+// Free blocks are linked by the lists of their orders,
+// and the link is stored in the first bytes of each free block.
+// The state of every min_size block is stored in a byte array at the head of the zone.
+//
+// state encoding
+// 0b1xxx_xxxx: head of a free block, xxx_xxxx is the order
+// 0b0xxx_xxxx: head of a used block, xxx_xxxx is the order
+// 0b0111_1111: not a head, a part of a larger block or the state array
+//
+// e.g. min_size = 4KiB, 8 blocks
+//  0    1    2    3    4    5    6    7
+// meta used used tail free tail tail tail
+//   -    0    0    -    2    -    -    -
+//
+// the allocation takes O(MAX_ORDER) time,
+// because it takes the first block of the smallest non-empty list
+// and divides it until the requested order.
+
+use core::ptr::null_mut;
+
+/// the largest block is min_size << MAX_ORDER bytes
+pub const MAX_ORDER: usize = 24;
+const NUM_ORDERS: usize = MAX_ORDER + 1;
+
+const META_FREE: u8 = 0x80;
+const META_ORDER_MASK: u8 = 0x7f;
+const META_TAIL: u8 = 0x7f;
+
+/// link of the free list, placed at the head of a free block
+struct FreeBlock {
+    next: *mut FreeBlock,
+    prev: *mut FreeBlock,
+}
+
+pub struct BuddyAlloc {
+    min_size: usize,
+    start: usize, // start address of the zone, the state array is placed here
+    end: usize,
+    meta: *mut u8, // state of each min_size block
+    free_lists: [*mut FreeBlock; NUM_ORDERS],
+    free_bytes: usize,
+}
+
+impl Default for BuddyAlloc {
+    fn default() -> Self {
+        BuddyAlloc::new()
+    }
+}
+
+impl BuddyAlloc {
+    /// an empty allocator, call init before use
+    pub const fn new() -> BuddyAlloc {
+        BuddyAlloc {
+            min_size: 0,
+            start: 0,
+            end: 0,
+            meta: null_mut(),
+            free_lists: [null_mut(); NUM_ORDERS],
+            free_bytes: 0,
+        }
+    }
+
+    /// manage [start, end) by blocks of min_size << order bytes,
+    /// min_size must be a power of two, and start and end are aligned to min_size
+    ///
+    /// # Safety
+    ///
+    /// [start, end) must be valid memory, and used by only this allocator
+    pub unsafe fn init(&mut self, min_size: usize, start: usize, end: usize) {
+        if !min_size.is_power_of_two() || min_size < core::mem::size_of::<FreeBlock>() {
+            panic!("invalid min_size of the buddy allocator");
+        }
+
+        *self = BuddyAlloc::new();
+        self.min_size = min_size;
+
+        let start = (start + min_size - 1) & !(min_size - 1);
+        let end = end & !(min_size - 1);
+        if end <= start {
+            return;
+        }
+
+        // the state array takes blocks at the head of the zone
+        let num_blocks = (end - start) / min_size;
+        let meta_blocks = (num_blocks - 1) / min_size + 1;
+        if meta_blocks >= num_blocks {
+            return;
+        }
+
+        self.start = start;
+        self.end = end;
+        self.meta = start as *mut u8;
+        self.meta.write_bytes(META_TAIL, num_blocks);
+
+        // divide the rest into the largest aligned blocks
+        let mut addr = start + meta_blocks * min_size;
+        while addr < end {
+            let mut order = 0;
+            while order < MAX_ORDER {
+                let size = min_size << (order + 1);
+                if addr & (size - 1) != 0 || end - addr < size {
+                    break;
+                }
+                order += 1;
+            }
+
+            self.push(addr, order);
+            addr += min_size << order;
+        }
+    }
+
+    /// free bytes of the zone
+    pub fn free_bytes(&self) -> usize {
+        self.free_bytes
+    }
+
+    /// whether addr is in the zone
+    pub fn contains(&self, addr: usize) -> bool {
+        self.start <= addr && addr < self.end
+    }
+
+    pub fn mem_alloc(&mut self, size: usize) -> Option<*mut u8> {
+        self.mem_alloc_aligned(size, 1)
+    }
+
+    /// allocate a block whose address is a multiple of align,
+    /// return None if no such block is free
+    ///
+    /// blocks are aligned to their size,
+    /// so a block of align bytes is allocated if align is larger than size
+    pub fn mem_alloc_aligned(&mut self, size: usize, align: usize) -> Option<*mut u8> {
+        if !align.is_power_of_two() {
+            return None;
+        }
+
+        let order = self.get_order(size.max(align))?;
+
+        // the smallest free block
+        let mut cur = (order..NUM_ORDERS).find(|o| !self.free_lists[*o].is_null())?;
+        let addr = self.free_lists[cur] as usize;
+        self.remove(addr, cur);
+
+        // divide it, and free the upper halves
+        while cur > order {
+            cur -= 1;
+            self.push(addr + (self.min_size << cur), cur);
+        }
+
+        self.set_meta(addr, order as u8);
+        Some(addr as *mut u8)
+    }
+
+    pub fn mem_free(&mut self, addr: *mut u8) {
+        let mut addr = addr as usize;
+        if !self.contains(addr) || addr & (self.min_size - 1) != 0 {
+            panic!("freed invalid address");
+        }
+
+        let meta = self.get_meta(addr);
+        if meta & META_FREE != 0 || (meta == META_TAIL && self.is_in_free_block(addr)) {
+            panic!("freed unused memory");
+        }
+        if meta == META_TAIL {
+            panic!("freed invalid address");
+        }
+
+        // combine buddies while both are free
+        let mut order = meta as usize;
+        while order < MAX_ORDER {
+            let size = self.min_size << order;
+            let buddy = addr ^ size;
+            if !self.contains(buddy) || self.end - buddy < size {
+                break;
+            }
+            if self.get_meta(buddy) != META_FREE | order as u8 {
+                break;
+            }
+
+            self.remove(buddy, order);
+            self.set_meta(addr.max(buddy), META_TAIL);
+            addr = addr.min(buddy);
+            order += 1;
+        }
+
+        self.push(addr, order);
+    }
+
+    /// resize the used block at addr to have size bytes without moving it,
+    /// return false if the following blocks are not free
+    ///
+    /// a block is grown by combining its free buddies at higher addresses,
+    /// and shrunk by freeing its upper halves
+    pub fn mem_realloc(&mut self, addr: *mut u8, size: usize) -> bool {
+        let addr = addr as usize;
+        if !self.contains(addr) || addr & (self.min_size - 1) != 0 {
+            panic!("resized invalid address");
+        }
+
+        let meta = self.get_meta(addr);
+        if meta & META_FREE != 0 || meta == META_TAIL {
+            panic!("resized unused memory");
+        }
+
+        let cur = meta as usize;
+        let order = match self.get_order(size) {
+            Some(order) => order,
+            None => return false,
+        };
+
+        if order < cur {
+            // free the upper halves, they cannot be combined because the lower half is used
+            for o in order..cur {
+                self.push(addr + (self.min_size << o), o);
+            }
+        } else if order > cur {
+            // every buddy must be a free block above addr
+            for o in cur..order {
+                let buddy = addr + (self.min_size << o);
+                if addr & ((self.min_size << (o + 1)) - 1) != 0
+                    || !self.contains(buddy)
+                    || self.end - buddy < self.min_size << o
+                    || self.get_meta(buddy) != META_FREE | o as u8
+                {
+                    return false;
+                }
+            }
+
+            for o in cur..order {
+                self.remove(addr + (self.min_size << o), o);
+            }
+        }
+
+        self.set_meta(addr, order as u8);
+        true
+    }
+
+    /// the smallest order whose block has size bytes
+    fn get_order(&self, size: usize) -> Option<usize> {
+        let mut order = 0;
+        while (self.min_size << order) < size {
+            order += 1;
+            if order > MAX_ORDER {
+                return None;
+            }
+        }
+        Some(order)
+    }
+
+    /// whether addr is a part of a free block
+    fn is_in_free_block(&self, addr: usize) -> bool {
+        for order in 1..NUM_ORDERS {
+            let head = addr & !((self.min_size << order) - 1);
+            if head < self.start {
+                return false;
+            }
+
+            let meta = self.get_meta(head);
+            if meta != META_TAIL {
+                return meta & META_FREE != 0 && (meta & META_ORDER_MASK) as usize >= order;
+            }
+        }
+        false
+    }
+
+    fn get_meta(&self, addr: usize) -> u8 {
+        let idx = (addr - self.start) / self.min_size;
+        unsafe { *self.meta.add(idx) }
+    }
+
+    fn set_meta(&mut self, addr: usize, val: u8) {
+        let idx = (addr - self.start) / self.min_size;
+        unsafe { *self.meta.add(idx) = val };
+    }
+
+    /// push a free block to the head of the list
+    fn push(&mut self, addr: usize, order: usize) {
+        let block = addr as *mut FreeBlock;
+        let head = self.free_lists[order];
+        unsafe {
+            (*block).next = head;
+            (*block).prev = null_mut();
+            if !head.is_null() {
+                (*head).prev = block;
+            }
+        }
+
+        self.free_lists[order] = block;
+        self.set_meta(addr, META_FREE | order as u8);
+        self.free_bytes += self.min_size << order;
+    }
+
+    /// remove a free block from the list
+    fn remove(&mut self, addr: usize, order: usize) {
+        let block = addr as *mut FreeBlock;
+        unsafe {
+            let next = (*block).next;
+            let prev = (*block).prev;
+            if prev.is_null() {
+                self.free_lists[order] = next;
+            } else {
+                (*prev).next = next;
+            }
+            if !next.is_null() {
+                (*next).prev = prev;
+            }
+        }
+
+        self.set_meta(addr, META_TAIL);
+        self.free_bytes -= self.min_size << order;
+    }
+}
+
+/// the maximum number of zones
+pub const MAX_ZONES: usize = 4;
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum ZoneKind {
+    Dma,    // memory reachable by DMA masters
+    Normal, // the rest
+}
+
+/// buddy allocators of disjoint zones
+pub struct Zones {
+    zones: [BuddyAlloc; MAX_ZONES],
+    kinds: [ZoneKind; MAX_ZONES],
+    num: usize,
+}
+
+impl Default for Zones {
+    fn default() -> Self {
+        Zones::new()
+    }
+}
+
+const EMPTY_ZONE: BuddyAlloc = BuddyAlloc::new();
+
+impl Zones {
+    pub const fn new() -> Zones {
+        Zones {
+            zones: [EMPTY_ZONE; MAX_ZONES],
+            kinds: [ZoneKind::Normal; MAX_ZONES],
+            num: 0,
+        }
+    }
+
+    /// add a zone of [start, end)
+    ///
+    /// # Safety
+    ///
+    /// [start, end) must be valid memory, and used by only this allocator
+    pub unsafe fn add(&mut self, kind: ZoneKind, min_size: usize, start: usize, end: usize) {
+        for z in self.zones[..self.num].iter() {
+            if start < z.end && z.start < end {
+                panic!("overlapped zones");
+            }
+        }
+
+        if self.num == MAX_ZONES {
+            panic!("too many zones");
+        }
+
+        self.zones[self.num].init(min_size, start, end);
+        self.kinds[self.num] = kind;
+        self.num += 1;
+    }
+
+    /// free bytes of the zones of kind
+    pub fn free_bytes(&self, kind: ZoneKind) -> usize {
+        self.iter(kind).map(|z| z.free_bytes()).sum()
+    }
+
+    pub fn mem_alloc(&mut self, size: usize) -> Option<*mut u8> {
+        self.mem_alloc_aligned(size, 1)
+    }
+
+    /// allocate from the normal zones,
+    /// and then from the DMA zones if the normal zones are exhausted
+    pub fn mem_alloc_aligned(&mut self, size: usize, align: usize) -> Option<*mut u8> {
+        match self.mem_alloc_zone(ZoneKind::Normal, size, align) {
+            None => self.mem_alloc_zone(ZoneKind::Dma, size, align),
+            ret => ret,
+        }
+    }
+
+    /// allocate from the zones of kind only
+    pub fn mem_alloc_zone(&mut self, kind: ZoneKind, size: usize, align: usize) -> Option<*mut u8> {
+        let num = self.num;
+        for (z, k) in self.zones[..num].iter_mut().zip(self.kinds.iter()) {
+            if *k == kind {
+                if let Some(ptr) = z.mem_alloc_aligned(size, align) {
+                    return Some(ptr);
+                }
+            }
+        }
+        None
+    }
+
+    /// resize the used block at addr without moving it, see BuddyAlloc::mem_realloc
+    pub fn mem_realloc(&mut self, addr: *mut u8, size: usize) -> bool {
+        match self.find(addr) {
+            Some(z) => z.mem_realloc(addr, size),
+            None => panic!("resized invalid address"),
+        }
+    }
+
+    pub fn mem_free(&mut self, addr: *mut u8) {
+        match self.find(addr) {
+            Some(z) => z.mem_free(addr),
+            None => panic!("freed invalid address"),
+        }
+    }
+
+    /// the zone containing addr
+    fn find(&mut self, addr: *mut u8) -> Option<&mut BuddyAlloc> {
+        let num = self.num;
+        self.zones[..num]
+            .iter_mut()
+            .find(|z| z.contains(addr as usize))
+    }
+
+    fn iter(&self, kind: ZoneKind) -> impl Iterator<Item = &BuddyAlloc> {
+        self.zones[..self.num]
+            .iter()
+            .zip(self.kinds.iter())
+            .filter(move |(_, k)| **k == kind)
+            .map(|(z, _)| z)
+    }
+}
@@ -0,0 +1,21 @@
+//! Memory allocators of baremetalisp.
+//!
+//! - buddy: buddy allocator for large objects, divided into zones
+//! - slab: slab allocator for small objects
+//! - magazine: per-CPU caches in front of the slab allocator
+//! - pager: 64KiB page allocator used by the slab allocator, also for contiguous pages
+//!
+//! This crate does not depend on the hardware, so it can be built and tested
+//! on the host with a simulated arena.
+
+#![cfg_attr(not(test), no_std)]
+
+pub mod buddy;
+pub mod magazine;
+pub mod pager;
+pub mod slab;
+
+/// counting leading zero
+fn clz(n: u64) -> u64 {
+    n.leading_zeros() as u64
+}
@@ -0,0 +1,173 @@
+// Per-CPU caches of small objects in front of the slab allocator.
+//
+// Each CPU has a magazine, a small stack of free objects, for every small size class.
+// alloc and free pop and push the magazine of the current CPU without any lock.
+// Only when the magazine is empty or full, the caller takes the lock of the shared
+// slab allocator, and moves BATCH objects at once by refill or drain.
+//
+// the size classes are the same as the slab allocator, 8 bytes meta data is
+// placed before each object
+// class: 0   1   2   3    4    5    6
+// slot:  16  32  64  128  256  512  1024
+// size:  8   24  56  120  248  504  1016
+
+use crate::slab::{self, SlabAllocator, MIN_ALIGN};
+use core::alloc::Layout;
+use core::ptr::null_mut;
+
+/// the number of objects cached by a magazine
+pub const MAGAZINE_SIZE: usize = 32;
+
+/// the number of objects moved between a magazine and the slab allocator at once
+pub const BATCH: usize = MAGAZINE_SIZE / 2;
+
+const NUM_CLASSES: usize = 7;
+
+/// size class of layout, or None if it is not cached
+fn get_class(layout: &Layout) -> Option<usize> {
+    if layout.align() > MIN_ALIGN {
+        return None;
+    }
+
+    match slab::get_class(layout) {
+        Some(class) if class < NUM_CLASSES => Some(class),
+        _ => None,
+    }
+}
+
+/// whether layout is served by magazines
+pub fn is_cached(layout: &Layout) -> bool {
+    get_class(layout).is_some()
+}
+
+/// the largest size of class
+fn class_size(class: usize) -> usize {
+    slab::CLASS_SIZES[class] - 8
+}
+
+#[derive(Copy, Clone)]
+struct Magazine {
+    num: usize,
+    objs: [*mut u8; MAGAZINE_SIZE],
+}
+
+impl Magazine {
+    const fn new() -> Magazine {
+        Magazine {
+            num: 0,
+            objs: [null_mut(); MAGAZINE_SIZE],
+        }
+    }
+
+    fn pop(&mut self) -> Option<*mut u8> {
+        if self.num == 0 {
+            None
+        } else {
+            self.num -= 1;
+            Some(self.objs[self.num])
+        }
+    }
+
+    fn push(&mut self, ptr: *mut u8) -> bool {
+        if self.num == MAGAZINE_SIZE {
+            false
+        } else {
+            self.objs[self.num] = ptr;
+            self.num += 1;
+            true
+        }
+    }
+}
+
+/// magazines of a CPU
+pub struct CpuCache {
+    mags: [Magazine; NUM_CLASSES],
+}
+
+impl Default for CpuCache {
+    fn default() -> Self {
+        CpuCache::new()
+    }
+}
+
+impl CpuCache {
+    pub const fn new() -> CpuCache {
+        CpuCache {
+            mags: [Magazine::new(); NUM_CLASSES],
+        }
+    }
+
+    /// take a cached object,
+    /// return None if the magazine is empty or layout is not cached
+    pub fn alloc(&mut self, layout: &Layout) -> Option<*mut u8> {
+        self.mags[get_class(layout)?].pop()
+    }
+
+    /// cache a freed object,
+    /// return false if the magazine is full or layout is not cached
+    pub fn free(&mut self, ptr: *mut u8, layout: &Layout) -> bool {
+        match get_class(layout) {
+            Some(class) => self.mags[class].push(ptr),
+            None => false,
+        }
+    }
+
+    /// fill the empty magazine of layout from slab, and take an object,
+    /// return null if slab has no memory
+    ///
+    /// # Safety
+    ///
+    /// the caller must hold the lock of slab, and layout must be cached
+    pub unsafe fn refill(&mut self, layout: &Layout, slab: &mut SlabAllocator) -> *mut u8 {
+        let class = get_class(layout).unwrap();
+        let class_layout = Layout::from_size_align_unchecked(class_size(class), MIN_ALIGN);
+
+        let mag = &mut self.mags[class];
+        while mag.num < BATCH {
+            let ptr = slab.alloc(class_layout);
+            if ptr.is_null() {
+                break;
+            }
+            mag.push(ptr);
+        }
+
+        mag.pop().unwrap_or(null_mut())
+    }
+
+    /// return the half of the full magazine of layout to slab, and cache ptr
+    ///
+    /// # Safety
+    ///
+    /// the caller must hold the lock of slab, and layout must be cached
+    pub unsafe fn drain(&mut self, ptr: *mut u8, layout: &Layout, slab: &mut SlabAllocator) {
+        let class = get_class(layout).unwrap();
+        let class_layout = Layout::from_size_align_unchecked(class_size(class), MIN_ALIGN);
+
+        let mag = &mut self.mags[class];
+        while mag.num > MAGAZINE_SIZE - BATCH {
+            let obj = mag.pop().unwrap();
+            slab.dealloc(obj, class_layout);
+        }
+
+        mag.push(ptr);
+    }
+
+    /// return every cached object to slab
+    ///
+    /// # Safety
+    ///
+    /// the caller must hold the lock of slab
+    pub unsafe fn flush(&mut self, slab: &mut SlabAllocator) {
+        for (class, mag) in self.mags.iter_mut().enumerate() {
+            let class_layout = Layout::from_size_align_unchecked(class_size(class), MIN_ALIGN);
+            while let Some(obj) = mag.pop() {
+                slab.dealloc(obj, class_layout);
+            }
+        }
+    }
+
+    /// the number of cached objects
+    pub fn num_cached(&self) -> usize {
+        self.mags.iter().map(|m| m.num).sum()
+    }
+}
@@ -0,0 +1,367 @@
+use crate::clz;
+use core::{cmp, fmt};
+
+/// the size of a page managed by a PageManager
+pub const PAGE_SIZE: usize = 64 * 1024;
+
+/// the maximum number of pages managed by a PageManager
+const MAX_PAGES: usize = 64 * 64 * 64;
+
+/// 64 * 64 * 64 pages = 64 * 64 * 64 * 64KiB = 16GiB
+///
+/// ```
+/// use allocator::pager::PageManager;
+///
+/// static mut PAGEMNG: PageManager = PageManager::new();
+///
+/// unsafe { PAGEMNG.set_range(0, 64 * 1024 * 1024 * 512) };
+///
+/// // 4 contiguous pages aligned to 1MiB
+/// let addr = unsafe { PAGEMNG.alloc_contiguous(4, 1024 * 1024) }.unwrap();
+/// assert_eq!(addr & (1024 * 1024 - 1), 0);
+/// unsafe { PAGEMNG.free_contiguous(addr, 4) };
+/// ```
+pub struct PageManager {
+    start: usize,
+    end: usize,
+    vacancy_books: u64,
+    vacancy_pages: [u64; 64],
+    book: [Book; 64],
+}
+
+impl Default for PageManager {
+    fn default() -> Self {
+        PageManager::new()
+    }
+}
+
+#[derive(Copy, Clone)]
+pub struct Book {
+    pages: [u64; 64],
+}
+
+impl PageManager {
+    pub const fn new() -> PageManager {
+        PageManager {
+            start: 0,
+            end: 0,
+            vacancy_books: 0,
+            vacancy_pages: [0; 64],
+            book: [Book { pages: [0; 64] }; 64],
+        }
+    }
+
+    pub fn set_range(&mut self, start: usize, end: usize) {
+        self.start = start;
+        self.end = end;
+    }
+
+    pub fn alloc(&mut self) -> Option<usize> {
+        if self.vacancy_books == !0 {
+            return None;
+        }
+
+        let idx1 = clz(!self.vacancy_books) as usize;
+        let idx2 = clz(!self.vacancy_pages[idx1]) as usize;
+        let idx3 = clz(!self.book[idx1].pages[idx2]) as usize;
+
+        let addr =
+            64 * 1024 * 64 * 64 * idx1 + 64 * 1024 * 64 * idx2 + 64 * 1024 * idx3 + self.start;
+
+        if addr + PAGE_SIZE > self.end {
+            return None;
+        }
+
+        self.book[idx1].pages[idx2] |= 1 << (63 - idx3);
+        if self.book[idx1].pages[idx2] == !0 {
+            self.vacancy_pages[idx1] |= 1 << (63 - idx2);
+            if self.vacancy_pages[idx1] == !0 {
+                self.vacancy_books |= 1 << (63 - idx1);
+            }
+        }
+
+        Some(addr)
+    }
+
+    pub fn free(&mut self, addr: usize) {
+        self.free_contiguous(addr, 1);
+    }
+
+    /// allocate num contiguous pages whose address is aligned to align bytes,
+    /// the lowest run of free pages is taken
+    pub fn alloc_contiguous(&mut self, num: usize, align: usize) -> Option<usize> {
+        if num == 0 || !align.is_power_of_two() {
+            return None;
+        }
+
+        // indices of aligned pages are first + step * n
+        let align = cmp::max(align, PAGE_SIZE);
+        let step = align / PAGE_SIZE;
+        let first = (align - self.start % align) % align / PAGE_SIZE;
+
+        let mut i = first;
+        while i + num <= self.num_pages() {
+            match self.find_used(i, i + num) {
+                Some(used) => {
+                    // the next aligned page after the used one
+                    let n = used + 1 - first;
+                    i = first + ((n - 1) / step + 1) * step;
+                }
+                None => {
+                    for idx in i..i + num {
+                        self.set_used(idx);
+                    }
+                    return Some(self.start + i * PAGE_SIZE);
+                }
+            }
+        }
+
+        None
+    }
+
+    /// free num pages from addr allocated by alloc_contiguous
+    pub fn free_contiguous(&mut self, addr: usize, num: usize) {
+        if addr < self.start
+            || (addr - self.start) & 0xFFFF != 0
+            || num > self.end.saturating_sub(addr) / PAGE_SIZE
+        {
+            panic!("invalid address");
+        }
+
+        // indices are relative to the start address as alloc
+        let i = (addr - self.start) / PAGE_SIZE;
+        for idx in i..i + num {
+            self.set_free(idx);
+        }
+    }
+
+    /// the usage of pages and their fragmentation
+    pub fn usage(&self) -> Usage {
+        let mut usage = Usage {
+            total: self.num_pages(),
+            ..Usage::default()
+        };
+
+        let mut run = 0;
+        for i in 0..usage.total {
+            if self.is_used(i) {
+                usage.used += 1;
+                run = 0;
+            } else {
+                if run == 0 {
+                    usage.free_runs += 1;
+                }
+                run += 1;
+                usage.largest_run = cmp::max(usage.largest_run, run);
+            }
+        }
+
+        usage
+    }
+
+    fn num_pages(&self) -> usize {
+        cmp::min(self.end.saturating_sub(self.start) / PAGE_SIZE, MAX_PAGES)
+    }
+
+    fn is_used(&self, idx: usize) -> bool {
+        self.book[idx >> 12].pages[(idx >> 6) & 63] & (1 << (63 - (idx & 63))) != 0
+    }
+
+    fn set_used(&mut self, idx: usize) {
+        let (idx1, idx2, idx3) = (idx >> 12, (idx >> 6) & 63, idx & 63);
+
+        self.book[idx1].pages[idx2] |= 1 << (63 - idx3);
+        if self.book[idx1].pages[idx2] == !0 {
+            self.vacancy_pages[idx1] |= 1 << (63 - idx2);
+            if self.vacancy_pages[idx1] == !0 {
+                self.vacancy_books |= 1 << (63 - idx1);
+            }
+        }
+    }
+
+    fn set_free(&mut self, idx: usize) {
+        let (idx1, idx2, idx3) = (idx >> 12, (idx >> 6) & 63, idx & 63);
+
+        self.book[idx1].pages[idx2] &= !(1 << (63 - idx3));
+        self.vacancy_pages[idx1] &= !(1 << (63 - idx2));
+        self.vacancy_books &= !(1 << (63 - idx1));
+    }
+
+    /// the first used page in [start, end)
+    fn find_used(&self, start: usize, end: usize) -> Option<usize> {
+        let mut idx = start;
+        while idx < end {
+            // bits of pages from idx
+            let bits = self.book[idx >> 12].pages[(idx >> 6) & 63] << (idx & 63);
+            if bits == 0 {
+                idx = (idx | 63) + 1;
+            } else {
+                let used = idx + clz(bits) as usize;
+                return if used < end { Some(used) } else { None };
+            }
+        }
+
+        None
+    }
+}
+
+/// usage of a PageManager, the numbers are counted in pages
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct Usage {
+    pub total: usize,
+    pub used: usize,
+    pub free_runs: usize,   // the number of runs of contiguous free pages
+    pub largest_run: usize, // the largest run of contiguous free pages
+}
+
+impl Usage {
+    pub fn free(&self) -> usize {
+        self.total - self.used
+    }
+
+    /// 0 if every free page is contiguous, and near 100 if free pages are scattered
+    pub fn fragmentation(&self) -> usize {
+        if self.free() == 0 {
+            0
+        } else {
+            100 - self.largest_run * 100 / self.free()
+        }
+    }
+}
+
+impl fmt::Display for Usage {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "pages: {} used, {} free of {}, {} free runs, the largest run is {} pages, fragmentation {}%",
+            self.used,
+            self.free(),
+            self.total,
+            self.free_runs,
+            self.largest_run,
+            self.fragmentation()
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PAGE: usize = 64 * 1024;
+
+    #[test]
+    fn alloc_in_order() {
+        let start = 0x4000_0000;
+        let mut pages = PageManager::new();
+        pages.set_range(start, start + PAGE * 3);
+
+        assert_eq!(pages.alloc(), Some(start));
+        assert_eq!(pages.alloc(), Some(start + PAGE));
+        assert_eq!(pages.alloc(), Some(start + PAGE * 2));
+        assert_eq!(pages.alloc(), None);
+
+        pages.free(start + PAGE);
+        assert_eq!(pages.alloc(), Some(start + PAGE));
+    }
+
+    #[test]
+    fn free_unaligned_start() {
+        // the start address is not aligned to a book nor a set of pages
+        let start = 0x4123_0000;
+        let mut pages = PageManager::new();
+        pages.set_range(start, start + PAGE * 64 * 3);
+
+        let addrs: Vec<usize> = (0..64 * 2 + 1).map(|_| pages.alloc().unwrap()).collect();
+        for addr in addrs.iter() {
+            pages.free(*addr);
+        }
+
+        for addr in addrs.iter() {
+            assert_eq!(pages.alloc(), Some(*addr));
+        }
+    }
+
+    #[test]
+    fn alloc_contiguous_aligned() {
+        let start = 0x4001_0000;
+        let mut pages = PageManager::new();
+        pages.set_range(start, start + PAGE * 64);
+
+        // the first page is taken, so the next 4 pages are from the second
+        assert_eq!(pages.alloc(), Some(start));
+        assert_eq!(pages.alloc_contiguous(4, PAGE), Some(start + PAGE));
+
+        // 0x4008_0000 is the first address aligned to 256KiB after them
+        assert_eq!(pages.alloc_contiguous(2, PAGE * 4), Some(0x4008_0000));
+        assert_eq!(pages.alloc(), Some(start + PAGE * 5));
+
+        pages.free_contiguous(start + PAGE, 4);
+        assert_eq!(pages.alloc_contiguous(5, PAGE), Some(0x400a_0000));
+        assert_eq!(pages.alloc_contiguous(4, PAGE), Some(start + PAGE));
+
+        assert_eq!(pages.alloc_contiguous(64, PAGE), None);
+        assert_eq!(pages.alloc_contiguous(0, PAGE), None);
+        assert_eq!(pages.alloc_contiguous(1, PAGE * 3), None);
+    }
+
+    #[test]
+    fn alloc_contiguous_across_books() {
+        let start = 0x4000_0000;
+        let mut pages = PageManager::new();
+        pages.set_range(start, start + PAGE * 64 * 64 * 2);
+
+        for _ in 0..64 * 64 - 10 {
+            pages.alloc().unwrap();
+        }
+
+        let addr = pages.alloc_contiguous(100, PAGE).unwrap();
+        assert_eq!(addr, start + PAGE * (64 * 64 - 10));
+        assert_eq!(pages.alloc(), Some(addr + PAGE * 100));
+    }
+
+    #[test]
+    fn usage() {
+        let start = 0x4000_0000;
+        let mut pages = PageManager::new();
+        pages.set_range(start, start + PAGE * 10);
+
+        let addrs: Vec<usize> = (0..10).map(|_| pages.alloc().unwrap()).collect();
+        pages.free(addrs[1]);
+        pages.free_contiguous(addrs[4], 3);
+
+        let usage = pages.usage();
+        assert_eq!(
+            usage,
+            Usage {
+                total: 10,
+                used: 6,
+                free_runs: 2,
+                largest_run: 3,
+            }
+        );
+        assert_eq!(usage.fragmentation(), 25);
+        assert_eq!(
+            format!("{}", usage),
+            "pages: 6 used, 4 free of 10, 2 free runs, the largest run is 3 pages, fragmentation 25%"
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "invalid address")]
+    fn free_contiguous_out_of_range() {
+        let start = 0x4000_0000;
+        let mut pages = PageManager::new();
+        pages.set_range(start, start + PAGE * 4);
+        pages.free_contiguous(start + PAGE * 2, 3);
+    }
+
+    #[test]
+    #[should_panic(expected = "invalid address")]
+    fn free_out_of_range() {
+        let start = 0x4000_0000;
+        let mut pages = PageManager::new();
+        pages.set_range(start, start + PAGE);
+        pages.free(start + PAGE);
+    }
+}
@@ -1,14 +1,70 @@
-use alloc::alloc::handle_alloc_error;
 use core::alloc::Layout;
-use core::ptr::null_mut;
+use core::ptr::{copy, null_mut};
 
-use crate::aarch64::bits::clz;
-use crate::driver;
+use crate::clz;
 use crate::pager;
 
-pub const MAX_SLAB_SIZE: usize = 65512;
+/// the maximum bytes served by the slab allocator,
+/// the largest slab has 16 bytes of meta data
+pub const MAX_SLAB_SIZE: usize = 65512 - 16;
 
-struct SlabAllocator {
+/// objects are aligned to 8 bytes at least
+pub const MIN_ALIGN: usize = 8;
+
+/// padding to align an object, the first object of a slot is 8 bytes aligned
+fn padding(layout: &Layout) -> usize {
+    layout.align().saturating_sub(MIN_ALIGN)
+}
+
+/// whether the slab allocator can serve layout
+pub fn fits(layout: &Layout) -> bool {
+    match layout.size().checked_add(padding(layout)) {
+        Some(size) => size <= MAX_SLAB_SIZE,
+        None => false,
+    }
+}
+
+/// the number of size classes
+pub const NUM_CLASSES: usize = 13;
+
+/// slot size of each class, including meta data
+pub const CLASS_SIZES: [usize; NUM_CLASSES] = [
+    16, 32, 64, 128, 256, 512, 1024, 2040, 4088, 8184, 16376, 32752, 65512,
+];
+
+/// class of the slab serving size bytes of 8 bytes alignment
+fn size_class(size: usize) -> Option<usize> {
+    if size <= 1024 - 8 {
+        let n = clz(size as u64 + 8 - 1).min(60) as usize;
+        return Some(60 - n);
+    }
+
+    (7..NUM_CLASSES).find(|c| size <= CLASS_SIZES[*c] - 16)
+}
+
+/// class of the slab serving layout, None if layout does not fit
+pub fn get_class(layout: &Layout) -> Option<usize> {
+    if fits(layout) {
+        size_class(layout.size() + padding(layout))
+    } else {
+        None
+    }
+}
+
+/// whether an object of layout can be resized to new_size in place,
+/// that is, the object stays in the same slab
+pub fn can_resize(layout: &Layout, new_size: usize) -> bool {
+    if layout.align() > MIN_ALIGN || new_size > MAX_SLAB_SIZE {
+        return false;
+    }
+
+    match size_class(layout.size()) {
+        Some(class) => size_class(new_size) == Some(class),
+        None => false,
+    }
+}
+
+pub struct SlabAllocator {
     pages: pager::PageManager,
 
     slab16_partial: *mut Slab16,
@@ -41,13 +97,13 @@ struct SlabAllocator {
 }
 
 macro_rules! AllocMemory {
-    ($t:ident, $slab_partial:ident, $slab_full:ident, $layout:ident) => {
+    ($s:expr, $t:ident, $slab_partial:ident, $slab_full:ident) => {
         let r = {
-            match SLAB_ALLOC.$slab_partial.as_mut() {
+            match $s.$slab_partial.as_mut() {
                 Some(partial) => {
                     let ret = partial.alloc();
                     if partial.is_full() {
-                        let ptr = SLAB_ALLOC.$slab_partial;
+                        let ptr = $s.$slab_partial;
                         match partial.next.as_mut() {
                             Some(next) => {
                                 next.prev = null_mut();
@@ -55,21 +111,21 @@ macro_rules! AllocMemory {
                             None => {}
                         }
 
-                        SLAB_ALLOC.$slab_partial = partial.next;
-                        match SLAB_ALLOC.$slab_full.as_mut() {
+                        $s.$slab_partial = partial.next;
+                        match $s.$slab_full.as_mut() {
                             Some(full) => {
                                 full.prev = ptr;
                             }
                             None => {}
                         }
 
-                        partial.next = SLAB_ALLOC.$slab_full;
-                        SLAB_ALLOC.$slab_full = ptr;
+                        partial.next = $s.$slab_full;
+                        $s.$slab_full = ptr;
                     }
                     ret
                 }
                 None => {
-                    match SLAB_ALLOC.pages.alloc() {
+                    match $s.pages.alloc() {
                         Some(addr) => {
                             let ptr = addr as *mut $t;
                             match ptr.as_mut() {
@@ -78,16 +134,16 @@ macro_rules! AllocMemory {
                                     let ret = slab.alloc();
                                     if slab.is_full() {
                                         // for only Slab65512
-                                        match SLAB_ALLOC.$slab_full.as_mut() {
+                                        match $s.$slab_full.as_mut() {
                                             Some(full) => {
                                                 full.prev = ptr;
                                             }
                                             None => {}
                                         }
-                                        slab.next = SLAB_ALLOC.$slab_full;
-                                        SLAB_ALLOC.$slab_full = ptr;
+                                        slab.next = $s.$slab_full;
+                                        $s.$slab_full = ptr;
                                     } else {
-                                        SLAB_ALLOC.$slab_partial = ptr;
+                                        $s.$slab_partial = ptr;
                                     }
                                     ret
                                 }
@@ -100,15 +156,12 @@ macro_rules! AllocMemory {
             }
         };
 
-        if r == null_mut() {
-            handle_alloc_error($layout);
-        }
         return r;
     };
 }
 
 macro_rules! DeallocMemory {
-    ($ptr:expr, $addr_slab:expr, $t:ident, $slab_partial:ident, $slab_full:ident) => {
+    ($s:expr, $ptr:expr, $addr_slab:expr, $t:ident, $slab_partial:ident, $slab_full:ident) => {
         match ($addr_slab as *mut $t).as_mut() {
             Some(slab) => {
                 let is_full = slab.is_full();
@@ -119,7 +172,7 @@ macro_rules! DeallocMemory {
                             prev.next = slab.next;
                         }
                         None => {
-                            SLAB_ALLOC.$slab_full = slab.next;
+                            $s.$slab_full = slab.next;
                         }
                     }
 
@@ -131,9 +184,9 @@ macro_rules! DeallocMemory {
                     }
 
                     if slab.is_empty() {
-                        SLAB_ALLOC.pages.free($addr_slab as usize);
+                        $s.pages.free($addr_slab as usize);
                     } else {
-                        match SLAB_ALLOC.$slab_partial.as_mut() {
+                        match $s.$slab_partial.as_mut() {
                             Some(partial) => {
                                 partial.prev = slab;
                                 slab.next = partial;
@@ -143,7 +196,7 @@ macro_rules! DeallocMemory {
                             }
                         }
                         slab.prev = null_mut();
-                        SLAB_ALLOC.$slab_partial = slab;
+                        $s.$slab_partial = slab;
                     }
                 } else {
                     if slab.is_empty() {
@@ -152,7 +205,7 @@ macro_rules! DeallocMemory {
                                 prev.next = slab.next;
                             }
                             None => {
-                                SLAB_ALLOC.$slab_partial = slab.next;
+                                $s.$slab_partial = slab.next;
                             }
                         }
 
@@ -163,7 +216,7 @@ macro_rules! DeallocMemory {
                             None => {}
                         }
 
-                        SLAB_ALLOC.pages.free($addr_slab as usize);
+                        $s.pages.free($addr_slab as usize);
                     }
                 }
             }
@@ -172,177 +225,257 @@ macro_rules! DeallocMemory {
     };
 }
 
-pub unsafe fn slab_alloc(layout: Layout) -> *mut u8 {
-    /*
-            driver::uart::puts("alloc:\n");
-            driver::uart::puts("  layout.size: ");
-            driver::uart::decimal(layout.size() as u64);
-            driver::uart::puts("\n  layout.align: ");
-            driver::uart::decimal(layout.align() as u64);
-            driver::uart::puts("\n");
-    */
-    let size = layout.size();
-    let n = clz(size as u64 + 8 - 1);
-
-    match n {
-        61 => {
-            AllocMemory!(Slab16, slab16_partial, slab16_full, layout);
-        }
-        60 => {
-            AllocMemory!(Slab16, slab16_partial, slab16_full, layout);
-        }
-        59 => {
-            AllocMemory!(Slab32, slab32_partial, slab32_full, layout);
-        }
-        58 => {
-            AllocMemory!(Slab64, slab64_partial, slab64_full, layout);
+impl Default for SlabAllocator {
+    fn default() -> Self {
+        SlabAllocator::new()
+    }
+}
+
+impl SlabAllocator {
+    pub const fn new() -> SlabAllocator {
+        SlabAllocator {
+            pages: pager::PageManager::new(),
+            slab16_partial: null_mut(),
+            slab32_partial: null_mut(),
+            slab64_partial: null_mut(),
+            slab128_partial: null_mut(),
+            slab256_partial: null_mut(),
+            slab512_partial: null_mut(),
+            slab1024_partial: null_mut(),
+            slab2040_partial: null_mut(),
+            slab4088_partial: null_mut(),
+            slab8184_partial: null_mut(),
+            slab16376_partial: null_mut(),
+            slab32752_partial: null_mut(),
+            slab65512_partial: null_mut(),
+            slab16_full: null_mut(),
+            slab32_full: null_mut(),
+            slab64_full: null_mut(),
+            slab128_full: null_mut(),
+            slab256_full: null_mut(),
+            slab512_full: null_mut(),
+            slab1024_full: null_mut(),
+            slab2040_full: null_mut(),
+            slab4088_full: null_mut(),
+            slab8184_full: null_mut(),
+            slab16376_full: null_mut(),
+            slab32752_full: null_mut(),
+            slab65512_full: null_mut(),
         }
-        57 => {
-            AllocMemory!(Slab128, slab128_partial, slab128_full, layout);
+    }
+
+    /// pages for slabs are taken from [start, end)
+    pub fn init(&mut self, start: usize, end: usize) {
+        self.pages.set_range(start, end);
+    }
+
+    /// allocate a memory region for layout,
+    /// return null if no page is available or layout does not fit
+    ///
+    /// # Safety
+    ///
+    /// the range passed to init must be valid memory, and used by only this allocator
+    pub unsafe fn alloc(&mut self, layout: Layout) -> *mut u8 {
+        if !fits(&layout) {
+            return null_mut();
         }
-        56 => {
-            AllocMemory!(Slab256, slab256_partial, slab256_full, layout);
+
+        if layout.align() <= MIN_ALIGN {
+            return self.alloc_size(layout.size());
         }
-        55 => {
-            AllocMemory!(Slab512, slab512_partial, slab512_full, layout);
+
+        // allocate a padded object and shift it to the alignment
+        let ptr = self.alloc_size(layout.size() + padding(&layout));
+        if ptr.is_null() {
+            return ptr;
         }
-        54 => {
-            AllocMemory!(Slab1024, slab1024_partial, slab1024_full, layout);
+
+        let addr = ptr as usize;
+        let aligned = (addr + layout.align() - 1) & !(layout.align() - 1);
+        if aligned != addr {
+            // move the meta data just before the object,
+            // addr_slab is at -8 bytes and the index of large slabs is at -16 bytes
+            let addr_slab = *((addr - 8) as *const u64);
+            let size = *((addr_slab + 65532) as *const u32);
+            let len = if is_large(size) { 16 } else { 8 };
+            copy((addr - len) as *const u8, (aligned - len) as *mut u8, len);
         }
-        _ => {
-            if size <= 4088 - 16 {
-                if size <= 2040 - 16 {
-                    // Slab2040
-                    AllocMemory!(Slab2040, slab2040_partial, slab2040_full, layout);
-                } else {
-                    // Slab4088
-                    AllocMemory!(Slab4088, slab4088_partial, slab4088_full, layout);
-                }
-            } else {
-                if size <= 16376 - 16 {
-                    if size <= 8184 - 16 {
-                        // Slab8184
-                        AllocMemory!(Slab8184, slab8184_partial, slab8184_full, layout);
+
+        aligned as *mut u8
+    }
+
+    /// allocate a memory region of 8 bytes aligned
+    unsafe fn alloc_size(&mut self, size: usize) -> *mut u8 {
+        let n = clz(size as u64 + 8 - 1);
+
+        match n {
+            61 => {
+                AllocMemory!(self, Slab16, slab16_partial, slab16_full);
+            }
+            60 => {
+                AllocMemory!(self, Slab16, slab16_partial, slab16_full);
+            }
+            59 => {
+                AllocMemory!(self, Slab32, slab32_partial, slab32_full);
+            }
+            58 => {
+                AllocMemory!(self, Slab64, slab64_partial, slab64_full);
+            }
+            57 => {
+                AllocMemory!(self, Slab128, slab128_partial, slab128_full);
+            }
+            56 => {
+                AllocMemory!(self, Slab256, slab256_partial, slab256_full);
+            }
+            55 => {
+                AllocMemory!(self, Slab512, slab512_partial, slab512_full);
+            }
+            54 => {
+                AllocMemory!(self, Slab1024, slab1024_partial, slab1024_full);
+            }
+            _ => {
+                if size <= 4088 - 16 {
+                    if size <= 2040 - 16 {
+                        // Slab2040
+                        AllocMemory!(self, Slab2040, slab2040_partial, slab2040_full);
                     } else {
-                        // Slab16376
-                        AllocMemory!(Slab16376, slab16376_partial, slab16376_full, layout);
+                        // Slab4088
+                        AllocMemory!(self, Slab4088, slab4088_partial, slab4088_full);
                     }
                 } else {
-                    if size <= 32752 - 16 {
-                        // Slab32752
-                        AllocMemory!(Slab32752, slab32752_partial, slab32752_full, layout);
-                    } else if size <= 65512 - 16 {
-                        // Slab65512
-                        AllocMemory!(Slab65512, slab65512_partial, slab65512_full, layout);
+                    if size <= 16376 - 16 {
+                        if size <= 8184 - 16 {
+                            // Slab8184
+                            AllocMemory!(self, Slab8184, slab8184_partial, slab8184_full);
+                        } else {
+                            // Slab16376
+                            AllocMemory!(self, Slab16376, slab16376_partial, slab16376_full);
+                        }
                     } else {
-                        handle_alloc_error(layout);
+                        if size <= 32752 - 16 {
+                            // Slab32752
+                            AllocMemory!(self, Slab32752, slab32752_partial, slab32752_full);
+                        } else if size <= 65512 - 16 {
+                            // Slab65512
+                            AllocMemory!(self, Slab65512, slab65512_partial, slab65512_full);
+                        } else {
+                            null_mut()
+                        }
                     }
                 }
             }
         }
     }
-}
 
-pub unsafe fn slab_dealloc(ptr: *mut u8, _layout: Layout) {
-    let addr_slab = *((ptr as usize - 8) as *const u64);
-    let size = *((addr_slab + 65532) as *const u32);
-    /*
-            driver::uart::puts("dealloc:\n");
-            driver::uart::puts("  ptr: 0x");
-            driver::uart::hex(ptr as u64);
-            driver::uart::puts("\n");
-            driver::uart::puts("  addr_slab: 0x");
-            driver::uart::hex(addr_slab);
-            driver::uart::puts("\n");
-            driver::uart::puts("  size: ");
-            driver::uart::decimal(size as u64);
-            driver::uart::puts("\n");
-    */
-    match size {
-        16 => {
-            DeallocMemory!(ptr, addr_slab, Slab16, slab16_partial, slab16_full);
-        }
-        32 => {
-            DeallocMemory!(ptr, addr_slab, Slab32, slab32_partial, slab32_full);
-        }
-        64 => {
-            DeallocMemory!(ptr, addr_slab, Slab64, slab64_partial, slab64_full);
-        }
-        128 => {
-            DeallocMemory!(ptr, addr_slab, Slab128, slab128_partial, slab128_full);
-        }
-        256 => {
-            DeallocMemory!(ptr, addr_slab, Slab256, slab256_partial, slab256_full);
-        }
-        512 => {
-            DeallocMemory!(ptr, addr_slab, Slab512, slab512_partial, slab512_full);
-        }
-        1024 => {
-            DeallocMemory!(ptr, addr_slab, Slab1024, slab1024_partial, slab1024_full);
-        }
-        2040 => {
-            DeallocMemory!(ptr, addr_slab, Slab2040, slab2040_partial, slab2040_full);
-        }
-        4088 => {
-            DeallocMemory!(ptr, addr_slab, Slab4088, slab4088_partial, slab4088_full);
-        }
-        8184 => {
-            DeallocMemory!(ptr, addr_slab, Slab8184, slab8184_partial, slab8184_full);
-        }
-        16376 => {
-            DeallocMemory!(ptr, addr_slab, Slab16376, slab16376_partial, slab16376_full);
-        }
-        32752 => {
-            DeallocMemory!(ptr, addr_slab, Slab32752, slab32752_partial, slab32752_full);
-        }
-        65512 => {
-            DeallocMemory!(ptr, addr_slab, Slab65512, slab65512_partial, slab65512_full);
+    /// deallocate the memory region pointed by ptr which is returned by alloc,
+    /// the meta data is placed just before ptr even if it is shifted for the alignment
+    ///
+    /// # Safety
+    ///
+    /// ptr must be returned by alloc of this allocator, and not be freed yet
+    pub unsafe fn dealloc(&mut self, ptr: *mut u8, _layout: Layout) {
+        let addr_slab = *((ptr as usize - 8) as *const u64);
+        let size = *((addr_slab + 65532) as *const u32);
+        match size {
+            16 => {
+                DeallocMemory!(self, ptr, addr_slab, Slab16, slab16_partial, slab16_full);
+            }
+            32 => {
+                DeallocMemory!(self, ptr, addr_slab, Slab32, slab32_partial, slab32_full);
+            }
+            64 => {
+                DeallocMemory!(self, ptr, addr_slab, Slab64, slab64_partial, slab64_full);
+            }
+            128 => {
+                DeallocMemory!(self, ptr, addr_slab, Slab128, slab128_partial, slab128_full);
+            }
+            256 => {
+                DeallocMemory!(self, ptr, addr_slab, Slab256, slab256_partial, slab256_full);
+            }
+            512 => {
+                DeallocMemory!(self, ptr, addr_slab, Slab512, slab512_partial, slab512_full);
+            }
+            1024 => {
+                DeallocMemory!(
+                    self,
+                    ptr,
+                    addr_slab,
+                    Slab1024,
+                    slab1024_partial,
+                    slab1024_full
+                );
+            }
+            2040 => {
+                DeallocMemory!(
+                    self,
+                    ptr,
+                    addr_slab,
+                    Slab2040,
+                    slab2040_partial,
+                    slab2040_full
+                );
+            }
+            4088 => {
+                DeallocMemory!(
+                    self,
+                    ptr,
+                    addr_slab,
+                    Slab4088,
+                    slab4088_partial,
+                    slab4088_full
+                );
+            }
+            8184 => {
+                DeallocMemory!(
+                    self,
+                    ptr,
+                    addr_slab,
+                    Slab8184,
+                    slab8184_partial,
+                    slab8184_full
+                );
+            }
+            16376 => {
+                DeallocMemory!(
+                    self,
+                    ptr,
+                    addr_slab,
+                    Slab16376,
+                    slab16376_partial,
+                    slab16376_full
+                );
+            }
+            32752 => {
+                DeallocMemory!(
+                    self,
+                    ptr,
+                    addr_slab,
+                    Slab32752,
+                    slab32752_partial,
+                    slab32752_full
+                );
+            }
+            65512 => {
+                DeallocMemory!(
+                    self,
+                    ptr,
+                    addr_slab,
+                    Slab65512,
+                    slab65512_partial,
+                    slab65512_full
+                );
+            }
+            _ => {}
         }
-        _ => {}
     }
 }
 
-static mut SLAB_ALLOC: SlabAllocator = SlabAllocator {
-    pages: pager::PageManager::new(),
-    slab16_partial: null_mut(),
-    slab32_partial: null_mut(),
-    slab64_partial: null_mut(),
-    slab128_partial: null_mut(),
-    slab256_partial: null_mut(),
-    slab512_partial: null_mut(),
-    slab1024_partial: null_mut(),
-    slab2040_partial: null_mut(),
-    slab4088_partial: null_mut(),
-    slab8184_partial: null_mut(),
-    slab16376_partial: null_mut(),
-    slab32752_partial: null_mut(),
-    slab65512_partial: null_mut(),
-    slab16_full: null_mut(),
-    slab32_full: null_mut(),
-    slab64_full: null_mut(),
-    slab128_full: null_mut(),
-    slab256_full: null_mut(),
-    slab512_full: null_mut(),
-    slab1024_full: null_mut(),
-    slab2040_full: null_mut(),
-    slab4088_full: null_mut(),
-    slab8184_full: null_mut(),
-    slab16376_full: null_mut(),
-    slab32752_full: null_mut(),
-    slab65512_full: null_mut(),
-};
-
-pub(crate) unsafe fn init(start: usize, size: usize) {
-    SLAB_ALLOC.pages.set_range(start, size);
-}
-
 trait Slab {
     fn alloc(&mut self) -> *mut u8;
     fn free(&mut self, ptr: *mut u8);
     fn is_full(&self) -> bool;
     fn is_empty(&self) -> bool;
     fn init(&mut self);
-    fn print(&self);
 }
 
 macro_rules! SlabSmall {
@@ -380,12 +513,7 @@ macro_rules! SlabSmall {
                 let idx = idx1 * size * 64 + idx2 * size;
 
                 if idx >= 65536 - 32 - 8 * $n {
-                    driver::uart::puts("error: idx = ");
-                    driver::uart::decimal(idx as u64);
-                    driver::uart::puts(", n = ");
-                    driver::uart::decimal($n);
-                    driver::uart::puts("\n");
-                    print_slabs();
+                    panic!("slab index out of range");
                 }
 
                 let ptr = &mut (self.buf[idx]) as *mut u8;
@@ -401,7 +529,8 @@ macro_rules! SlabSmall {
                 &mut (self.buf[idx + 8]) as *mut u8
             }
 
-            /// deallocate the memory region pointed by ptr which is returned by alloc
+            /// deallocate the memory region pointed by ptr which is returned by alloc,
+            /// the meta data is placed just before ptr even if it is shifted for the alignment
             fn free(&mut self, ptr: *mut u8) {
                 let addr = ptr as usize - 8;
                 let org = self as *mut $id as usize;
@@ -435,35 +564,6 @@ macro_rules! SlabSmall {
                 self.num = 0;
                 self.size = $size;
             }
-
-            fn print(&self) {
-                driver::uart::puts("num: ");
-                driver::uart::decimal(self.num as u64);
-                driver::uart::puts("\nL1 bitmap: 0x");
-                driver::uart::hex(self.l1_bitmap);
-                driver::uart::puts("\n");
-
-                driver::uart::puts("L2 bitmap:\n");
-                let mut i = 0;
-                for v in self.l2_bitmap.iter() {
-                    driver::uart::puts(" 0x");
-                    driver::uart::hex(*v);
-                    if i == 3 {
-                        driver::uart::puts("\n");
-                        i = 0;
-                    } else {
-                        i += 1;
-                    }
-                }
-
-                match unsafe { self.next.as_ref() } {
-                    Some(next) => {
-                        driver::uart::puts("\n");
-                        next.print();
-                    }
-                    None => {}
-                }
-            }
         }
     };
 }
@@ -503,6 +603,11 @@ SlabSmall!(Slab512, 2, 9, 0x3FFFFFFFFFFFFFFF, 1, 512);
 // size = 1024
 SlabSmall!(Slab1024, 1, 10, 0x7FFFFFFFFFFFFFFF, 1, 1024);
 
+/// slabs defined by SlabLarge have 16 bytes of meta data for each object
+fn is_large(size: u32) -> bool {
+    size >= 2040 && size != 65512
+}
+
 #[repr(C)]
 struct SlabMemory {
     idx1: usize,
@@ -552,7 +657,8 @@ macro_rules! SlabLarge {
                 &mut (self.buf[idx + 16]) as *mut u8
             }
 
-            /// deallocate the memory region pointed by ptr which is returned by alloc
+            /// deallocate the memory region pointed by ptr which is returned by alloc,
+            /// the meta data is placed just before ptr even if it is shifted for the alignment
             fn free(&mut self, ptr: *mut u8) {
                 let addr = ptr as usize;
                 let idx1 = unsafe { *((addr - 16) as *mut usize) };
@@ -576,20 +682,6 @@ macro_rules! SlabLarge {
                 self.size = $size;
                 self.num = 0;
             }
-
-            fn print(&self) {
-                driver::uart::puts("L1 bitmap: 0x");
-                driver::uart::hex(self.l1_bitmap);
-                driver::uart::puts("\n");
-
-                match unsafe { self.next.as_ref() } {
-                    Some(next) => {
-                        driver::uart::puts("\n");
-                        next.print();
-                    }
-                    None => {}
-                }
-            }
         }
     };
 }
@@ -664,56 +756,4 @@ impl Slab for Slab65512 {
         self.size = 65512;
         self.num = 0;
     }
-
-    fn print(&self) {
-        driver::uart::puts("1");
-
-        match unsafe { self.next.as_ref() } {
-            Some(next) => {
-                next.print();
-            }
-            None => {}
-        }
-    }
-}
-
-macro_rules! print_slabs {
-    ($s:literal, $slab_partial:ident, $slab_full:ident) => {
-        driver::uart::puts("\n");
-        driver::uart::puts($s);
-        driver::uart::puts("_partial:\n");
-        match unsafe { SLAB_ALLOC.$slab_partial.as_ref() } {
-            Some(slab) => {
-                slab.print();
-            }
-            None => {}
-        }
-
-        driver::uart::puts("\n");
-        driver::uart::puts($s);
-        driver::uart::puts("_full:\n");
-        match unsafe { SLAB_ALLOC.$slab_full.as_ref() } {
-            Some(slab) => {
-                slab.print();
-            }
-            None => {}
-        }
-    };
-}
-
-pub fn print_slabs() {
-    print_slabs!("slab16", slab16_partial, slab16_full);
-    print_slabs!("slab32", slab32_partial, slab32_full);
-    print_slabs!("slab64", slab64_partial, slab64_full);
-    print_slabs!("slab128", slab128_partial, slab128_full);
-    print_slabs!("slab256", slab256_partial, slab256_full);
-    print_slabs!("slab512", slab512_partial, slab512_full);
-    print_slabs!("slab1024", slab1024_partial, slab1024_full);
-    print_slabs!("slab2040", slab2040_partial, slab2040_full);
-    print_slabs!("slab4088", slab4088_partial, slab4088_full);
-    print_slabs!("slab8184", slab8184_partial, slab8184_full);
-    print_slabs!("slab16376", slab16376_partial, slab16376_full);
-    print_slabs!("slab32752", slab32752_partial, slab32752_full);
-    print_slabs!("slab65512", slab65512_partial, slab65512_full);
-    driver::uart::puts("\n");
 }
@@ -0,0 +1,347 @@
+mod common;
+
+use allocator::buddy::{BuddyAlloc, ZoneKind, Zones, MAX_ORDER};
+use common::{overlaps, Arena, Op};
+use quickcheck::{quickcheck, Arbitrary, Gen};
+
+const MIN_SIZE: usize = 4096;
+
+/// 1029 pages, not a power of two
+const ARENA_SIZE: usize = (1024 + 5) * MIN_SIZE;
+
+fn new_buddy(arena: &Arena) -> Box<BuddyAlloc> {
+    let mut allc = Box::new(BuddyAlloc::new());
+    unsafe { allc.init(MIN_SIZE, arena.start(), arena.end()) };
+    allc
+}
+
+/// the first address of free blocks, after the state array
+fn first_free(arena: &Arena, allc: &BuddyAlloc) -> usize {
+    arena.end() - allc.free_bytes()
+}
+
+/// size of the block allocated for req bytes
+fn block_size(req: usize) -> usize {
+    req.next_power_of_two().max(MIN_SIZE)
+}
+
+#[test]
+fn alloc_rounds_up_to_blocks() {
+    let arena = Arena::new(64 * MIN_SIZE, 64 * MIN_SIZE);
+    let mut allc = new_buddy(&arena);
+    let total = allc.free_bytes();
+
+    let addr1 = allc.mem_alloc(MIN_SIZE * 2).unwrap() as usize;
+    let addr2 = allc.mem_alloc(MIN_SIZE * 3).unwrap() as usize;
+    let addr3 = allc.mem_alloc(MIN_SIZE * 8).unwrap() as usize;
+
+    assert_eq!(addr1 % (MIN_SIZE * 2), 0);
+    assert_eq!(addr2 % (MIN_SIZE * 4), 0); // 4 pages block
+    assert_eq!(addr3 % (MIN_SIZE * 8), 0);
+    assert_eq!(allc.free_bytes(), total - MIN_SIZE * 14);
+
+    allc.mem_free(addr2 as *mut u8);
+    allc.mem_free(addr1 as *mut u8);
+    allc.mem_free(addr3 as *mut u8);
+
+    // every block is combined again
+    assert_eq!(allc.free_bytes(), total);
+    assert!(allc.mem_alloc(MIN_SIZE * 32).is_some());
+}
+
+#[test]
+fn alloc_until_exhausted() {
+    let arena = Arena::new(ARENA_SIZE, MIN_SIZE);
+    let mut allc = new_buddy(&arena);
+    let num = allc.free_bytes() / MIN_SIZE;
+
+    let mut ptrs: Vec<usize> = (0..num)
+        .map(|_| allc.mem_alloc(1).unwrap() as usize)
+        .collect();
+    assert_eq!(allc.mem_alloc(1), None);
+    assert_eq!(allc.free_bytes(), 0);
+
+    ptrs.sort_unstable();
+    ptrs.dedup();
+    assert_eq!(ptrs.len(), num);
+
+    allc.mem_free(ptrs[5] as *mut u8);
+    assert_eq!(allc.mem_alloc(MIN_SIZE), Some(ptrs[5] as *mut u8));
+}
+
+#[test]
+fn alloc_too_large() {
+    let arena = Arena::new(64 * MIN_SIZE, 64 * MIN_SIZE);
+    let mut allc = new_buddy(&arena);
+    assert_eq!(allc.mem_alloc(MIN_SIZE * 64), None);
+    assert_eq!(allc.mem_alloc((MIN_SIZE << MAX_ORDER) + 1), None);
+    assert!(allc.mem_alloc(MIN_SIZE * 32).is_some());
+    assert!(allc.mem_alloc(MIN_SIZE * 32).is_none());
+}
+
+#[test]
+fn alloc_aligned() {
+    let arena = Arena::new(ARENA_SIZE, MIN_SIZE);
+    let mut allc = new_buddy(&arena);
+
+    for align in [1, 8, MIN_SIZE, MIN_SIZE * 4, MIN_SIZE * 64].iter() {
+        let addr = allc.mem_alloc_aligned(1, *align).unwrap() as usize;
+        assert_eq!(addr % align, 0);
+    }
+
+    assert_eq!(allc.mem_alloc_aligned(1, 3), None);
+    assert_eq!(allc.mem_alloc_aligned(1, 0), None);
+}
+
+#[test]
+fn realloc_in_place() {
+    let arena = Arena::new(64 * MIN_SIZE, 64 * MIN_SIZE);
+    let mut allc = new_buddy(&arena);
+    let total = allc.free_bytes();
+
+    // the block of 32 pages is at the upper half
+    let ptr = allc.mem_alloc(MIN_SIZE * 32).unwrap();
+    assert_eq!(ptr as usize, arena.start() + 32 * MIN_SIZE);
+    assert!(!allc.mem_realloc(ptr, MIN_SIZE * 64));
+
+    // shrink by freeing the upper halves
+    assert!(allc.mem_realloc(ptr, 1));
+    assert_eq!(allc.free_bytes(), total - MIN_SIZE);
+
+    // grow by combining the free buddies
+    assert!(allc.mem_realloc(ptr, MIN_SIZE * 5));
+    assert_eq!(allc.free_bytes(), total - MIN_SIZE * 8);
+    assert!(allc.mem_realloc(ptr, 1));
+
+    // the buddy is used
+    let ptr2 = allc.mem_alloc(MIN_SIZE).unwrap();
+    assert_eq!(ptr2 as usize, ptr as usize + MIN_SIZE);
+    assert!(!allc.mem_realloc(ptr, MIN_SIZE * 2));
+
+    allc.mem_free(ptr2);
+    allc.mem_free(ptr);
+    assert_eq!(allc.free_bytes(), total);
+}
+
+#[test]
+fn init_empty() {
+    let arena = Arena::new(MIN_SIZE, MIN_SIZE);
+    let mut allc = new_buddy(&arena);
+    assert_eq!(allc.free_bytes(), 0);
+    assert_eq!(allc.mem_alloc(1), None);
+}
+
+#[test]
+#[should_panic(expected = "freed invalid address")]
+fn free_invalid_address() {
+    let arena = Arena::new(64 * MIN_SIZE, 64 * MIN_SIZE);
+    let mut allc = new_buddy(&arena);
+    let addr = allc.mem_alloc(MIN_SIZE * 32).unwrap() as usize;
+    allc.mem_free((addr + MIN_SIZE) as *mut u8);
+}
+
+#[test]
+#[should_panic(expected = "freed unused memory")]
+fn free_twice() {
+    let arena = Arena::new(64 * MIN_SIZE, 64 * MIN_SIZE);
+    let mut allc = new_buddy(&arena);
+    let addr = allc.mem_alloc(MIN_SIZE).unwrap();
+    allc.mem_free(addr);
+    allc.mem_free(addr);
+}
+
+#[test]
+fn zones() {
+    let dma = Arena::new(64 * MIN_SIZE, 64 * MIN_SIZE);
+    let normal = Arena::new(64 * MIN_SIZE, 64 * MIN_SIZE);
+
+    let mut zones = Box::new(Zones::new());
+    unsafe {
+        zones.add(ZoneKind::Dma, MIN_SIZE, dma.start(), dma.end());
+        zones.add(ZoneKind::Normal, MIN_SIZE, normal.start(), normal.end());
+    }
+
+    let in_dma = |p: *mut u8| dma.start() <= p as usize && (p as usize) < dma.end();
+    let in_normal = |p: *mut u8| normal.start() <= p as usize && (p as usize) < normal.end();
+
+    // from the DMA zone only
+    let ptr1 = zones.mem_alloc_zone(ZoneKind::Dma, MIN_SIZE, 1).unwrap();
+    assert!(in_dma(ptr1));
+
+    // from the normal zone, and then the DMA zone
+    let ptr2 = zones.mem_alloc(MIN_SIZE * 32).unwrap();
+    let ptr3 = zones.mem_alloc(MIN_SIZE * 32).unwrap();
+    assert!(in_normal(ptr2));
+    assert!(in_dma(ptr3));
+    assert_eq!(
+        zones.mem_alloc_zone(ZoneKind::Normal, MIN_SIZE * 32, 1),
+        None
+    );
+
+    for ptr in [ptr1, ptr2, ptr3].iter() {
+        zones.mem_free(*ptr);
+    }
+    assert_eq!(zones.free_bytes(ZoneKind::Dma), 63 * MIN_SIZE);
+    assert_eq!(zones.free_bytes(ZoneKind::Normal), 63 * MIN_SIZE);
+}
+
+#[test]
+#[should_panic(expected = "overlapped zones")]
+fn zones_overlapped() {
+    let arena = Arena::new(64 * MIN_SIZE, 64 * MIN_SIZE);
+    let mut zones = Box::new(Zones::new());
+    let mid = arena.start() + 32 * MIN_SIZE;
+    unsafe {
+        zones.add(ZoneKind::Dma, MIN_SIZE, arena.start(), mid + MIN_SIZE);
+        zones.add(ZoneKind::Normal, MIN_SIZE, mid, arena.end());
+    }
+}
+
+/// whether a free block of size bytes aligned to its size exists,
+/// the buddy allocator combines every free buddy, so it must find one
+fn model_can_alloc(first: usize, end: usize, live: &[(usize, usize)], size: usize) -> bool {
+    let start = (first + size - 1) & !(size - 1);
+    (start..end)
+        .step_by(size)
+        .any(|addr| addr + size <= end && !overlaps(live, addr, size))
+}
+
+/// alloc and free, or resize a live block in place
+#[derive(Clone, Debug)]
+enum BuddyOp {
+    Op(Op),
+    Realloc(usize, usize), // index of live blocks, and size
+}
+
+impl Arbitrary for BuddyOp {
+    fn arbitrary(g: &mut Gen) -> BuddyOp {
+        match Op::arbitrary(g) {
+            Op::Free(n) if n % 3 == 0 => BuddyOp::Realloc(n / 3, usize::arbitrary(g) % (1 << 16)),
+            op => BuddyOp::Op(op),
+        }
+    }
+}
+
+fn check(ops: Vec<BuddyOp>) -> bool {
+    let arena = Arena::new(ARENA_SIZE, MIN_SIZE);
+    let mut allc = new_buddy(&arena);
+    let first = first_free(&arena, &allc);
+    let total = allc.free_bytes();
+
+    // (address, size of the block, fill pattern)
+    let mut live: Vec<(usize, usize, u64)> = Vec::new();
+    let mut ranges: Vec<(usize, usize)> = Vec::new();
+
+    for (n, op) in ops.into_iter().enumerate() {
+        match op {
+            BuddyOp::Op(Op::Alloc(size)) => {
+                // up to 4MiB
+                let req = size * 64;
+                // from 1 byte to 64 pages
+                let align = 1 << (size % 19);
+                let block = block_size(req).max(align);
+
+                let expected = model_can_alloc(first, arena.end(), &ranges, block);
+                let ret = allc.mem_alloc_aligned(req, align);
+                if ret.is_some() != expected {
+                    return false;
+                }
+
+                if let Some(ptr) = ret {
+                    let addr = ptr as usize;
+                    if addr & (block - 1) != 0 || addr < first || addr + block > arena.end() {
+                        return false;
+                    }
+                    if overlaps(&ranges, addr, block) {
+                        return false;
+                    }
+
+                    // the first and last words are broken if free lists are wrong
+                    let val = n as u64;
+                    unsafe {
+                        *(addr as *mut u64) = val;
+                        *((addr + block - 8) as *mut u64) = val;
+                    }
+                    live.push((addr, block, val));
+                    ranges.push((addr, block));
+                }
+            }
+            BuddyOp::Op(Op::Free(idx)) => {
+                if live.is_empty() {
+                    continue;
+                }
+                let i = idx % live.len();
+                let (addr, block, val) = live.swap_remove(i);
+                ranges.swap_remove(i);
+
+                let (head, tail) =
+                    unsafe { (*(addr as *const u64), *((addr + block - 8) as *const u64)) };
+                if head != val || tail != val {
+                    return false;
+                }
+
+                allc.mem_free(addr as *mut u8);
+            }
+            BuddyOp::Realloc(idx, size) => {
+                if live.is_empty() {
+                    continue;
+                }
+                let i = idx % live.len();
+                let (addr, block, val) = live[i];
+                let new_block = block_size(size * 64);
+
+                // a block can grow if it is aligned to the new size and followed by free memory
+                let others: Vec<(usize, usize)> =
+                    ranges.iter().filter(|r| r.0 != addr).cloned().collect();
+                let expected = new_block <= block
+                    || (addr & (new_block - 1) == 0
+                        && addr + new_block <= arena.end()
+                        && !overlaps(&others, addr + block, new_block - block));
+
+                if allc.mem_realloc(addr as *mut u8, size * 64) != expected {
+                    return false;
+                }
+
+                if expected {
+                    if unsafe { *(addr as *const u64) } != val {
+                        return false;
+                    }
+                    unsafe { *((addr + new_block - 8) as *mut u64) = val };
+                    live[i].1 = new_block;
+                    ranges[i].1 = new_block;
+                }
+            }
+        }
+
+        let used: usize = ranges.iter().map(|(_, s)| s).sum();
+        if allc.free_bytes() != total - used {
+            return false;
+        }
+    }
+
+    // blocks are combined after every block is freed
+    for (addr, _, _) in live {
+        allc.mem_free(addr as *mut u8);
+    }
+    if allc.free_bytes() != total {
+        return false;
+    }
+
+    // every aligned free block can be allocated from the largest one
+    let mut ranges = Vec::new();
+    for order in (0..=10).rev() {
+        let size = MIN_SIZE << order;
+        while model_can_alloc(first, arena.end(), &ranges, size) {
+            match allc.mem_alloc(size) {
+                Some(ptr) => ranges.push((ptr as usize, size)),
+                None => return false,
+            }
+        }
+    }
+    allc.free_bytes() == 0
+}
+
+#[test]
+fn buddy_matches_model() {
+    quickcheck(check as fn(Vec<BuddyOp>) -> bool);
+}
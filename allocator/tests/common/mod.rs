@@ -0,0 +1,91 @@
+#![allow(dead_code)]
+
+use allocator::slab::{SlabAllocator, MAX_SLAB_SIZE};
+use quickcheck::{Arbitrary, Gen};
+use std::alloc::{alloc_zeroed, dealloc, Layout};
+
+/// an operation of random alloc/free sequences
+#[derive(Clone, Debug)]
+pub enum Op {
+    Alloc(usize),
+    Free(usize), // index of live objects, modulo the number of them
+}
+
+impl Arbitrary for Op {
+    fn arbitrary(g: &mut Gen) -> Op {
+        let n = usize::arbitrary(g);
+        if bool::arbitrary(g) {
+            // log-uniform size up to 64KiB
+            let shift = (n & 0xff) % 17;
+            Op::Alloc((n >> 8) % (1 << shift))
+        } else {
+            Op::Free(n)
+        }
+    }
+}
+
+/// simulated physical memory
+pub struct Arena {
+    layout: Layout,
+    ptr: *mut u8,
+}
+
+impl Arena {
+    pub fn new(size: usize, align: usize) -> Arena {
+        let layout = Layout::from_size_align(size, align).unwrap();
+        let ptr = unsafe { alloc_zeroed(layout) };
+        assert!(!ptr.is_null());
+        Arena { layout, ptr }
+    }
+
+    pub fn start(&self) -> usize {
+        self.ptr as usize
+    }
+
+    pub fn end(&self) -> usize {
+        self.ptr as usize + self.layout.size()
+    }
+}
+
+impl Drop for Arena {
+    fn drop(&mut self) {
+        unsafe { dealloc(self.ptr, self.layout) };
+    }
+}
+
+/// whether [addr, addr + size) overlaps any live region
+pub fn overlaps(live: &[(usize, usize)], addr: usize, size: usize) -> bool {
+    live.iter()
+        .any(|&(a, s)| addr < a + s.max(1) && a < addr + size.max(1))
+}
+
+/// 64KiB pages of the slab allocator
+pub const PAGE: usize = 64 * 1024;
+
+pub fn new_slab(num_pages: usize) -> (Arena, Box<SlabAllocator>) {
+    let arena = Arena::new(num_pages * PAGE, PAGE);
+    let mut slab = Box::new(SlabAllocator::new());
+    slab.init(arena.start(), arena.end());
+    (arena, slab)
+}
+
+pub fn layout(size: usize) -> Layout {
+    Layout::from_size_align(size, 8).unwrap()
+}
+
+/// every page is free if a page for the largest slab can be allocated num_pages times
+pub fn all_pages_free(slab: &mut SlabAllocator, num_pages: usize) -> bool {
+    let mut ptrs = Vec::new();
+    for _ in 0..num_pages {
+        let ptr = unsafe { slab.alloc(layout(MAX_SLAB_SIZE)) };
+        if ptr.is_null() {
+            return false;
+        }
+        ptrs.push(ptr);
+    }
+
+    for ptr in ptrs {
+        unsafe { slab.dealloc(ptr, layout(MAX_SLAB_SIZE)) };
+    }
+    true
+}
@@ -0,0 +1,161 @@
+mod common;
+
+use allocator::magazine::{is_cached, CpuCache, BATCH, MAGAZINE_SIZE};
+use allocator::slab::SlabAllocator;
+use common::{all_pages_free, layout, new_slab, overlaps, Op};
+use core::alloc::Layout;
+use quickcheck::quickcheck;
+
+const NUM_PAGES: usize = 16;
+const NUM_CPU: usize = 4;
+
+/// allocate through cache, and refill it on miss
+fn alloc(cache: &mut CpuCache, slab: &mut SlabAllocator, layout: Layout) -> *mut u8 {
+    if let Some(ptr) = cache.alloc(&layout) {
+        return ptr;
+    }
+
+    unsafe {
+        if is_cached(&layout) {
+            cache.refill(&layout, slab)
+        } else {
+            slab.alloc(layout)
+        }
+    }
+}
+
+/// free through cache, and drain it if full
+fn free(cache: &mut CpuCache, slab: &mut SlabAllocator, ptr: *mut u8, layout: Layout) {
+    if cache.free(ptr, &layout) {
+        return;
+    }
+
+    unsafe {
+        if is_cached(&layout) {
+            cache.drain(ptr, &layout, slab)
+        } else {
+            slab.dealloc(ptr, layout)
+        }
+    }
+}
+
+#[test]
+fn cached_layouts() {
+    assert!(is_cached(&layout(0)));
+    assert!(is_cached(&layout(1016)));
+    assert!(!is_cached(&layout(1017)));
+    assert!(!is_cached(&Layout::from_size_align(8, 16).unwrap()));
+}
+
+#[test]
+fn refill_and_drain_by_batch() {
+    let (_arena, mut slab) = new_slab(NUM_PAGES);
+    let mut cache = Box::new(CpuCache::new());
+
+    // a miss takes BATCH objects from the slab allocator
+    assert_eq!(cache.alloc(&layout(100)), None);
+    let ptr = alloc(&mut cache, &mut slab, layout(100));
+    assert!(!ptr.is_null());
+    assert_eq!(cache.num_cached(), BATCH - 1);
+
+    // objects of the same class are cached
+    let ptr2 = cache.alloc(&layout(120)).unwrap();
+    assert!(cache.free(ptr2, &layout(65)));
+    assert!(cache.free(ptr, &layout(100)));
+    assert_eq!(cache.num_cached(), BATCH);
+
+    // a full magazine returns the half
+    let ptrs: Vec<*mut u8> = (0..MAGAZINE_SIZE)
+        .map(|_| unsafe { slab.alloc(layout(100)) })
+        .collect();
+    for ptr in ptrs {
+        free(&mut cache, &mut slab, ptr, layout(100));
+    }
+    assert_eq!(cache.num_cached(), MAGAZINE_SIZE); // drained once
+
+    unsafe { cache.flush(&mut slab) };
+    assert_eq!(cache.num_cached(), 0);
+    assert!(all_pages_free(&mut slab, NUM_PAGES));
+}
+
+#[test]
+fn refill_exhausted() {
+    let (_arena, mut slab) = new_slab(1);
+    let mut cache = Box::new(CpuCache::new());
+
+    // the only page is used by the largest slab
+    let big = unsafe { slab.alloc(layout(60000)) };
+    assert!(!big.is_null());
+    assert!(unsafe { cache.refill(&layout(8), &mut slab) }.is_null());
+    assert_eq!(cache.num_cached(), 0);
+}
+
+/// CPUs allocate and free objects in turn, and free objects allocated by other CPUs
+fn check(ops: Vec<Op>) -> bool {
+    let (arena, mut slab) = new_slab(NUM_PAGES);
+    let mut caches: Vec<CpuCache> = (0..NUM_CPU).map(|_| CpuCache::new()).collect();
+
+    // (address, size, fill byte)
+    let mut live: Vec<(usize, usize, u8)> = Vec::new();
+    let mut ranges: Vec<(usize, usize)> = Vec::new();
+
+    for (n, op) in ops.into_iter().enumerate() {
+        let cache = &mut caches[n % NUM_CPU];
+        match op {
+            Op::Alloc(size) => {
+                // mostly cached sizes
+                let size = size % 2048;
+                let ptr = alloc(cache, &mut slab, layout(size));
+                if ptr.is_null() {
+                    return false;
+                }
+
+                let addr = ptr as usize;
+                if addr & 0b111 != 0 || addr < arena.start() || addr + size > arena.end() {
+                    return false;
+                }
+                if overlaps(&ranges, addr, size) {
+                    return false;
+                }
+
+                unsafe { ptr.write_bytes(n as u8, size) };
+                live.push((addr, size, n as u8));
+                ranges.push((addr, size));
+            }
+            Op::Free(idx) => {
+                if live.is_empty() {
+                    continue;
+                }
+                let i = idx % live.len();
+                let (addr, size, val) = live.swap_remove(i);
+                ranges.swap_remove(i);
+
+                let data = unsafe { core::slice::from_raw_parts(addr as *const u8, size) };
+                if data.iter().any(|b| *b != val) {
+                    return false;
+                }
+
+                free(cache, &mut slab, addr as *mut u8, layout(size));
+            }
+        }
+    }
+
+    for (i, (addr, size, _)) in live.into_iter().enumerate() {
+        free(
+            &mut caches[i % NUM_CPU],
+            &mut slab,
+            addr as *mut u8,
+            layout(size),
+        );
+    }
+    for cache in caches.iter_mut() {
+        unsafe { cache.flush(&mut slab) };
+    }
+
+    all_pages_free(&mut slab, NUM_PAGES)
+}
+
+#[test]
+fn magazine_matches_model() {
+    quickcheck(check as fn(Vec<Op>) -> bool);
+}
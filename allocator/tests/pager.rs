@@ -0,0 +1,107 @@
+mod common;
+
+use allocator::pager::PageManager;
+use common::Op;
+use quickcheck::quickcheck;
+use std::collections::BTreeSet;
+
+const PAGE: usize = 64 * 1024;
+
+fn check(start: u16, num: u8, ops: Vec<Op>) -> bool {
+    // any 64KiB aligned start address
+    let start = 0x4000_0000 + start as usize * PAGE;
+    let num = num as usize;
+
+    let mut pages = PageManager::new();
+    pages.set_range(start, start + num * PAGE);
+
+    // indices of allocated pages
+    let mut model = BTreeSet::new();
+
+    for op in ops {
+        match op {
+            Op::Alloc(_) => {
+                // the page manager returns the lowest free page
+                let expected = (0..num).find(|i| !model.contains(i));
+                let ret = pages.alloc();
+                if ret != expected.map(|i| start + i * PAGE) {
+                    return false;
+                }
+
+                if let Some(i) = expected {
+                    model.insert(i);
+                }
+            }
+            Op::Free(idx) => {
+                if model.is_empty() {
+                    continue;
+                }
+                let i = *model.iter().nth(idx % model.len()).unwrap();
+                model.remove(&i);
+                pages.free(start + i * PAGE);
+            }
+        }
+    }
+
+    true
+}
+
+#[test]
+fn pager_matches_model() {
+    quickcheck(check as fn(u16, u8, Vec<Op>) -> bool);
+}
+
+fn check_contiguous(start: u16, num: u8, ops: Vec<Op>) -> bool {
+    let start = 0x4000_0000 + start as usize * PAGE;
+    let num = num as usize;
+
+    let mut pages = PageManager::new();
+    pages.set_range(start, start + num * PAGE);
+
+    // used pages, and allocated runs of (index, the number of pages)
+    let mut used = vec![false; num];
+    let mut runs = Vec::new();
+
+    for op in ops {
+        match op {
+            Op::Alloc(n) => {
+                // 1 to 8 pages aligned to 1, 2, 4 or 8 pages
+                let len = n % 8 + 1;
+                let align = 1 << ((n >> 3) % 4);
+
+                // the lowest aligned run of free pages
+                let expected = (0..num)
+                    .filter(|i| ((start / PAGE + i) & (align - 1)) == 0)
+                    .find(|i| i + len <= num && used[*i..*i + len].iter().all(|u| !u));
+
+                let ret = pages.alloc_contiguous(len, align * PAGE);
+                if ret != expected.map(|i| start + i * PAGE) {
+                    return false;
+                }
+
+                if let Some(i) = expected {
+                    used[i..i + len].iter_mut().for_each(|u| *u = true);
+                    runs.push((i, len));
+                }
+            }
+            Op::Free(idx) => {
+                if runs.is_empty() {
+                    continue;
+                }
+                let (i, len) = runs.swap_remove(idx % runs.len());
+                used[i..i + len].iter_mut().for_each(|u| *u = false);
+                pages.free_contiguous(start + i * PAGE, len);
+            }
+        }
+    }
+
+    let usage = pages.usage();
+    usage.total == num
+        && usage.used == used.iter().filter(|u| **u).count()
+        && usage.largest_run == used.split(|u| *u).map(|run| run.len()).max().unwrap_or(0)
+}
+
+#[test]
+fn contiguous_matches_model() {
+    quickcheck(check_contiguous as fn(u16, u8, Vec<Op>) -> bool);
+}
@@ -0,0 +1,216 @@
+mod common;
+
+use allocator::slab::{can_resize, fits, MAX_SLAB_SIZE};
+use common::{all_pages_free, layout, new_slab, overlaps, Op};
+use core::alloc::Layout;
+use quickcheck::quickcheck;
+
+const NUM_PAGES: usize = 128;
+
+#[test]
+fn alloc_every_size_class() {
+    let (arena, mut slab) = new_slab(NUM_PAGES);
+    let sizes = [
+        0,
+        1,
+        8,
+        16,
+        100,
+        1016,
+        1017,
+        2024,
+        2025,
+        4072,
+        8168,
+        16360,
+        32736,
+        MAX_SLAB_SIZE,
+    ];
+
+    let mut ptrs = Vec::new();
+    for (i, size) in sizes.iter().enumerate() {
+        let ptr = unsafe { slab.alloc(layout(*size)) };
+        assert!(!ptr.is_null());
+        assert!(arena.start() <= ptr as usize && ptr as usize + size <= arena.end());
+        unsafe { ptr.write_bytes(i as u8, *size) };
+        ptrs.push(ptr);
+    }
+
+    for (i, (ptr, size)) in ptrs.iter().zip(sizes.iter()).enumerate() {
+        let data = unsafe { core::slice::from_raw_parts(*ptr, *size) };
+        assert!(data.iter().all(|b| *b == i as u8));
+        unsafe { slab.dealloc(*ptr, layout(*size)) };
+    }
+
+    assert!(all_pages_free(&mut slab, NUM_PAGES));
+}
+
+#[test]
+fn alloc_too_large() {
+    let (_arena, mut slab) = new_slab(NUM_PAGES);
+    let ptr = unsafe { slab.alloc(layout(MAX_SLAB_SIZE + 1)) };
+    assert!(ptr.is_null());
+}
+
+#[test]
+fn alloc_until_exhausted() {
+    let (_arena, mut slab) = new_slab(2);
+    let ptr1 = unsafe { slab.alloc(layout(MAX_SLAB_SIZE)) };
+    let ptr2 = unsafe { slab.alloc(layout(16)) };
+    assert!(!ptr1.is_null() && !ptr2.is_null());
+    assert!(unsafe { slab.alloc(layout(4000)) }.is_null());
+
+    unsafe { slab.dealloc(ptr1, layout(MAX_SLAB_SIZE)) };
+    assert!(!unsafe { slab.alloc(layout(4000)) }.is_null());
+}
+
+#[test]
+fn free_pages_of_empty_slabs() {
+    let (_arena, mut slab) = new_slab(4);
+
+    // more than a slab of 16 bytes objects
+    let ptrs: Vec<*mut u8> = (0..5000)
+        .map(|_| unsafe { slab.alloc(layout(8)) })
+        .collect();
+    assert!(ptrs.iter().all(|p| !p.is_null()));
+
+    for ptr in ptrs {
+        unsafe { slab.dealloc(ptr, layout(8)) };
+    }
+
+    assert!(all_pages_free(&mut slab, 4));
+}
+
+#[test]
+fn alloc_aligned() {
+    let (_arena, mut slab) = new_slab(NUM_PAGES);
+
+    // (size, align), 64 bytes for a cache line and 4096 bytes for a DMA buffer
+    let layouts = [
+        (8, 16),
+        (24, 64),
+        (100, 128),
+        (1000, 512),
+        (2000, 1024),
+        (4096, 4096),
+        (10000, 4096),
+        (30000, 32768),
+    ];
+
+    let mut ptrs = Vec::new();
+    for (i, (size, align)) in layouts.iter().enumerate() {
+        let aligned = Layout::from_size_align(*size, *align).unwrap();
+        assert!(fits(&aligned));
+
+        // objects before and after the aligned one
+        let before = unsafe { slab.alloc(layout(*size)) };
+        let ptr = unsafe { slab.alloc(aligned) };
+        let after = unsafe { slab.alloc(layout(*size)) };
+        assert!(!ptr.is_null() && !before.is_null() && !after.is_null());
+        assert_eq!(ptr as usize % align, 0);
+
+        unsafe { ptr.write_bytes(i as u8, *size) };
+        ptrs.push((ptr, aligned));
+
+        unsafe {
+            slab.dealloc(before, layout(*size));
+            slab.dealloc(after, layout(*size));
+        }
+    }
+
+    for (i, (ptr, aligned)) in ptrs.into_iter().enumerate() {
+        let data = unsafe { core::slice::from_raw_parts(ptr, aligned.size()) };
+        assert!(data.iter().all(|b| *b == i as u8));
+        unsafe { slab.dealloc(ptr, aligned) };
+    }
+
+    assert!(all_pages_free(&mut slab, NUM_PAGES));
+}
+
+#[test]
+fn alloc_aligned_too_large() {
+    let (_arena, mut slab) = new_slab(NUM_PAGES);
+    let layout = Layout::from_size_align(8, 65536).unwrap();
+    assert!(!fits(&layout));
+    assert!(unsafe { slab.alloc(layout) }.is_null());
+
+    let layout = Layout::from_size_align(MAX_SLAB_SIZE, 16).unwrap();
+    assert!(!fits(&layout));
+    assert!(unsafe { slab.alloc(layout) }.is_null());
+}
+
+#[test]
+fn resize_in_same_slab() {
+    assert!(can_resize(&layout(0), 8));
+    assert!(!can_resize(&layout(8), 9));
+    assert!(can_resize(&layout(600), 1016));
+    assert!(can_resize(&layout(1016), 600));
+    assert!(!can_resize(&layout(1016), 1017));
+    assert!(can_resize(&layout(2100), 4072));
+    assert!(!can_resize(&layout(4072), 4073));
+    assert!(can_resize(&layout(40000), MAX_SLAB_SIZE));
+    assert!(!can_resize(&layout(40000), MAX_SLAB_SIZE + 1));
+    assert!(!can_resize(&Layout::from_size_align(8, 16).unwrap(), 8));
+}
+
+fn check(ops: Vec<Op>) -> bool {
+    let (arena, mut slab) = new_slab(NUM_PAGES);
+
+    // (address, size, fill byte)
+    let mut live: Vec<(usize, usize, u8)> = Vec::new();
+    let mut ranges: Vec<(usize, usize)> = Vec::new();
+
+    for (n, op) in ops.into_iter().enumerate() {
+        match op {
+            Op::Alloc(size) => {
+                let size = size % (MAX_SLAB_SIZE + 1);
+                let ptr = unsafe { slab.alloc(layout(size)) };
+
+                // the arena never runs out with at most 100 objects
+                if ptr.is_null() {
+                    return false;
+                }
+
+                let addr = ptr as usize;
+                if addr & 0b111 != 0 || addr < arena.start() || addr + size > arena.end() {
+                    return false;
+                }
+
+                if overlaps(&ranges, addr, size) {
+                    return false;
+                }
+
+                unsafe { ptr.write_bytes(n as u8, size) };
+                live.push((addr, size, n as u8));
+                ranges.push((addr, size));
+            }
+            Op::Free(idx) => {
+                if live.is_empty() {
+                    continue;
+                }
+                let i = idx % live.len();
+                let (addr, size, val) = live.swap_remove(i);
+                ranges.swap_remove(i);
+
+                // contents are not broken by other objects and meta data
+                let data = unsafe { core::slice::from_raw_parts(addr as *const u8, size) };
+                if data.iter().any(|b| *b != val) {
+                    return false;
+                }
+
+                unsafe { slab.dealloc(addr as *mut u8, layout(size)) };
+            }
+        }
+    }
+
+    for (addr, size, _) in live {
+        unsafe { slab.dealloc(addr as *mut u8, layout(size)) };
+    }
+
+    all_pages_free(&mut slab, NUM_PAGES)
+}
+
+#[test]
+fn slab_matches_model() {
+    quickcheck(check as fn(Vec<Op>) -> bool);
+}
@@ -57,6 +57,24 @@ pub fn invalidate_icache() {
     unsafe { asm!("ic iallu") };
 }
 
+/// make instructions written to [addr, addr + size) visible to instruction fetches
+/// dc cvau and ic ialluis
+pub fn sync_icache(addr: u64, size: u64) {
+    let line = 4 << ((cpu::ctr_el0::get() >> 16) & 0xf); // DminLine
+    let mut base = addr & !(line - 1);
+
+    cpu::dsb_ish();
+    while base < addr + size {
+        unsafe { asm!("dc cvau, {}", in(reg) base) };
+        base += line;
+    }
+
+    cpu::dsb_ish();
+    unsafe { asm!("ic ialluis") };
+    cpu::dsb_ish();
+    cpu::isb();
+}
+
 /// Disable L1 data cache and unified L2 cache
 pub fn disable_dcache_el3() {
     let sctlr_el3 = cpu::sctlr_el3::get();
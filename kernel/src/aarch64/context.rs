@@ -1,5 +1,4 @@
-use super::cpu;
-use crate::driver::topology;
+use super::{cpu, percpu};
 use crate::driver::topology::CORE_COUNT;
 use crate::psci::ep_info;
 use crate::psci::ep_info::{EntryPointInfo, ParamHeader};
@@ -93,6 +92,24 @@ impl GpRegs {
             _unused: [0; 12],
         }
     }
+
+    /// get xn, 31 means xzr
+    pub fn get(&self, n: usize) -> u64 {
+        if n >= 31 {
+            0
+        } else {
+            let regs = unsafe { &*(self as *const GpRegs as *const [u64; 31]) };
+            regs[n]
+        }
+    }
+
+    /// set xn, writing to xzr (31) is ignored
+    pub fn set(&mut self, n: usize, val: u64) {
+        if n < 31 {
+            let regs = unsafe { &mut *(self as *mut GpRegs as *mut [u64; 31]) };
+            regs[n] = val;
+        }
+    }
 }
 
 /// System Registers of EL1 and EL0
@@ -254,6 +271,64 @@ impl FPRegs {
             fp_fpcr: 0,
         }
     }
+
+    /// save q0 to q31, FPSR and FPCR of the current EL
+    pub fn save(&mut self) {
+        unsafe {
+            asm!("stp  q0,  q1, [{0}]
+                  stp  q2,  q3, [{0}, #32 *  1]
+                  stp  q4,  q5, [{0}, #32 *  2]
+                  stp  q6,  q7, [{0}, #32 *  3]
+                  stp  q8,  q9, [{0}, #32 *  4]
+                  stp q10, q11, [{0}, #32 *  5]
+                  stp q12, q13, [{0}, #32 *  6]
+                  stp q14, q15, [{0}, #32 *  7]
+                  stp q16, q17, [{0}, #32 *  8]
+                  stp q18, q19, [{0}, #32 *  9]
+                  stp q20, q21, [{0}, #32 * 10]
+                  stp q22, q23, [{0}, #32 * 11]
+                  stp q24, q25, [{0}, #32 * 12]
+                  stp q26, q27, [{0}, #32 * 13]
+                  stp q28, q29, [{0}, #32 * 14]
+                  stp q30, q31, [{0}, #32 * 15]
+                  mrs {1}, fpsr
+                  str {1}, [{0}, #32 * 16]
+                  mrs {1}, fpcr
+                  str {1}, [{0}, #32 * 16 + 8]",
+                in(reg) self as *mut FPRegs as u64,
+                out(reg) _,
+            );
+        }
+    }
+
+    /// restore the registers saved by save
+    pub fn restore(&self) {
+        unsafe {
+            asm!("ldp  q0,  q1, [{0}]
+                  ldp  q2,  q3, [{0}, #32 *  1]
+                  ldp  q4,  q5, [{0}, #32 *  2]
+                  ldp  q6,  q7, [{0}, #32 *  3]
+                  ldp  q8,  q9, [{0}, #32 *  4]
+                  ldp q10, q11, [{0}, #32 *  5]
+                  ldp q12, q13, [{0}, #32 *  6]
+                  ldp q14, q15, [{0}, #32 *  7]
+                  ldp q16, q17, [{0}, #32 *  8]
+                  ldp q18, q19, [{0}, #32 *  9]
+                  ldp q20, q21, [{0}, #32 * 10]
+                  ldp q22, q23, [{0}, #32 * 11]
+                  ldp q24, q25, [{0}, #32 * 12]
+                  ldp q26, q27, [{0}, #32 * 13]
+                  ldp q28, q29, [{0}, #32 * 14]
+                  ldp q30, q31, [{0}, #32 * 15]
+                  ldr {1}, [{0}, #32 * 16]
+                  msr fpsr, {1}
+                  ldr {1}, [{0}, #32 * 16 + 8]
+                  msr fpcr, {1}",
+                in(reg) self as *const FPRegs as u64,
+                out(reg) _,
+            );
+        }
+    }
 }
 
 #[derive(Copy, Clone)]
@@ -518,6 +593,11 @@ impl CPUContext {
     }
 }
 
+/// the context of the calling core
+pub fn get_my_ctx(is_secure: bool) -> &'static mut CPUContext {
+    get_ctx(percpu::index(), is_secure)
+}
+
 pub fn get_ctx(idx: usize, is_secure: bool) -> &'static mut CPUContext {
     if is_secure {
         unsafe { &mut CPU_CONTEXT_SECURE[idx] }
@@ -751,7 +831,7 @@ pub fn init_secure() {
     };
 
     // Store the re-entry information for the secure world.
-    init_context(topology::core_pos(), ep);
+    init_context(percpu::index(), ep);
 }
 
 #[cfg(feature = "ERRATA_A75_764081")]
@@ -771,7 +851,7 @@ fn errata_a75_764081(sctlr: u64) -> u64 {
 /// EL2 then EL2 is disabled by configuring all necessary EL2 registers.
 /// For all entries, the EL1 registers are initialized from the cpu_context
 pub fn init_el2_regs() {
-    let idx = topology::core_pos();
+    let idx = percpu::index();
     let ctx = unsafe { &CPU_CONTEXT_NON_SECURE[idx] };
 
     let scr_el3 = ctx.scr_el3;
@@ -38,9 +38,14 @@ pub const HCR_E2H_BIT: u64 = 1 << 34;
 pub const HCR_TGE_BIT: u64 = 1 << 27;
 pub const HCR_RW_SHIFT: u64 = 31;
 pub const HCR_RW_BIT: u64 = 1 << HCR_RW_SHIFT;
+pub const HCR_TACR_BIT: u64 = 1 << 21;
+pub const HCR_TSC_BIT: u64 = 1 << 19;
+pub const HCR_TID3_BIT: u64 = 1 << 18;
 pub const HCR_AMO_BIT: u64 = 1 << 5;
 pub const HCR_IMO_BIT: u64 = 1 << 4;
 pub const HCR_FMO_BIT: u64 = 1 << 3;
+pub const HCR_SWIO_BIT: u64 = 1 << 1;
+pub const HCR_VM_BIT: u64 = 1 << 0;
 
 // CPTR_EL2 definitions
 pub const CPTR_EL2_RES1: u64 = (1 << 13) | (1 << 12) | (0x3ff);
@@ -146,6 +151,7 @@ pub const SCTLR_NTWE_BIT: u64 = 1 << 18;
 pub const SCTLR_WXN_BIT: u64 = 1 << 19;
 pub const SCTLR_UWXN_BIT: u64 = 1 << 20;
 pub const SCTLR_IESB_BIT: u64 = 1 << 21;
+pub const SCTLR_SPAN_BIT: u64 = 1 << 23;
 pub const SCTLR_E0E_BIT: u64 = 1 << 24;
 pub const SCTLR_EE_BIT: u64 = 1 << 25;
 pub const SCTLR_UCI_BIT: u64 = 1 << 26;
@@ -314,6 +320,15 @@ pub const ID_AA64MMFR1_EL1_TWED_MASK: u64 = 0xf;
 pub const ID_AA64MMFR1_EL1_TWED_SUPPORTED: u64 = 0x1;
 pub const ID_AA64MMFR1_EL1_TWED_NOT_SUPPORTED: u64 = 0x0;
 
+pub const ID_AA64MMFR1_EL1_PAN_SHIFT: u64 = 20;
+pub const ID_AA64MMFR1_EL1_PAN_MASK: u64 = 0xf;
+pub const ID_AA64MMFR1_EL1_PAN_NOT_SUPPORTED: u64 = 0x0;
+
+// ID_AA64MMFR2_EL1 definitions
+pub const ID_AA64MMFR2_EL1_UAO_SHIFT: u64 = 4;
+pub const ID_AA64MMFR2_EL1_UAO_MASK: u64 = 0xf;
+pub const ID_AA64MMFR2_EL1_UAO_SUPPORTED: u64 = 0x1;
+
 pub const MPIDR_AFFINITY_MASK: u64 = 0xff00ffffff;
 
 pub enum EL {
@@ -374,10 +389,12 @@ sysreg!(cntv_ctl_el0);
 sysreg!(cntv_cval_el0);
 sysreg!(cntfrq_el0);
 sysreg!(cntpct_el0);
+sysreg!(cntvct_el0);
 sysreg!(tpidr_el0);
 sysreg!(tpidrro_el0);
 sysreg!(pmcr_el0);
 sysreg!(sp_el0);
+sysreg!(daif);
 sysreg!(ctr_el0);
 
 sysreg!(elr_el1);
@@ -403,9 +420,14 @@ sysreg!(contextidr_el1);
 sysreg!(vbar_el1);
 sysreg!(mpidr_el1);
 sysreg!(midr_el1);
+sysreg!(id_aa64pfr0_el1);
 sysreg!(id_aa64pfr1_el1);
+sysreg!(id_aa64dfr0_el1);
+sysreg!(id_aa64isar0_el1);
+sysreg!(id_aa64isar1_el1);
 sysreg!(id_aa64mmfr0_el1);
 sysreg!(id_aa64mmfr1_el1);
+sysreg!(id_aa64mmfr2_el1);
 sysreg!(clidr_el1);
 
 sysreg!(sctlr_el2);
@@ -413,13 +435,19 @@ sysreg!(mdcr_el2);
 sysreg!(vpidr_el2);
 sysreg!(vmpidr_el2);
 sysreg!(vttbr_el2);
+sysreg!(vtcr_el2);
+sysreg!(hpfar_el2);
+sysreg!(far_el2);
+sysreg!(ttbr0_el2);
 sysreg!(hcr_el2);
 sysreg!(cptr_el2);
 sysreg!(cnthctl_el2);
 sysreg!(cntvoff_el2);
 sysreg!(hstr_el2);
 sysreg!(cnthp_ctl_el2);
+sysreg!(cnthp_cval_el2);
 sysreg!(esr_el2);
+sysreg!(tpidr_el2);
 
 sysreg!(scr_el3);
 sysreg!(esr_el3);
@@ -427,7 +455,9 @@ sysreg!(far_el3);
 sysreg!(sctlr_el3);
 sysreg!(actlr_el3);
 sysreg!(mair_el3);
+sysreg!(ttbr0_el3);
 sysreg!(cptr_el3);
+sysreg!(tpidr_el3);
 
 pub fn get_affinity_lv0() -> u64 {
     let mpidr: u64;
@@ -456,6 +486,26 @@ pub fn is_armv8_6_twed_present() -> bool {
         == ID_AA64MMFR1_EL1_TWED_SUPPORTED
 }
 
+pub fn is_armv8_1_pan_present() -> bool {
+    ((id_aa64mmfr1_el1::get() >> ID_AA64MMFR1_EL1_PAN_SHIFT) & ID_AA64MMFR1_EL1_PAN_MASK)
+        != ID_AA64MMFR1_EL1_PAN_NOT_SUPPORTED
+}
+
+pub fn is_armv8_2_uao_present() -> bool {
+    ((id_aa64mmfr2_el1::get() >> ID_AA64MMFR2_EL1_UAO_SHIFT) & ID_AA64MMFR2_EL1_UAO_MASK)
+        == ID_AA64MMFR2_EL1_UAO_SUPPORTED
+}
+
+/// set PSTATE.PAN, ARMv8.1 or later is required
+pub fn set_pan() {
+    unsafe { asm!(".inst 0xd500419f") }; // msr pan, #1
+}
+
+/// clear PSTATE.UAO, ARMv8.2 or later is required
+pub fn clear_uao() {
+    unsafe { asm!(".inst 0xd500407f") }; // msr uao, #0
+}
+
 pub fn is_armv8_6_fgt_present() -> bool {
     ((id_aa64mmfr0_el1::get() >> ID_AA64MMFR0_EL1_FGT_SHIFT) & ID_AA64MMFR0_EL1_FGT_MASK)
         == ID_AA64MMFR0_EL1_FGT_SUPPORTED
@@ -508,6 +558,77 @@ pub fn isb() {
     unsafe { asm!("isb") };
 }
 
+/// current stack pointer
+pub fn get_sp() -> u64 {
+    let sp: u64;
+    unsafe { asm!("mov {}, sp", lateout(reg) sp) };
+    sp
+}
+
+/// dsb ish
+pub fn dsb_ish() {
+    unsafe { asm!("dsb ish") };
+}
+
+/// dsb ishst
+pub fn dsb_ishst() {
+    unsafe { asm!("dsb ishst") };
+}
+
+/// dsb nsh
+pub fn dsb_nsh() {
+    unsafe { asm!("dsb nsh") };
+}
+
+/// invalidate stage 1 and 2 TLB entries of the current VMID, inner shareable
+pub fn tlbi_vmalls12e1is() {
+    unsafe { asm!("tlbi vmalls12e1is") };
+}
+
+/// invalidate TLB entries of EL1&0 of the current VMID, inner shareable
+pub fn tlbi_vmalle1is() {
+    unsafe { asm!("tlbi vmalle1is") };
+}
+
+/// invalidate TLB entries of EL2, inner shareable
+pub fn tlbi_alle2is() {
+    unsafe { asm!("tlbi alle2is") };
+}
+
+/// invalidate TLB entries of EL3, inner shareable
+pub fn tlbi_alle3is() {
+    unsafe { asm!("tlbi alle3is") };
+}
+
+/// invalidate TLB entries tagged with the ASID, EL1&0, inner shareable
+pub fn tlbi_aside1is(asid: u64) {
+    unsafe { asm!("tlbi aside1is, {}", in(reg) asid << 48) };
+}
+
+/// invalidate TLB entries of the virtual address tagged with the ASID, EL1&0, inner shareable
+pub fn tlbi_vae1is(asid: u64, vm_addr: u64) {
+    let arg = asid << 48 | (vm_addr >> 12) & 0xFFF_FFFF_FFFF;
+    unsafe { asm!("tlbi vae1is, {}", in(reg) arg) };
+}
+
+/// invalidate TLB entries tagged with the ASID, EL1&0, the calling core only
+pub fn tlbi_aside1(asid: u64) {
+    unsafe { asm!("tlbi aside1, {}", in(reg) asid << 48) };
+}
+
+/// invalidate TLB entries of the virtual address tagged with the ASID, EL1&0,
+/// the calling core only
+pub fn tlbi_vae1(asid: u64, vm_addr: u64) {
+    let arg = asid << 48 | (vm_addr >> 12) & 0xFFF_FFFF_FFFF;
+    unsafe { asm!("tlbi vae1, {}", in(reg) arg) };
+}
+
+/// invalidate TLB entries of the virtual address for all ASIDs, EL1&0, inner shareable
+pub fn tlbi_vaae1is(vm_addr: u64) {
+    let arg = (vm_addr >> 12) & 0xFFF_FFFF_FFFF;
+    unsafe { asm!("tlbi vaae1is, {}", in(reg) arg) };
+}
+
 pub fn start_non_primary() {
     if cfg!(feature = "raspi3") {
         unsafe {
@@ -2,6 +2,9 @@ use super::context::GpRegs;
 use super::cpu;
 use super::syscall;
 use crate::driver;
+use crate::hyp;
+use crate::ktimer;
+use crate::sched;
 
 const ESR_EL1_EC_MASK: u64 = 0b111111 << 26;
 const ESR_EL1_EC_SVC32: u64 = 0b010001 << 26;
@@ -178,6 +181,11 @@ pub fn curr_el_spx_serror_el2(_ctx: *mut GpRegs, _sp: usize) {
 // from lower EL (AArch64)
 #[no_mangle]
 pub fn lower_el_aarch64_sync_el2(ctx: *mut GpRegs, _sp: usize) {
+    if hyp::is_enabled() {
+        hyp::handle_sync(unsafe { &mut *ctx });
+        return;
+    }
+
     let r = unsafe { &*ctx };
     driver::uart::puts("EL2 exception: Sync lower AArch64\nELR = ");
     driver::uart::hex(r.elr);
@@ -189,7 +197,11 @@ pub fn lower_el_aarch64_sync_el2(ctx: *mut GpRegs, _sp: usize) {
 }
 
 #[no_mangle]
-pub fn lower_el_aarch64_irq_el2(_ctx: *mut GpRegs, _sp: usize) {}
+pub fn lower_el_aarch64_irq_el2(_ctx: *mut GpRegs, _sp: usize) {
+    if hyp::is_enabled() {
+        hyp::handle_irq();
+    }
+}
 
 #[no_mangle]
 pub fn lower_el_aarch64_fiq_el2(_ctx: *mut GpRegs, _sp: usize) {}
@@ -249,7 +261,9 @@ pub fn curr_el_spx_sync_el1(ctx: *mut GpRegs, _sp: usize) {
 
 #[no_mangle]
 pub fn curr_el_spx_irq_el1(_ctx: *mut GpRegs, _sp: usize) {
-    driver::uart::puts("EL1 exception: SPX IRQ\n");
+    driver::irq::handle();
+    ktimer::run_deferred();
+    sched::preempt();
 }
 
 #[no_mangle]
@@ -265,7 +279,7 @@ pub fn curr_el_spx_serror_el1(_ctx: *mut GpRegs, _sp: usize) {
 // from lower EL (AArch64)
 #[no_mangle]
 pub fn lower_el_aarch64_sync_el1(ctx: *mut GpRegs, sp: usize) {
-    let r = unsafe { &*ctx };
+    let r = unsafe { &mut *ctx };
     let esr = cpu::esr_el1::get();
     if esr & ESR_EL1_EC_MASK == ESR_EL1_EC_SVC64 {
         syscall::svc::handle64(esr & 0xff, r, sp);
@@ -275,7 +289,11 @@ pub fn lower_el_aarch64_sync_el1(ctx: *mut GpRegs, sp: usize) {
 }
 
 #[no_mangle]
-pub fn lower_el_aarch64_irq_el1(_ctx: *mut GpRegs, _sp: usize) {}
+pub fn lower_el_aarch64_irq_el1(_ctx: *mut GpRegs, _sp: usize) {
+    driver::irq::handle();
+    ktimer::run_deferred();
+    sched::preempt();
+}
 
 #[no_mangle]
 pub fn lower_el_aarch64_fiq_el1(_ctx: *mut GpRegs, _sp: usize) {}
@@ -0,0 +1,191 @@
+// lock debugging, enabled by the lock_debug feature
+//
+// Each core records the locks it holds and the callers which took them.
+// - taking a lock held by the same core is reported as recursion, it never succeeds
+// - every pair of nested locks is recorded, and taking them in the reverse order
+//   on any core is reported as a lock-order inversion, which may deadlock
+// - spinning longer than SPIN_LIMIT is reported with the core and the caller holding the lock
+//
+// Locks are identified by their addresses.
+// Preemption is disabled while a lock is held, see preempt.rs,
+// so the holder stays on the core until it releases the lock.
+
+use crate::aarch64::preempt::core_id;
+use crate::driver::topology::CORE_COUNT;
+use crate::driver::uart;
+
+use core::hint::spin_loop;
+use core::panic::Location;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+/// the maximum number of locks held by a core at once
+const MAX_HELD: usize = 16;
+
+/// the maximum number of recorded pairs of nested locks
+const MAX_ORDERS: usize = 256;
+
+/// the number of spins regarded as a timeout
+const SPIN_LIMIT: u64 = 1 << 24;
+
+type Caller = &'static Location<'static>;
+
+#[derive(Copy, Clone)]
+struct Held {
+    addr: usize,
+    caller: Option<Caller>,
+}
+
+const EMPTY_HELD: Held = Held {
+    addr: 0,
+    caller: None,
+};
+
+// each core writes only its own stack, other cores read it only to report
+static mut HELD: [[Held; MAX_HELD]; CORE_COUNT] = [[EMPTY_HELD; MAX_HELD]; CORE_COUNT];
+static mut NUM_HELD: [usize; CORE_COUNT] = [0; CORE_COUNT];
+
+/// (outer, inner), inner was taken while outer was held
+static mut ORDERS: [(usize, usize); MAX_ORDERS] = [(0, 0); MAX_ORDERS];
+static mut NUM_ORDERS: usize = 0;
+static ORDERS_LOCK: AtomicBool = AtomicBool::new(false);
+
+fn print_caller(caller: Option<Caller>) {
+    match caller {
+        Some(c) => {
+            uart::puts(c.file());
+            uart::puts(":");
+            uart::decimal(c.line() as u64);
+        }
+        None => uart::puts("unknown"),
+    }
+}
+
+fn print_lock(msg: &str, addr: usize) {
+    uart::puts("lock_debug: ");
+    uart::puts(msg);
+    uart::puts(": lock 0x");
+    uart::hex(addr as u64);
+    uart::puts("\n");
+}
+
+fn find_held(core: usize, addr: usize) -> Option<Held> {
+    let held = unsafe { &HELD[core][..NUM_HELD[core]] };
+    held.iter().rev().find(|h| h.addr == addr).copied()
+}
+
+/// called before taking the lock at addr
+pub fn acquiring(addr: usize, caller: Caller) {
+    let core = core_id();
+    if let Some(h) = find_held(core, addr) {
+        print_lock("recursive acquisition", addr);
+        uart::puts("  held by core ");
+        uart::decimal(core as u64);
+        uart::puts(" at ");
+        print_caller(h.caller);
+        uart::puts("\n  taken again at ");
+        print_caller(Some(caller));
+        uart::puts("\n");
+        panic!("recursive lock");
+    }
+
+    let held = unsafe { &HELD[core][..NUM_HELD[core]] };
+    for h in held.iter() {
+        check_order(h, addr, caller);
+    }
+}
+
+/// record that outer is held while inner is taken,
+/// and report if inner has been held while outer was taken
+fn check_order(outer: &Held, inner: usize, caller: Caller) {
+    while ORDERS_LOCK
+        .compare_exchange_weak(false, true, Ordering::Acquire, Ordering::Relaxed)
+        .is_err()
+    {
+        spin_loop();
+    }
+
+    let orders = unsafe { &mut ORDERS[..NUM_ORDERS] };
+    if orders.iter().any(|o| *o == (inner, outer.addr)) {
+        print_lock("lock-order inversion", inner);
+        uart::puts("  taken at ");
+        print_caller(Some(caller));
+        uart::puts(" while 0x");
+        uart::hex(outer.addr as u64);
+        uart::puts(" taken at ");
+        print_caller(outer.caller);
+        uart::puts(" is held,\n  but the reverse order has been seen\n");
+    } else if !orders.iter().any(|o| *o == (outer.addr, inner)) {
+        unsafe {
+            if NUM_ORDERS < MAX_ORDERS {
+                ORDERS[NUM_ORDERS] = (outer.addr, inner);
+                NUM_ORDERS += 1;
+            }
+        }
+    }
+
+    ORDERS_LOCK.store(false, Ordering::Release);
+}
+
+/// called after taking the lock at addr
+pub fn acquired(addr: usize, caller: Caller) {
+    let core = core_id();
+    unsafe {
+        let n = NUM_HELD[core];
+        if n < MAX_HELD {
+            HELD[core][n] = Held {
+                addr,
+                caller: Some(caller),
+            };
+            NUM_HELD[core] = n + 1;
+        }
+    }
+}
+
+/// called after releasing the lock at addr, locks may be released in any order
+pub fn released(addr: usize) {
+    let core = core_id();
+    unsafe {
+        let n = NUM_HELD[core];
+        let held = &mut HELD[core];
+        if let Some(i) = held[..n].iter().rposition(|h| h.addr == addr) {
+            held.copy_within(i + 1..n, i);
+            NUM_HELD[core] = n - 1;
+        }
+    }
+}
+
+/// counter of spins for a lock, reports the holder every SPIN_LIMIT spins
+pub struct Spin {
+    addr: usize,
+    count: u64,
+}
+
+impl Spin {
+    pub fn new(addr: usize) -> Spin {
+        Spin { addr, count: 0 }
+    }
+
+    pub fn spin(&mut self) {
+        if !cfg!(feature = "lock_debug") {
+            return;
+        }
+
+        self.count += 1;
+        if self.count % SPIN_LIMIT != 0 {
+            return;
+        }
+
+        print_lock("spinning too long", self.addr);
+        uart::puts("  waited by core ");
+        uart::decimal(core_id() as u64);
+        for core in 0..CORE_COUNT {
+            if let Some(h) = find_held(core, self.addr) {
+                uart::puts(", held by core ");
+                uart::decimal(core as u64);
+                uart::puts(" at ");
+                print_caller(h.caller);
+            }
+        }
+        uart::puts("\n");
+    }
+}
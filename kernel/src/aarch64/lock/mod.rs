@@ -0,0 +1,582 @@
+pub mod debug;
+
+use super::{cpu, preempt};
+use crate::driver::topology::{core_pos, CORE_COUNT};
+
+use core::cell::UnsafeCell;
+use core::hint::spin_loop;
+use core::ops::{Deref, DerefMut};
+use core::panic::Location;
+use core::ptr::{read_volatile, write_volatile};
+use core::sync::atomic::{AtomicU32, Ordering};
+
+/// ```
+/// let var = LockVar::new(); // create lock variable
+/// var.lock();               // acquire lock
+/// ```
+pub struct LockVar {
+    var: u64,
+}
+
+impl LockVar {
+    pub const fn new() -> LockVar {
+        LockVar { var: 0 }
+    }
+
+    #[track_caller]
+    pub fn lock(&mut self) -> SpinLock {
+        SpinLock::new(&mut self.var)
+    }
+
+    /// this function lock but do not release automatically
+    #[track_caller]
+    pub unsafe fn force_lock(&mut self) {
+        lock_var(&mut self.var);
+    }
+
+    /// unlock
+    pub unsafe fn force_unlock(&mut self) {
+        unlock_var(&mut self.var);
+    }
+}
+
+pub struct SpinLock<'a> {
+    lock: &'a mut u64,
+}
+
+impl<'a> SpinLock<'a> {
+    #[track_caller]
+    fn new(n: &'a mut u64) -> SpinLock<'a> {
+        lock_var(n);
+        return SpinLock { lock: n };
+    }
+}
+
+impl<'a> Drop for SpinLock<'a> {
+    fn drop(&mut self) {
+        unlock_var(&mut self.lock);
+    }
+}
+
+#[track_caller]
+fn lock_var<'a>(n: &'a mut u64) {
+    let addr = n as *mut u64 as usize;
+    preempt::disable();
+    debug_acquiring(addr);
+
+    if 0 != unsafe { read_volatile(n) } || !test_and_set_no_release(n) {
+        let mut spin = debug::Spin::new(addr);
+        loop {
+            cpu::send_event_local();
+            loop {
+                cpu::wait_event();
+                if 0 == unsafe { read_volatile(n) } {
+                    break;
+                }
+                spin.spin();
+            }
+
+            if test_and_set_no_release(n) {
+                break;
+            }
+        }
+    }
+
+    debug_acquired(addr);
+}
+
+fn unlock_var<'a>(n: &'a mut u64) {
+    let addr = n as *mut u64 as usize;
+    debug_released(addr);
+    unsafe {
+        asm!("stlr xzr, [{}]", in(reg) addr);
+    }
+    preempt::enable();
+}
+
+/// ```
+/// let ticket = BakeryTicket::new(); // create lock variable
+/// ticket.lock();                    // acquire lock
+/// ```
+pub struct BakeryTicket {
+    entering: [bool; CORE_COUNT as usize],
+    number: [usize; CORE_COUNT as usize],
+}
+
+impl BakeryTicket {
+    pub const fn new() -> BakeryTicket {
+        BakeryTicket {
+            entering: [false; CORE_COUNT as usize],
+            number: [0; CORE_COUNT as usize],
+        }
+    }
+
+    #[track_caller]
+    pub fn lock(&mut self) -> BakeryLock {
+        BakeryLock::new(self)
+    }
+}
+
+pub struct BakeryLock<'a> {
+    ticket: &'a mut BakeryTicket,
+}
+
+impl<'a> BakeryLock<'a> {
+    #[track_caller]
+    fn new(t: &'a mut BakeryTicket) -> BakeryLock<'a> {
+        let addr = t as *mut BakeryTicket as usize;
+        preempt::disable();
+        debug_acquiring(addr);
+
+        let core = core_pos() as usize;
+        unsafe {
+            write_volatile(&mut t.entering[core], true);
+        }
+        cpu::dmb_sy();
+        let mut max = 0;
+        for v in &t.number {
+            if max < *v {
+                max = *v;
+            }
+        }
+        unsafe {
+            write_volatile(&mut t.number[core], 1 + max);
+            write_volatile(&mut t.entering[core], false);
+        }
+        cpu::dmb_sy();
+
+        let mut spin = debug::Spin::new(addr);
+        for i in 0..(CORE_COUNT as usize) {
+            while unsafe { read_volatile(&t.entering[i]) } {
+                spin.spin();
+            }
+
+            let mut n = unsafe { read_volatile(&t.number[i]) };
+            while n != 0 && (n, i) < (unsafe { read_volatile(&t.number[core]) }, core) {
+                spin.spin();
+                n = unsafe { read_volatile(&t.number[i]) };
+            }
+        }
+
+        debug_acquired(addr);
+        BakeryLock { ticket: t }
+    }
+}
+
+impl<'a> Drop for BakeryLock<'a> {
+    fn drop(&mut self) {
+        debug_released(self.ticket as *mut BakeryTicket as usize);
+        let core = core_pos() as usize;
+        self.ticket.number[core] = 0;
+        preempt::enable();
+    }
+}
+
+/// ticket lock, cores acquire it in the order of arrival
+///
+/// ```
+/// let ticket = TicketLock::new(); // create lock variable
+/// ticket.lock();                  // acquire lock
+/// ```
+pub struct TicketLock {
+    next: AtomicU32,
+    serving: AtomicU32,
+}
+
+impl TicketLock {
+    pub const fn new() -> TicketLock {
+        TicketLock {
+            next: AtomicU32::new(0),
+            serving: AtomicU32::new(0),
+        }
+    }
+
+    #[track_caller]
+    pub fn lock(&self) -> TicketGuard {
+        let addr = self as *const TicketLock as usize;
+        preempt::disable();
+        debug_acquiring(addr);
+
+        let ticket = self.next.fetch_add(1, Ordering::Relaxed);
+        let mut spin = debug::Spin::new(addr);
+        while self.serving.load(Ordering::Acquire) != ticket {
+            spin.spin();
+            spin_loop();
+        }
+
+        debug_acquired(addr);
+        TicketGuard { lock: self }
+    }
+
+    /// acquire the lock only if no one holds or waits for it
+    #[track_caller]
+    pub fn try_lock(&self) -> Option<TicketGuard> {
+        preempt::disable();
+        let ticket = self.serving.load(Ordering::Relaxed);
+        if self
+            .next
+            .compare_exchange(
+                ticket,
+                ticket.wrapping_add(1),
+                Ordering::Acquire,
+                Ordering::Relaxed,
+            )
+            .is_err()
+        {
+            preempt::enable();
+            return None;
+        }
+
+        debug_acquired(self as *const TicketLock as usize);
+        Some(TicketGuard { lock: self })
+    }
+
+    fn unlock(&self) {
+        debug_released(self as *const TicketLock as usize);
+        self.serving.fetch_add(1, Ordering::Release);
+        preempt::enable();
+    }
+}
+
+pub struct TicketGuard<'a> {
+    lock: &'a TicketLock,
+}
+
+impl<'a> Drop for TicketGuard<'a> {
+    fn drop(&mut self) {
+        self.lock.unlock();
+    }
+}
+
+/// DAIF is saved and IRQ and FIQ are masked while this is alive,
+/// EL0 cannot access DAIF, so this is only for EL1 or higher
+pub struct IrqMask {
+    daif: u64,
+}
+
+impl IrqMask {
+    pub fn new() -> IrqMask {
+        let daif = cpu::daif::get();
+        unsafe { asm!("msr daifset, #3") };
+        IrqMask { daif }
+    }
+}
+
+impl Drop for IrqMask {
+    fn drop(&mut self) {
+        cpu::daif::set(self.daif);
+    }
+}
+
+/// a guard of a lock held with IRQ and FIQ masked,
+/// the lock is released before DAIF is restored
+pub struct IrqGuard<G> {
+    guard: G,
+    _mask: IrqMask,
+}
+
+impl<G: Deref> Deref for IrqGuard<G> {
+    type Target = G::Target;
+
+    fn deref(&self) -> &G::Target {
+        &self.guard
+    }
+}
+
+impl<G: DerefMut> DerefMut for IrqGuard<G> {
+    fn deref_mut(&mut self) -> &mut G::Target {
+        &mut self.guard
+    }
+}
+
+/// mutual exclusion owning the data, based on TicketLock
+///
+/// ```
+/// static ASIDS: Mutex<u64> = Mutex::new(0);
+///
+/// *ASIDS.lock() |= 1;
+///
+/// // in the code shared with interrupt handlers
+/// *ASIDS.lock_irq() |= 2;
+/// ```
+pub struct Mutex<T> {
+    lock: TicketLock,
+    data: UnsafeCell<T>,
+}
+
+unsafe impl<T: Send> Sync for Mutex<T> {}
+unsafe impl<T: Send> Send for Mutex<T> {}
+
+impl<T> Mutex<T> {
+    pub const fn new(data: T) -> Mutex<T> {
+        Mutex {
+            lock: TicketLock::new(),
+            data: UnsafeCell::new(data),
+        }
+    }
+
+    #[track_caller]
+    pub fn lock(&self) -> MutexGuard<T> {
+        MutexGuard {
+            _guard: self.lock.lock(),
+            data: unsafe { &mut *self.data.get() },
+        }
+    }
+
+    #[track_caller]
+    pub fn try_lock(&self) -> Option<MutexGuard<T>> {
+        Some(MutexGuard {
+            _guard: self.lock.try_lock()?,
+            data: unsafe { &mut *self.data.get() },
+        })
+    }
+
+    /// lock with IRQ and FIQ masked, so that an interrupt handler on the same core
+    /// does not spin for the lock forever
+    #[track_caller]
+    pub fn lock_irq(&self) -> IrqGuard<MutexGuard<T>> {
+        let mask = IrqMask::new();
+        IrqGuard {
+            guard: self.lock(),
+            _mask: mask,
+        }
+    }
+}
+
+pub struct MutexGuard<'a, T> {
+    _guard: TicketGuard<'a>,
+    data: &'a mut T,
+}
+
+impl<'a, T> Deref for MutexGuard<'a, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        self.data
+    }
+}
+
+impl<'a, T> DerefMut for MutexGuard<'a, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        self.data
+    }
+}
+
+const RW_WRITER: u32 = 1; // a writer holds the lock
+const RW_WAITING: u32 = 2; // a writer waits, new readers must wait too
+const RW_READER: u32 = 4; // readers are counted by this unit
+
+/// readers-writer lock owning the data,
+/// waiting writers take precedence over new readers, so writers never starve
+///
+/// ```
+/// static TABLE: RwLock<[u64; 16]> = RwLock::new([0; 16]);
+///
+/// let n = TABLE.read()[0];
+/// TABLE.write()[0] = n + 1;
+/// ```
+pub struct RwLock<T> {
+    state: AtomicU32,
+    data: UnsafeCell<T>,
+}
+
+unsafe impl<T: Send + Sync> Sync for RwLock<T> {}
+unsafe impl<T: Send> Send for RwLock<T> {}
+
+impl<T> RwLock<T> {
+    pub const fn new(data: T) -> RwLock<T> {
+        RwLock {
+            state: AtomicU32::new(0),
+            data: UnsafeCell::new(data),
+        }
+    }
+
+    #[track_caller]
+    pub fn read(&self) -> ReadGuard<T> {
+        let addr = self as *const RwLock<T> as usize;
+        preempt::disable();
+        debug_acquiring(addr);
+
+        let mut spin = debug::Spin::new(addr);
+        loop {
+            let state = self.state.load(Ordering::Relaxed);
+            if state & (RW_WRITER | RW_WAITING) == 0
+                && self
+                    .state
+                    .compare_exchange_weak(
+                        state,
+                        state + RW_READER,
+                        Ordering::Acquire,
+                        Ordering::Relaxed,
+                    )
+                    .is_ok()
+            {
+                debug_acquired(addr);
+                return ReadGuard { lock: self };
+            }
+            spin.spin();
+            spin_loop();
+        }
+    }
+
+    #[track_caller]
+    pub fn write(&self) -> WriteGuard<T> {
+        let addr = self as *const RwLock<T> as usize;
+        preempt::disable();
+        debug_acquiring(addr);
+
+        let mut spin = debug::Spin::new(addr);
+        loop {
+            let state = self.state.load(Ordering::Relaxed);
+            if state & !RW_WAITING == 0 {
+                // no one holds the lock, the waiting flag is cleared
+                if self
+                    .state
+                    .compare_exchange_weak(state, RW_WRITER, Ordering::Acquire, Ordering::Relaxed)
+                    .is_ok()
+                {
+                    debug_acquired(addr);
+                    return WriteGuard { lock: self };
+                }
+            } else if state & RW_WAITING == 0 {
+                self.state.fetch_or(RW_WAITING, Ordering::Relaxed);
+            }
+            spin.spin();
+            spin_loop();
+        }
+    }
+
+    /// read with IRQ and FIQ masked
+    #[track_caller]
+    pub fn read_irq(&self) -> IrqGuard<ReadGuard<T>> {
+        let mask = IrqMask::new();
+        IrqGuard {
+            guard: self.read(),
+            _mask: mask,
+        }
+    }
+
+    /// write with IRQ and FIQ masked
+    #[track_caller]
+    pub fn write_irq(&self) -> IrqGuard<WriteGuard<T>> {
+        let mask = IrqMask::new();
+        IrqGuard {
+            guard: self.write(),
+            _mask: mask,
+        }
+    }
+}
+
+pub struct ReadGuard<'a, T> {
+    lock: &'a RwLock<T>,
+}
+
+impl<'a, T> Deref for ReadGuard<'a, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.lock.data.get() }
+    }
+}
+
+impl<'a, T> Drop for ReadGuard<'a, T> {
+    fn drop(&mut self) {
+        debug_released(self.lock as *const RwLock<T> as usize);
+        self.lock.state.fetch_sub(RW_READER, Ordering::Release);
+        preempt::enable();
+    }
+}
+
+pub struct WriteGuard<'a, T> {
+    lock: &'a RwLock<T>,
+}
+
+impl<'a, T> Deref for WriteGuard<'a, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.lock.data.get() }
+    }
+}
+
+impl<'a, T> DerefMut for WriteGuard<'a, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.lock.data.get() }
+    }
+}
+
+impl<'a, T> Drop for WriteGuard<'a, T> {
+    fn drop(&mut self) {
+        debug_released(self.lock as *const RwLock<T> as usize);
+
+        // keep the waiting flag set by other writers
+        self.lock.state.fetch_and(!RW_WRITER, Ordering::Release);
+        preempt::enable();
+    }
+}
+
+// hooks of lock debugging, see debug.rs
+
+#[track_caller]
+fn debug_acquiring(addr: usize) {
+    if cfg!(feature = "lock_debug") {
+        debug::acquiring(addr, Location::caller());
+    }
+}
+
+#[track_caller]
+fn debug_acquired(addr: usize) {
+    if cfg!(feature = "lock_debug") {
+        debug::acquired(addr, Location::caller());
+    }
+}
+
+fn debug_released(addr: usize) {
+    if cfg!(feature = "lock_debug") {
+        debug::released(addr);
+    }
+}
+
+/// load-acquire and store exclusive
+fn test_and_set_no_release(n: &mut u64) -> bool {
+    let mut rd: u64;
+    let addr = n as *mut u64 as u64;
+    unsafe {
+        asm! (
+            "mov   {2}, #1
+             1:
+             ldaxr {3}, [{0}]
+             stxr  {4:w}, {2}, [{0}]
+             cbnz  {4:w}, 1b
+             and   {1}, {3}, #1",
+            in(reg) addr,
+            lateout(reg) rd,
+            out(reg) _,
+            out(reg) _,
+            out(reg) _,
+        );
+    }
+    rd == 0
+}
+
+/// load-acquire and store-release exclusive
+fn test_and_set(n: &mut u64) -> bool {
+    let mut rd: u64;
+    let addr = n as *mut u64 as u64;
+    unsafe {
+        asm! (
+            "mov   {2}, #1
+             1:
+             ldaxr {3}, [{0}]
+             stlxr {4:w}, {2}, [{0}]
+             cbnz  {4:w}, 1b
+             and   {1}, {3}, #1",
+            in(reg) addr,
+            lateout(reg) rd,
+            out(reg) _,
+            out(reg) _,
+            out(reg) _,
+        );
+    }
+    rd == 0
+}
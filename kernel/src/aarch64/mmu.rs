@@ -1,6 +1,8 @@
 use core::slice;
 
+use super::cache;
 use super::cpu;
+use super::lock::Mutex;
 use crate::driver;
 use crate::driver::memory::{
     DEVICE_MEM_END, DEVICE_MEM_START, ROM_END, ROM_START, SRAM_END, SRAM_START,
@@ -8,25 +10,40 @@ use crate::driver::memory::{
 
 const NUM_CPU: u64 = driver::topology::CORE_COUNT as u64;
 
-pub const EL1_ADDR_OFFSET: u64 = 0x3FFFFF << 42;
+// granule and virtual address space of the firmware and the kernel
+// 64KiB granule, 42 bits (4TiB) space, level 2 and 3 translation tables
+pub const GRANULE: Granule = Granule::Size64KiB;
+pub const VA_BITS: u64 = 42;
 
+pub const EL1_ADDR_OFFSET: u64 = !((1 << VA_BITS) - 1);
+
+// the number of pages reserved for translation tables,
+// tables are allocated from them on demand,
+// the levels below are of the 64KiB granule and the 42 bits space
+//
 // level 2 table x 1 (for 4TiB space)
 // level 3 table x 8 (for 512MiB x 8 = 4GiB space)
-pub const FIRM_LV2_TABLE_NUM: usize = 1;
-pub const FIRM_LV3_TABLE_NUM: usize = 8;
-pub const FIRM_TABLE_NUM: usize = FIRM_LV2_TABLE_NUM + FIRM_LV3_TABLE_NUM;
+pub const FIRM_TABLE_NUM: usize = 9;
 
 // level 2 table x 1 (for 4TiB space)
 // level 3 table x 8 (for 512MiB x 8 = 4GiB space)
-pub const KERN_TTBR0_LV2_TABLE_NUM: usize = 1;
-pub const KERN_TTBR0_LV3_TABLE_NUM: usize = 8;
-pub const KERN_TTBR0_TABLE_NUM: usize = KERN_TTBR0_LV2_TABLE_NUM + KERN_TTBR0_LV3_TABLE_NUM;
+pub const KERN_TTBR0_TABLE_NUM: usize = 9;
 
 // level 2 table x 1 (for 4TiB space)
 // level 3 table x 4 (for 2GiB space)
-pub const KERN_TTBR1_LV2_TABLE_NUM: usize = 1;
-pub const KERN_TTBR1_LV3_TABLE_NUM: usize = 4;
-pub const KERN_TTBR1_TABLE_NUM: usize = KERN_TTBR1_LV2_TABLE_NUM + KERN_TTBR1_LV3_TABLE_NUM;
+pub const KERN_TTBR1_TABLE_NUM: usize = 5;
+
+// 16 tables of the 4KiB granule in a page, see STAGE2_GRANULE
+// level 1 table x 1 (for 512GiB space)
+// level 2 table x 4 (for 1GiB x 4 = 4GiB space)
+// level 3 table x 139 (for 2MiB blocks split by hidden or emulated regions)
+pub const STAGE2_TABLE_NUM: usize = 9;
+
+// granule of stage 2, 4KiB because GICV is not 64KiB aligned on pine64
+pub const STAGE2_GRANULE: Granule = Granule::Size4KiB;
+
+// intermediate physical address space of a guest, 512GiB, level 1 to 3 tables
+pub const IPA_BITS: u64 = 39;
 
 static mut MEMORY_MAP: Addr = Addr {
     no_cache_start: 0,
@@ -37,6 +54,8 @@ static mut MEMORY_MAP: Addr = Addr {
     tt_el1_ttbr0_end: 0,
     tt_el1_ttbr1_start: 0,
     tt_el1_ttbr1_end: 0,
+    tt_stage2_start: 0,
+    tt_stage2_end: 0,
     rom_start: 0,
     rom_end: 0,
     sram_start: 0,
@@ -46,8 +65,20 @@ static mut MEMORY_MAP: Addr = Addr {
     stack_el1_start: 0,
     stack_el0_end: 0,
     stack_el0_start: 0,
+    heap_firm_start: 0,
+    heap_firm_end: 0,
+    heap_el1_start: 0,
+    heap_el1_end: 0,
+    stack_pool_start: 0,
+    stack_pool_end: 0,
+    guest_image_start: 0,
+    guest_image_end: 0,
+    el0_image_start: 0,
+    el0_image_end: 0,
     el0_heap_start: 0,
     el0_heap_end: 0,
+    dram_start: 0,
+    dram_end: 0,
 };
 
 extern "C" {
@@ -59,6 +90,8 @@ extern "C" {
     static __bss_end: u64;
     static __stack_firm_end: u64;
     static __stack_firm_start: u64;
+    static __trampoline_start: u64;
+    static __trampoline_end: u64;
 }
 
 pub fn get_free_mem_start() -> u64 {
@@ -93,8 +126,16 @@ pub fn get_data_end() -> u64 {
     unsafe { &__data_end as *const u64 as u64 }
 }
 
+pub fn get_trampoline_start() -> u64 {
+    unsafe { &__trampoline_start as *const u64 as u64 }
+}
+
+pub fn get_trampoline_end() -> u64 {
+    unsafe { &__trampoline_end as *const u64 as u64 }
+}
+
 // 64KB page
-// level 2 and 3 translation tables
+// the unit of memory management and of the translation table pool
 
 pub const PAGESIZE: u64 = 64 * 1024;
 
@@ -105,6 +146,7 @@ const FLAG_L3_XN: u64 = 1 << 54; // execute never
 const FLAG_L3_PXN: u64 = 1 << 53; // priviledged execute
 const FLAG_L3_CONT: u64 = 1 << 52; // contiguous
 const FLAG_L3_DBM: u64 = 1 << 51; // dirty bit modifier
+const FLAG_L3_NG: u64 = 1 << 11; // not global, tagged with ASID
 const FLAG_L3_AF: u64 = 1 << 10; // access flag
 const FLAG_L3_NS: u64 = 1 << 5; // non secure
 
@@ -138,12 +180,171 @@ const FLAG_L3_ATTR_MEM: u64 = 0; // normal memory
 const FLAG_L3_ATTR_DEV: u64 = 1 << 2; // device MMIO
 const FLAG_L3_ATTR_NC: u64 = 2 << 2; // non-cachable
 
-// transition table
+// [1:0]: descriptor type
+const FLAG_TYPE_MASK: u64 = 0b11;
+const FLAG_TYPE_BLOCK: u64 = 0b01; // block, level 1 and 2
+const FLAG_TYPE_TABLE: u64 = 0b11; // next level table, level 0 to 2
+const FLAG_TYPE_PAGE: u64 = 0b11; // page, level 3
+
+// [47:12]: output address
+const DESC_ADDR_MASK: u64 = 0x0000_FFFF_FFFF_F000;
+
+/// translation granule
+#[derive(Copy, Clone, PartialEq)]
+pub enum Granule {
+    Size4KiB,
+    Size16KiB,
+    Size64KiB,
+}
+
+impl Granule {
+    /// bits of the offset in a page
+    pub fn shift(&self) -> u64 {
+        match self {
+            Granule::Size4KiB => 12,
+            Granule::Size16KiB => 14,
+            Granule::Size64KiB => 16,
+        }
+    }
+
+    /// bytes of a page, a translation table has the same size
+    pub fn size(&self) -> u64 {
+        1 << self.shift()
+    }
+
+    /// bits of a virtual address resolved by a level
+    fn bits_per_level(&self) -> u64 {
+        self.shift() - 3
+    }
+
+    /// TG0 of TCR_ELx
+    fn tg0(&self) -> u64 {
+        match self {
+            Granule::Size4KiB => 0b00,
+            Granule::Size16KiB => 0b10,
+            Granule::Size64KiB => 0b01,
+        }
+    }
+
+    /// TG1 of TCR_EL1
+    fn tg1(&self) -> u64 {
+        match self {
+            Granule::Size4KiB => 0b10,
+            Granule::Size16KiB => 0b01,
+            Granule::Size64KiB => 0b11,
+        }
+    }
+
+    /// block descriptors are available at
+    /// 4KiB granule: level 1 (1GiB) and level 2 (2MiB)
+    /// 16KiB granule: level 2 (32MiB)
+    /// 64KiB granule: level 2 (512MiB)
+    fn has_block(&self, level: u64) -> bool {
+        match self {
+            Granule::Size4KiB => level == 1 || level == 2,
+            _ => level == 2,
+        }
+    }
+
+    /// check ID_AA64MMFR0_EL1 whether the CPU supports this granule
+    fn is_supported(&self) -> bool {
+        let mmfr = cpu::id_aa64mmfr0_el1::get();
+        match self {
+            Granule::Size4KiB => {
+                (mmfr >> cpu::ID_AA64MMFR0_EL1_TGRAN4_SHIFT) & cpu::ID_AA64MMFR0_EL1_TGRAN4_MASK
+                    != cpu::ID_AA64MMFR0_EL1_TGRAN4_NOT_SUPPORTED
+            }
+            Granule::Size16KiB => {
+                (mmfr >> cpu::ID_AA64MMFR0_EL1_TGRAN16_SHIFT) & cpu::ID_AA64MMFR0_EL1_TGRAN16_MASK
+                    != cpu::ID_AA64MMFR0_EL1_TGRAN16_NOT_SUPPORTED
+            }
+            Granule::Size64KiB => {
+                (mmfr >> cpu::ID_AA64MMFR0_EL1_TGRAN64_SHIFT) & cpu::ID_AA64MMFR0_EL1_TGRAN64_MASK
+                    != cpu::ID_AA64MMFR0_EL1_TGRAN64_NOT_SUPPORTED
+            }
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            Granule::Size4KiB => "4KiB",
+            Granule::Size16KiB => "16KiB",
+            Granule::Size64KiB => "64KiB",
+        }
+    }
+}
+
+/// pages for translation tables,
+/// a table is taken from here when it is required
+pub struct TablePool {
+    start: u64,
+    end: u64,
+    next: u64,   // never used pages start from here
+    free: u64,   // list of released pages, the first 8 bytes point the next
+    size: u64,   // bytes of a table
+    offset: u64, // added to a physical address to access a table
+}
+
+impl TablePool {
+    pub const fn new(start: u64, end: u64, size: u64, offset: u64) -> TablePool {
+        TablePool {
+            start: start,
+            end: end,
+            next: start,
+            free: 0,
+            size: size,
+            offset: offset,
+        }
+    }
+
+    /// allocate a zero cleared table
+    fn alloc(&mut self) -> Option<u64> {
+        let addr = if self.free != 0 {
+            let addr = self.free;
+            self.free = unsafe { *((addr + self.offset) as *const u64) };
+            addr
+        } else if self.next + self.size <= self.end {
+            let addr = self.next;
+            self.next += self.size;
+            addr
+        } else {
+            return None;
+        };
+
+        let len = (self.size >> 3) as usize;
+        let table = unsafe { slice::from_raw_parts_mut((addr + self.offset) as *mut u64, len) };
+        for e in table.iter_mut() {
+            *e = 0;
+        }
+
+        Some(addr)
+    }
+
+    fn free(&mut self, addr: u64) {
+        if addr < self.start || addr >= self.next || (addr - self.start) % self.size != 0 {
+            panic!("freed invalid translation table");
+        }
+
+        unsafe { *((addr + self.offset) as *mut u64) = self.free };
+        self.free = addr;
+    }
+}
+
+/// translation table
+///
+/// ```ignore
+/// // 64KiB granule, 42 bits space (level 2 and 3 translation tables)
+/// let mut table = TTable::new(start, end, Granule::Size64KiB, 42);
+/// table.map(vm_addr, phy_addr, flag); // map a page
+/// table.map_range(vm_addr, phy_addr, size, flag); // use block descriptors if possible
+/// ```
 pub struct TTable {
-    tt_lv2: &'static mut [u64],
-    tt_lv3: &'static mut [u64],
-    num_lv2: usize,
-    num_lv3: usize,
+    root: u64,
+    granule: Granule,
+    va_bits: u64,
+    start_level: u64,
+    pool: TablePool,
+    stage2: bool, // descriptors have the stage 2 format
 }
 
 pub struct VMTables {
@@ -162,6 +363,8 @@ pub struct Addr {
     pub tt_el1_ttbr0_end: u64,
     pub tt_el1_ttbr1_start: u64,
     pub tt_el1_ttbr1_end: u64,
+    pub tt_stage2_start: u64,
+    pub tt_stage2_end: u64,
     pub rom_start: u64,
     pub rom_end: u64,
     pub sram_start: u64,
@@ -174,8 +377,22 @@ pub struct Addr {
     pub stack_el1_start: u64,
     pub stack_el0_end: u64,
     pub stack_el0_start: u64,
+    pub heap_firm_start: u64,
+    pub heap_firm_end: u64,
+    pub heap_el1_start: u64,
+    pub heap_el1_end: u64,
+    pub stack_pool_start: u64,
+    pub stack_pool_end: u64,
+    pub guest_image_start: u64,
+    pub guest_image_end: u64,
+    pub el0_image_start: u64,
+    pub el0_image_end: u64,
     pub el0_heap_start: u64,
     pub el0_heap_end: u64,
+
+    // DRAM containing the firmware, discovered at boot time
+    pub dram_start: u64,
+    pub dram_end: u64,
 }
 
 impl Addr {
@@ -187,31 +404,74 @@ impl Addr {
         self.tt_firm_start = self.no_cache_end;
         self.tt_firm_end = self.tt_firm_start + PAGESIZE * FIRM_TABLE_NUM as u64;
 
-        // MMU's transition table #0 for EL1
+        // MMU's transition table #0 for EL1, for every user address space and EL1 itself,
+        // EL1's table is the last one, see EL1_ASID
         self.tt_el1_ttbr0_start = self.tt_firm_end;
-        self.tt_el1_ttbr0_end = self.tt_el1_ttbr0_start + PAGESIZE * KERN_TTBR0_TABLE_NUM as u64;
+        self.tt_el1_ttbr0_end = self.tt_el1_ttbr0_start
+            + PAGESIZE * KERN_TTBR0_TABLE_NUM as u64 * (MAX_ADDR_SPACE as u64 + 1);
 
-        // MMU's transition table #1 for EL1
-        // level 2 table x 1 (for 4TiB space)
-        // level 3 table x 1 (for 512MiB space)
+        // MMU's transition table #1 for EL1, see KERN_TTBR1_TABLE_NUM
         self.tt_el1_ttbr1_start = self.tt_el1_ttbr0_end;
         self.tt_el1_ttbr1_end = self.tt_el1_ttbr1_start + PAGESIZE * KERN_TTBR1_TABLE_NUM as u64;
 
-        // 2MiB stack for each
+        // MMU's stage 2 transition table for a guest of the hypervisor
+        self.tt_stage2_start = self.tt_el1_ttbr1_end;
+        self.tt_stage2_end = self.tt_stage2_start + PAGESIZE * STAGE2_TABLE_NUM as u64;
+
+        // 32 pages of stack for each, 2MiB with the 64KiB granule
         self.stack_size = 32 * PAGESIZE;
         let stack_size_total = self.stack_size * NUM_CPU;
 
         // EL1's stack
-        self.stack_el1_end = self.tt_el1_ttbr1_end;
+        self.stack_el1_end = self.tt_stage2_end;
         self.stack_el1_start = self.stack_el1_end + stack_size_total;
 
         // EL0's stack
         self.stack_el0_end = self.stack_el1_start;
         self.stack_el0_start = self.stack_el0_end + stack_size_total;
 
-        // heap memory for EL0
-        self.el0_heap_start = self.stack_el0_start;
-        self.el0_heap_end = self.el0_heap_start + PAGESIZE * 1024; // 64MiB
+        // DRAM
+        let (dram_start, dram_end) = get_dram_range();
+        self.dram_start = dram_start;
+        self.dram_end = dram_end;
+
+        // 64 pages of secure heap for EL3 (or EL2 without EL3), mapped only by the firmware's table
+        self.heap_firm_start = self.stack_el0_start;
+        self.heap_firm_end = self.heap_firm_start + 64 * PAGESIZE;
+
+        // 128 pages of heap for EL1, mapped only by TTBR1
+        self.heap_el1_start = self.heap_firm_end;
+        self.heap_el1_end = self.heap_el1_start + 128 * PAGESIZE;
+
+        // 64 pages for stacks of kernel threads, mapped only by TTBR1
+        self.stack_pool_start = self.heap_el1_end;
+        self.stack_pool_end = self.stack_pool_start + 64 * PAGESIZE;
+
+        // a copy of the image for the guest of the hypervisor, see init_stage2
+        self.guest_image_start = self.stack_pool_end;
+        self.guest_image_end = self.guest_image_start;
+        if cfg!(feature = "hypervisor") {
+            self.guest_image_end += get_stack_firm_end() - get_ram_start();
+        }
+
+        // EL0's copy of .data and .bss, see init_el0_image
+        self.el0_image_start = self.guest_image_end;
+        self.el0_image_end = self.el0_image_start + get_stack_firm_end() - get_data_start();
+
+        // heap memory for EL0, the rest of DRAM
+        self.el0_heap_start = self.el0_image_end;
+        self.el0_heap_end = self.dram_end;
+        if self.el0_heap_end <= self.el0_heap_start {
+            panic!("no memory for heap");
+        }
+
+        // DRAM above a hole, used for the heap of EL0 instead if it is larger
+        if let Some((start, end)) = get_high_dram_range() {
+            if end - start > self.el0_heap_end - self.el0_heap_start {
+                self.el0_heap_start = start;
+                self.el0_heap_end = end;
+            }
+        }
 
         // ROM
         self.rom_start = ROM_START;
@@ -309,6 +569,14 @@ impl Addr {
         driver::uart::hex(self.tt_el1_ttbr1_end as u64);
         driver::uart::puts("\n");
 
+        driver::uart::puts("tt_stage2_start    = 0x");
+        driver::uart::hex(self.tt_stage2_start as u64);
+        driver::uart::puts("\n");
+
+        driver::uart::puts("tt_stage2_end      = 0x");
+        driver::uart::hex(self.tt_stage2_end as u64);
+        driver::uart::puts("\n");
+
         driver::uart::puts("stack_el1_end      = 0x");
         driver::uart::hex(self.stack_el1_end as u64);
         driver::uart::puts("\n");
@@ -325,6 +593,46 @@ impl Addr {
         driver::uart::hex(self.stack_el0_start as u64);
         driver::uart::puts("\n");
 
+        driver::uart::puts("heap_firm_start    = 0x");
+        driver::uart::hex(self.heap_firm_start as u64);
+        driver::uart::puts("\n");
+
+        driver::uart::puts("heap_firm_end      = 0x");
+        driver::uart::hex(self.heap_firm_end as u64);
+        driver::uart::puts("\n");
+
+        driver::uart::puts("heap_el1_start     = 0x");
+        driver::uart::hex(self.heap_el1_start as u64);
+        driver::uart::puts("\n");
+
+        driver::uart::puts("heap_el1_end       = 0x");
+        driver::uart::hex(self.heap_el1_end as u64);
+        driver::uart::puts("\n");
+
+        driver::uart::puts("stack_pool_start   = 0x");
+        driver::uart::hex(self.stack_pool_start as u64);
+        driver::uart::puts("\n");
+
+        driver::uart::puts("stack_pool_end     = 0x");
+        driver::uart::hex(self.stack_pool_end as u64);
+        driver::uart::puts("\n");
+
+        driver::uart::puts("guest_image_start  = 0x");
+        driver::uart::hex(self.guest_image_start as u64);
+        driver::uart::puts("\n");
+
+        driver::uart::puts("guest_image_end    = 0x");
+        driver::uart::hex(self.guest_image_end as u64);
+        driver::uart::puts("\n");
+
+        driver::uart::puts("el0_image_start    = 0x");
+        driver::uart::hex(self.el0_image_start as u64);
+        driver::uart::puts("\n");
+
+        driver::uart::puts("el0_image_end      = 0x");
+        driver::uart::hex(self.el0_image_end as u64);
+        driver::uart::puts("\n");
+
         driver::uart::puts("el0_heap_start     = 0x");
         driver::uart::hex(self.el0_heap_start as u64);
         driver::uart::puts("\n");
@@ -332,6 +640,49 @@ impl Addr {
         driver::uart::puts("el0_heap_end       = 0x");
         driver::uart::hex(self.el0_heap_end as u64);
         driver::uart::puts("\n");
+
+        driver::uart::puts("dram_start         = 0x");
+        driver::uart::hex(self.dram_start as u64);
+        driver::uart::puts("\n");
+
+        driver::uart::puts("dram_end           = 0x");
+        driver::uart::hex(self.dram_end as u64);
+        driver::uart::puts("\n");
+    }
+}
+
+/// get the range of DRAM containing the firmware,
+/// the /memory node of the device tree blob is preferred if it is passed
+fn get_dram_range() -> (u64, u64) {
+    let ram_start = get_ram_start();
+    let (base, size) = match driver::dtb::get_memory(ram_start) {
+        Some(region) => region,
+        None => driver::memory::get_dram(),
+    };
+
+    // memory mapped devices may overlap the top of DRAM
+    let mut end = base + size;
+    if ram_start < DEVICE_MEM_START && DEVICE_MEM_START < end {
+        end = DEVICE_MEM_START;
+    }
+
+    (base, end & !(PAGESIZE - 1))
+}
+
+fn get_high_dram_range() -> Option<(u64, u64)> {
+    let (base, size) = driver::memory::get_high_dram()?;
+
+    let mut end = base + size;
+    if base < DEVICE_MEM_START && DEVICE_MEM_START < end {
+        end = DEVICE_MEM_START;
+    }
+
+    let start = (base + PAGESIZE - 1) & !(PAGESIZE - 1);
+    let end = end & !(PAGESIZE - 1);
+    if start < end {
+        Some((start, end))
+    } else {
+        None
     }
 }
 
@@ -348,64 +699,366 @@ pub fn get_memory_map() -> &'static Addr {
     }
 }
 
+/// whether the current code runs on EL0's stack, or a stack of an EL0 task in EL0's heap,
+/// used instead of CurrentEL, which EL0 cannot read
+pub fn is_el0() -> bool {
+    let addr = get_memory_map();
+    let sp = cpu::get_sp();
+    (addr.stack_el0_end <= sp && sp < addr.stack_el0_start)
+        || (addr.el0_heap_start < sp && sp <= addr.el0_heap_end)
+}
+
+/// no page is allowed to be writable and executable at the same time
+fn check_wx(vm_addr: u64, flag: u64) {
+    let writable = flag & FLAG_L3_SH_R_N == 0; // AP[2] is 0
+    let executable = flag & FLAG_L3_XN == 0 || flag & FLAG_L3_PXN == 0;
+    if writable && executable {
+        driver::uart::puts("writable and executable mapping: 0x");
+        driver::uart::hex(vm_addr);
+        driver::uart::puts("\n");
+        panic!("W^X violation");
+    }
+}
+
+/// the level of the root table to resolve va_bits
+fn get_start_level(granule: Granule, va_bits: u64) -> u64 {
+    let bits = granule.bits_per_level();
+    let levels = (va_bits - granule.shift() + bits - 1) / bits;
+    if levels == 0 || levels > 4 {
+        panic!("unsupported virtual address space");
+    }
+    4 - levels
+}
+
 impl TTable {
-    fn new(tt_addr: u64, num_lv2: usize, num_lv3: usize) -> TTable {
-        let ptr = tt_addr as *mut u64;
-        let tt_lv2 = unsafe { slice::from_raw_parts_mut(ptr, 8192 * num_lv2) };
+    fn new(pool_start: u64, pool_end: u64, granule: Granule, va_bits: u64) -> TTable {
+        TTable::with_offset(pool_start, pool_end, granule, va_bits, 0)
+    }
 
-        let ptr = ((PAGESIZE * num_lv2 as u64) + tt_addr) as *mut u64;
-        let tt_lv3 = unsafe { slice::from_raw_parts_mut(ptr, 8192 * num_lv3) };
+    /// tables are accessed through physical address + offset,
+    /// offset must be 0 if the MMU is disabled
+    fn with_offset(
+        pool_start: u64,
+        pool_end: u64,
+        granule: Granule,
+        va_bits: u64,
+        offset: u64,
+    ) -> TTable {
+        let start_level = get_start_level(granule, va_bits);
+        let mut pool = TablePool::new(pool_start, pool_end, granule.size(), offset);
+        let root = match pool.alloc() {
+            Some(addr) => addr,
+            None => panic!("no space for translation table"),
+        };
 
-        // initialize
-        for e in tt_lv2.iter_mut() {
-            *e = 0;
+        TTable {
+            root: root,
+            granule: granule,
+            va_bits: va_bits,
+            start_level: start_level,
+            pool: pool,
+            stage2: false,
+        }
+    }
+
+    /// a read only view of an existing table, no table can be allocated
+    fn from_root(root: u64, granule: Granule, va_bits: u64, offset: u64) -> TTable {
+        TTable {
+            root: root,
+            granule: granule,
+            va_bits: va_bits,
+            start_level: get_start_level(granule, va_bits),
+            pool: TablePool::new(0, 0, granule.size(), offset),
+            stage2: false,
         }
+    }
 
-        for e in tt_lv3.iter_mut() {
-            *e = 0;
+    /// physical address of the root table, for TTBR
+    pub fn root(&self) -> u64 {
+        self.root
+    }
+
+    pub fn granule(&self) -> Granule {
+        self.granule
+    }
+
+    /// bits to shift a virtual address to get the index of the level
+    fn level_shift(&self, level: u64) -> u64 {
+        self.granule.shift() + self.granule.bits_per_level() * (3 - level)
+    }
+
+    /// index of the entry for vm_addr at the level
+    fn index(&self, vm_addr: u64, level: u64) -> usize {
+        let shift = self.level_shift(level);
+        let bits = if level == self.start_level {
+            self.va_bits - shift
+        } else {
+            self.granule.bits_per_level()
+        };
+        ((vm_addr >> shift) & ((1 << bits) - 1)) as usize
+    }
+
+    fn get_table(&self, addr: u64) -> &'static mut [u64] {
+        let len = (self.granule.size() >> 3) as usize;
+        unsafe { slice::from_raw_parts_mut((addr + self.pool.offset) as *mut u64, len) }
+    }
+
+    fn next_table(&self, desc: u64) -> &'static mut [u64] {
+        self.get_table(desc & DESC_ADDR_MASK & !(self.granule.size() - 1))
+    }
+
+    fn alloc_table(&mut self) -> u64 {
+        match self.pool.alloc() {
+            Some(addr) => addr,
+            None => panic!("no space for translation table"),
         }
+    }
 
-        // set up level 2 tables
-        for i in 0..(8192 * num_lv2) {
-            if i >= num_lv3 {
-                break;
+    /// release a table at the level and its descendants to the pool
+    fn free_table(&mut self, addr: u64, level: u64) {
+        if level < 3 {
+            for e in self.get_table(addr).iter() {
+                if *e & FLAG_TYPE_MASK == FLAG_TYPE_TABLE {
+                    let next = *e & DESC_ADDR_MASK & !(self.granule.size() - 1);
+                    self.free_table(next, level + 1);
+                }
             }
-            tt_lv2[i] = (&tt_lv3[i * 8192] as *const u64) as u64 | 0b11;
         }
+        self.pool.free(addr);
+    }
 
-        TTable {
-            tt_lv2: tt_lv2,
-            tt_lv3: tt_lv3,
-            num_lv2: num_lv2,
-            num_lv3: num_lv3,
+    /// divide the block of the entry at the level into a next level table,
+    /// every entry of the table inherits the attributes of the block
+    ///
+    /// The entry is replaced by break-before-make, so the block must not contain
+    /// the running code nor the stack if the table is in use.
+    fn split_block(&mut self, entry: &mut u64, level: u64) {
+        let desc = *entry;
+        let addr = self.alloc_table();
+
+        let base = desc & DESC_ADDR_MASK & !((1 << self.level_shift(level)) - 1);
+        let size = 1 << self.level_shift(level + 1);
+        let attr = desc & !DESC_ADDR_MASK & !FLAG_TYPE_MASK;
+        let ty = if level + 1 == 3 {
+            FLAG_TYPE_PAGE
+        } else {
+            FLAG_TYPE_BLOCK
+        };
+
+        let mut phy_addr = base;
+        for e in self.get_table(addr).iter_mut() {
+            *e = phy_addr | attr | ty;
+            phy_addr += size;
+        }
+
+        *entry = 0;
+        self.flush_tlb();
+        *entry = addr | FLAG_TYPE_TABLE;
+        cpu::dsb_ishst();
+        cpu::isb();
+    }
+
+    /// invalidate TLB entries of the regime of the table, on every core
+    fn flush_tlb(&self) {
+        cpu::dsb_ishst();
+        if self.stage2 {
+            cpu::tlbi_vmalls12e1is();
+        } else {
+            match cpu::get_current_el() {
+                3 => cpu::tlbi_alle3is(),
+                2 => cpu::tlbi_alle2is(),
+                _ => cpu::tlbi_vmalle1is(),
+            }
+        }
+        cpu::dsb_ish();
+        cpu::isb();
+    }
+
+    /// get the entry for vm_addr at the level,
+    /// missing tables are allocated and blocks on the way are divided
+    fn get_entry(&mut self, vm_addr: u64, level: u64) -> &'static mut u64 {
+        let mut table = self.get_table(self.root);
+        for lv in self.start_level..level {
+            let idx = self.index(vm_addr, lv);
+            let desc = table[idx];
+            if desc & 1 == 0 {
+                table[idx] = self.alloc_table() | FLAG_TYPE_TABLE;
+            } else if desc & FLAG_TYPE_MASK == FLAG_TYPE_BLOCK {
+                self.split_block(&mut table[idx], lv);
+            }
+            table = self.next_table(table[idx]);
         }
+
+        let idx = self.index(vm_addr, level);
+        &mut table[idx]
     }
 
+    /// map a page
     fn map(&mut self, vm_addr: u64, phy_addr: u64, flag: u64) {
-        let lv2idx = ((vm_addr >> 29) & 8191) as usize;
-        let lv3idx = ((vm_addr >> 16) & 8191) as usize;
+        if !self.stage2 {
+            check_wx(vm_addr, flag);
+        }
+        let mask = !(self.granule.size() - 1);
+        let e = self.get_entry(vm_addr, 3);
+        *e = phy_addr & DESC_ADDR_MASK & mask | flag;
+    }
 
-        if lv2idx >= self.num_lv3 {
-            // memory access error
+    /// map a block at the level
+    fn map_block(&mut self, vm_addr: u64, phy_addr: u64, level: u64, flag: u64) {
+        let mask = (1 << self.level_shift(level)) - 1;
+        if !self.granule.has_block(level) || level < self.start_level || vm_addr & mask != 0 {
             panic!("memory map error");
         }
 
-        let e = phy_addr & !((1 << 16) - 1) | flag;
-        let idx = lv2idx * 8192 + lv3idx;
-        self.tt_lv3[idx] = e as u64;
+        if !self.stage2 {
+            check_wx(vm_addr, flag);
+        }
+
+        let e = self.get_entry(vm_addr, level);
+        if *e & FLAG_TYPE_MASK == FLAG_TYPE_TABLE {
+            let table = *e & DESC_ADDR_MASK & !(self.granule.size() - 1);
+            self.free_table(table, level + 1);
+        }
+
+        *e = phy_addr & DESC_ADDR_MASK & !mask | (flag & !FLAG_TYPE_MASK) | FLAG_TYPE_BLOCK;
     }
 
-    fn unmap(&mut self, vm_addr: u64) {
-        let lv2idx = ((vm_addr >> 29) & 8191) as usize;
-        let lv3idx = ((vm_addr >> 16) & 8191) as usize;
+    /// map [vm_addr, vm_addr + size) to [phy_addr, phy_addr + size),
+    /// the largest blocks are used where both addresses are aligned
+    fn map_range(&mut self, vm_addr: u64, phy_addr: u64, size: u64, flag: u64) {
+        let mut offset = 0;
+        while offset < size {
+            let vm = vm_addr + offset;
+            let phy = phy_addr + offset;
+
+            let mut bytes = 0;
+            for level in self.start_level..3 {
+                let n = 1 << self.level_shift(level);
+                if self.granule.has_block(level)
+                    && vm & (n - 1) == 0
+                    && phy & (n - 1) == 0
+                    && size - offset >= n
+                {
+                    self.map_block(vm, phy, level, flag);
+                    bytes = n;
+                    break;
+                }
+            }
+
+            if bytes == 0 {
+                self.map(vm, phy, flag);
+                bytes = self.granule.size();
+            }
+
+            offset += bytes;
+        }
+    }
+
+    /// unmap pages of [vm_addr, vm_addr + size)
+    fn unmap_range(&mut self, vm_addr: u64, size: u64) {
+        let page = self.granule.size();
+        let mut addr = vm_addr & !(page - 1);
+        while addr < vm_addr + size {
+            self.unmap(addr);
+            addr += page;
+        }
+    }
+
+    /// copy every mapping of src, tables are taken from the own pool
+    fn copy_from(&mut self, src: &TTable) {
+        if self.granule != src.granule || self.va_bits != src.va_bits {
+            panic!("copied incompatible translation table");
+        }
+
+        let root = self.root;
+        self.copy_table(src, src.root, root, src.start_level);
+    }
+
+    fn copy_table(&mut self, src: &TTable, src_addr: u64, dst_addr: u64, level: u64) {
+        let dst = self.get_table(dst_addr);
+        for (s, d) in src.get_table(src_addr).iter().zip(dst.iter_mut()) {
+            if level < 3 && *s & FLAG_TYPE_MASK == FLAG_TYPE_TABLE {
+                let next = self.alloc_table();
+                let src_next = *s & DESC_ADDR_MASK & !(self.granule.size() - 1);
+                self.copy_table(src, src_next, next, level + 1);
+                *d = next | (*s & !DESC_ADDR_MASK);
+            } else {
+                *d = *s;
+            }
+        }
+    }
+
+    /// walk the table without modification,
+    /// return (physical address, descriptor, level) if vm_addr is mapped
+    fn translate(&self, vm_addr: u64) -> Option<(u64, u64, u64)> {
+        let mut table = self.get_table(self.root);
+        for level in self.start_level..4 {
+            let desc = table[self.index(vm_addr, level)];
+            if desc & 1 == 0 {
+                return None;
+            }
+
+            if level == 3 || desc & FLAG_TYPE_MASK == FLAG_TYPE_BLOCK {
+                if level == 3 && desc & FLAG_TYPE_MASK != FLAG_TYPE_PAGE {
+                    return None; // reserved
+                }
+
+                let mask = (1 << self.level_shift(level)) - 1;
+                let phy_addr = desc & DESC_ADDR_MASK & !mask | vm_addr & mask;
+                return Some((phy_addr, desc, level));
+            }
+
+            table = self.next_table(desc);
+        }
+
+        None
+    }
+
+    /// call f(vm_addr, phy_addr, size, desc) for every page and block in address order
+    fn walk<F: FnMut(u64, u64, u64, u64)>(&self, f: &mut F) {
+        self.walk_table(self.root, self.start_level, 0, f);
+    }
+
+    fn walk_table<F: FnMut(u64, u64, u64, u64)>(
+        &self,
+        addr: u64,
+        level: u64,
+        vm_base: u64,
+        f: &mut F,
+    ) {
+        let size = 1 << self.level_shift(level);
+        for (i, desc) in self.get_table(addr).iter().enumerate() {
+            let vm_addr = vm_base + i as u64 * size;
+            if *desc & 1 == 0 {
+                continue;
+            }
+
+            if level < 3 && *desc & FLAG_TYPE_MASK == FLAG_TYPE_TABLE {
+                let next = *desc & DESC_ADDR_MASK & !(self.granule.size() - 1);
+                self.walk_table(next, level + 1, vm_addr, f);
+            } else if level < 3 || *desc & FLAG_TYPE_MASK == FLAG_TYPE_PAGE {
+                f(vm_addr, *desc & DESC_ADDR_MASK & !(size - 1), size, *desc);
+            }
+        }
+    }
 
-        if lv2idx >= self.num_lv3 {
-            // memory access error
-            panic!("memory unmap error");
+    /// unmap a page, a block including vm_addr is divided
+    fn unmap(&mut self, vm_addr: u64) {
+        let mut table = self.get_table(self.root);
+        for level in self.start_level..3 {
+            let idx = self.index(vm_addr, level);
+            let desc = table[idx];
+            if desc & 1 == 0 {
+                // not mapped
+                return;
+            } else if desc & FLAG_TYPE_MASK == FLAG_TYPE_BLOCK {
+                self.split_block(&mut table[idx], level);
+            }
+            table = self.next_table(table[idx]);
         }
 
-        let idx = lv2idx * 8192 + lv3idx;
-        self.tt_lv3[idx] = 0;
+        let idx = self.index(vm_addr, 3);
+        table[idx] = 0;
     }
 }
 
@@ -462,10 +1115,8 @@ pub fn set_regs() {
         set_reg_el3(addr.tt_firm_start as usize);
     };
 
-    set_reg_el1(
-        addr.tt_el1_ttbr0_start as usize,
-        addr.tt_el1_ttbr1_start as usize,
-    );
+    let (tt_start, _) = get_ttbr0_pool(EL1_ASID);
+    set_reg_el1(tt_start as usize, addr.tt_el1_ttbr1_start as usize);
 }
 
 /// initialize transition tables
@@ -474,7 +1125,7 @@ pub fn init() -> Option<(TTable, (TTable, TTable))> {
 
     addr.print();
 
-    // check for 64KiB granule and at least 36 bits physical address bus
+    // check for the granule and at least 36 bits physical address bus
     let mut mmfr: u64;
     unsafe { llvm_asm!("mrs $0, id_aa64mmfr0_el1" : "=r" (mmfr)) };
     let b = mmfr & 0xF;
@@ -485,10 +1136,10 @@ pub fn init() -> Option<(TTable, (TTable, TTable))> {
         return None;
     }
 
-    if mmfr & (0xF << 24) != 0
-    /* 64KiB */
-    {
-        driver::uart::puts("ERROR: 64KiB granule not supported\n");
+    if !GRANULE.is_supported() {
+        driver::uart::puts("ERROR: ");
+        driver::uart::puts(GRANULE.name());
+        driver::uart::puts(" granule not supported\n");
         return None;
     }
 
@@ -518,11 +1169,11 @@ fn get_tcr() -> u64 {
     1 << 31 | // Res1
     1 << 23 | // Res1
     b << 16 |
-    1 << 14 | // 64KiB granule
+    GRANULE.tg0() << 14 |
     3 << 12 | // inner shadable
     1 << 10 | // Normal memory, Outer Write-Back Read-Allocate Write-Allocate Cacheable.
     1 <<  8 | // Normal memory, Inner Write-Back Read-Allocate Write-Allocate Cacheable.
-    22 // T0SZ = 22, 2 levels (level 2 and 3 translation tables), 2^42B (4TiB) space
+    (64 - VA_BITS) // T0SZ, 2^VA_BITS bytes space
 }
 
 fn update_sctlr(sctlr: u64) -> u64 {
@@ -542,39 +1193,36 @@ fn update_sctlr(sctlr: u64) -> u64 {
 }
 
 fn init_firm(addr: &Addr) -> TTable {
-    let mut table = TTable::new(addr.tt_firm_start, FIRM_LV2_TABLE_NUM, FIRM_LV3_TABLE_NUM);
+    let mut table = TTable::new(addr.tt_firm_start, addr.tt_firm_end, GRANULE, VA_BITS);
 
     // map ROM
     if addr.rom_start != addr.rom_end {
-        let mut rom_start = addr.rom_start;
+        let rom_start = addr.rom_start;
         let flag = FLAG_L3_AF | FLAG_L3_ISH | FLAG_L3_SH_R_N | FLAG_L3_ATTR_MEM | 0b11;
-        while rom_start < addr.rom_end {
-            table.map(rom_start, rom_start, flag);
-            rom_start += PAGESIZE;
-        }
+        table.map_range(rom_start, rom_start, addr.rom_end - rom_start, flag);
     }
 
     // map SRAM
     if addr.sram_start != addr.sram_end {
-        let mut sram_start = addr.sram_start;
-        let flag = FLAG_L3_AF | FLAG_L3_ISH | FLAG_L3_SH_RW_N | FLAG_L3_ATTR_MEM | 0b11;
-        while sram_start < addr.sram_end {
-            table.map(sram_start, sram_start, flag);
-            sram_start += PAGESIZE;
-        }
+        let sram_start = addr.sram_start;
+        let flag = FLAG_L3_XN
+            | FLAG_L3_PXN
+            | FLAG_L3_AF
+            | FLAG_L3_ISH
+            | FLAG_L3_SH_RW_N
+            | FLAG_L3_ATTR_MEM
+            | 0b11;
+        table.map_range(sram_start, sram_start, addr.sram_end - sram_start, flag);
     }
 
     // map .init and .text section
-    let mut ram_start = get_ram_start();
+    let ram_start = get_ram_start();
     let data_start = get_data_start();
     let flag = FLAG_L3_AF | FLAG_L3_ISH | FLAG_L3_SH_R_R | FLAG_L3_ATTR_MEM | 0b11;
-    while ram_start < data_start {
-        table.map(ram_start, ram_start, flag);
-        ram_start += PAGESIZE;
-    }
+    table.map_range(ram_start, ram_start, data_start - ram_start, flag);
 
     // map .data
-    let mut data_start = get_data_start();
+    let data_start = get_data_start();
     let bss_start = get_bss_start();
     let flag = FLAG_L3_XN
         | FLAG_L3_PXN
@@ -583,13 +1231,10 @@ fn init_firm(addr: &Addr) -> TTable {
         | FLAG_L3_SH_RW_RW
         | FLAG_L3_ATTR_MEM
         | 0b11;
-    while data_start < bss_start {
-        table.map(data_start, data_start, flag);
-        data_start += PAGESIZE;
-    }
+    table.map_range(data_start, data_start, bss_start - data_start, flag);
 
     // map .bss section
-    let mut bss_start = get_bss_start();
+    let bss_start = get_bss_start();
     let end = get_stack_firm_end();
     let flag = FLAG_L3_XN
         | FLAG_L3_PXN
@@ -598,13 +1243,10 @@ fn init_firm(addr: &Addr) -> TTable {
         | FLAG_L3_SH_RW_RW
         | FLAG_L3_ATTR_MEM
         | 0b11;
-    while bss_start < end {
-        table.map(bss_start, bss_start, flag);
-        bss_start += PAGESIZE;
-    }
+    table.map_range(bss_start, bss_start, end - bss_start, flag);
 
     // map firmware stack
-    let mut stack_end = get_stack_firm_end();
+    let stack_end = get_stack_firm_end();
     let stack_start = get_stack_firm_start();
     let flag = FLAG_L3_XN
         | FLAG_L3_PXN
@@ -613,10 +1255,7 @@ fn init_firm(addr: &Addr) -> TTable {
         | FLAG_L3_SH_RW_N
         | FLAG_L3_ATTR_MEM
         | 0b11;
-    while stack_end < stack_start {
-        table.map(stack_end, stack_end, flag);
-        stack_end += PAGESIZE;
-    }
+    table.map_range(stack_end, stack_end, stack_start - stack_end, flag);
 
     for i in 0..NUM_CPU {
         let stack_end = get_stack_firm_end();
@@ -624,8 +1263,24 @@ fn init_firm(addr: &Addr) -> TTable {
         table.unmap(addr);
     }
 
+    // map the heap of the firmware, NS is not set so it is secure memory
+    let heap_start = addr.heap_firm_start;
+    let flag = FLAG_L3_XN
+        | FLAG_L3_PXN
+        | FLAG_L3_AF
+        | FLAG_L3_ISH
+        | FLAG_L3_SH_RW_N
+        | FLAG_L3_ATTR_MEM
+        | 0b11;
+    table.map_range(
+        heap_start,
+        heap_start,
+        addr.heap_firm_end - heap_start,
+        flag,
+    );
+
     // map non cached memory
-    let mut no_cache_start = addr.no_cache_start;
+    let no_cache_start = addr.no_cache_start;
     let flag = FLAG_L3_XN
         | FLAG_L3_PXN
         | FLAG_L3_AF
@@ -633,13 +1288,15 @@ fn init_firm(addr: &Addr) -> TTable {
         | FLAG_L3_SH_RW_N
         | FLAG_L3_ATTR_MEM
         | 0b11;
-    while no_cache_start < addr.no_cache_end {
-        table.map(no_cache_start, no_cache_start, flag);
-        no_cache_start += PAGESIZE;
-    }
+    table.map_range(
+        no_cache_start,
+        no_cache_start,
+        addr.no_cache_end - no_cache_start,
+        flag,
+    );
 
     // map transition table for EL2
-    let mut tt_firm_start = addr.tt_firm_start;
+    let tt_firm_start = addr.tt_firm_start;
     let flag = FLAG_L3_XN
         | FLAG_L3_PXN
         | FLAG_L3_AF
@@ -648,13 +1305,15 @@ fn init_firm(addr: &Addr) -> TTable {
         | FLAG_L3_ATTR_MEM
         | FLAG_L3_ATTR_NC
         | 0b11;
-    while tt_firm_start < addr.tt_firm_end {
-        table.map(tt_firm_start, tt_firm_start, flag);
-        tt_firm_start += PAGESIZE;
-    }
+    table.map_range(
+        tt_firm_start,
+        tt_firm_start,
+        addr.tt_firm_end - tt_firm_start,
+        flag,
+    );
 
     // map transition table for EL1 TTBR0
-    let mut tt_start = addr.tt_el1_ttbr0_start;
+    let tt_start = addr.tt_el1_ttbr0_start;
     let flag = FLAG_L3_XN
         | FLAG_L3_PXN
         | FLAG_L3_AF
@@ -663,13 +1322,10 @@ fn init_firm(addr: &Addr) -> TTable {
         | FLAG_L3_ATTR_MEM
         | FLAG_L3_ATTR_NC
         | 0b11;
-    while tt_start < addr.tt_el1_ttbr0_end {
-        table.map(tt_start, tt_start, flag);
-        tt_start += PAGESIZE;
-    }
+    table.map_range(tt_start, tt_start, addr.tt_el1_ttbr0_end - tt_start, flag);
 
     // map transition table for EL1 TTBR1
-    let mut tt_start = addr.tt_el1_ttbr1_start;
+    let tt_start = addr.tt_el1_ttbr1_start;
     let flag = FLAG_L3_XN
         | FLAG_L3_PXN
         | FLAG_L3_AF
@@ -678,13 +1334,38 @@ fn init_firm(addr: &Addr) -> TTable {
         | FLAG_L3_ATTR_MEM
         | FLAG_L3_ATTR_NC
         | 0b11;
-    while tt_start < addr.tt_el1_ttbr1_end {
-        table.map(tt_start, tt_start, flag);
-        tt_start += PAGESIZE;
-    }
+    table.map_range(tt_start, tt_start, addr.tt_el1_ttbr1_end - tt_start, flag);
+
+    // map stage 2 transition table
+    let tt_start = addr.tt_stage2_start;
+    let flag = FLAG_L3_XN
+        | FLAG_L3_PXN
+        | FLAG_L3_AF
+        | FLAG_L3_ISH
+        | FLAG_L3_SH_RW_N
+        | FLAG_L3_ATTR_MEM
+        | FLAG_L3_ATTR_NC
+        | 0b11;
+    table.map_range(tt_start, tt_start, addr.tt_stage2_end - tt_start, flag);
+
+    // map EL0's copy of .data and .bss to copy them, see init_el0_image
+    let el0_image_start = addr.el0_image_start;
+    let flag = FLAG_L3_XN
+        | FLAG_L3_PXN
+        | FLAG_L3_AF
+        | FLAG_L3_ISH
+        | FLAG_L3_SH_RW_N
+        | FLAG_L3_ATTR_MEM
+        | 0b11;
+    table.map_range(
+        el0_image_start,
+        el0_image_start,
+        addr.el0_image_end - el0_image_start,
+        flag,
+    );
 
     // map device memory
-    let mut device_addr = DEVICE_MEM_START;
+    let device_addr = DEVICE_MEM_START;
     let flag = FLAG_L3_NS
         | FLAG_L3_XN
         | FLAG_L3_PXN
@@ -693,19 +1374,16 @@ fn init_firm(addr: &Addr) -> TTable {
         | FLAG_L3_SH_RW_RW
         | FLAG_L3_ATTR_DEV
         | 0b11;
-    while device_addr < DEVICE_MEM_END {
-        table.map(device_addr, device_addr, flag);
-        device_addr += PAGESIZE;
-    }
+    table.map_range(device_addr, device_addr, DEVICE_MEM_END - device_addr, flag);
 
     table
 }
 
-/// set up EL3's page table, 64KB page, level 2 and 3 translation tables,
+/// set up EL3's page table, see GRANULE and VA_BITS for the format,
 /// assume 2MiB stack space per CPU
 fn init_el3(addr: &Addr) -> TTable {
     let table = init_firm(addr);
-    set_reg_el3(addr.tt_firm_start as usize);
+    set_reg_el3(table.root() as usize);
     table
 }
 
@@ -739,7 +1417,25 @@ fn init_el2(addr: &Addr) -> TTable {
         | 0b11;
     table.map(0, 0, flag);
 
-    set_reg_el2(addr.tt_firm_start as usize);
+    // map the copy of the image for the guest
+    let guest_start = addr.guest_image_start;
+    if guest_start != addr.guest_image_end {
+        let flag = FLAG_L3_XN
+            | FLAG_L3_PXN
+            | FLAG_L3_AF
+            | FLAG_L3_ISH
+            | FLAG_L3_SH_RW_N
+            | FLAG_L3_ATTR_MEM
+            | 0b11;
+        table.map_range(
+            guest_start,
+            guest_start,
+            addr.guest_image_end - guest_start,
+            flag,
+        );
+    }
+
+    set_reg_el2(table.root() as usize);
 
     table
 }
@@ -761,42 +1457,40 @@ fn set_reg_el2(ttbr: usize) {
     unsafe { llvm_asm!("msr sctlr_el2, $0; dsb sy; isb" : : "r" (sctlr)) };
 }
 
-/// set up EL1's page table, 64KB page, level 2 and 3 translation tables,
-/// assume 2MiB stack space per CPU
-fn init_el1(addr: &Addr) -> (TTable, TTable) {
-    // TTBR0: user space
-    let mut table0 = TTable::new(
-        addr.tt_el1_ttbr0_start,
-        KERN_TTBR0_LV2_TABLE_NUM,
-        KERN_TTBR0_LV3_TABLE_NUM,
-    );
+/// map the firmware image and device memory for EL0,
+/// every user address space has these mappings
+///
+/// EL1 executes only the trampoline through them, and .data and .bss are mapped to EL0's copy,
+/// so EL0 can neither read nor write the statics of EL1
+fn map_el0_image(table: &mut TTable) {
+    let addr = get_memory_map();
 
-    // map .init and .text section
-    let mut ram_start = get_ram_start();
+    // map .init, .text and .rodata sections except the trampoline
+    let ram_start = get_ram_start();
+    let tramp_start = get_trampoline_start();
+    let tramp_end = get_trampoline_end();
     let data_start = get_data_start();
-    let flag = FLAG_L3_AF | FLAG_L3_ISH | FLAG_L3_SH_R_R | FLAG_L3_ATTR_MEM | 0b11;
-    while ram_start < data_start {
-        table0.map(ram_start, ram_start, flag);
-        ram_start += PAGESIZE;
-    }
+    let flag = FLAG_L3_PXN
+        | FLAG_L3_AF
+        | FLAG_L3_ISH
+        | FLAG_L3_SH_R_R
+        | FLAG_L3_ATTR_MEM
+        | FLAG_L3_NG
+        | 0b11;
+    table.map_range(ram_start, ram_start, tramp_start - ram_start, flag);
+    table.map_range(tramp_end, tramp_end, data_start - tramp_end, flag);
 
-    // map .data
-    let mut data_start = get_data_start();
-    let bss_start = get_bss_start();
+    // map the trampoline, which switches TTBR0_EL1 on exceptions from EL0
     let flag = FLAG_L3_XN
-        | FLAG_L3_PXN
         | FLAG_L3_AF
         | FLAG_L3_ISH
-        | FLAG_L3_SH_RW_RW
+        | FLAG_L3_SH_R_N
         | FLAG_L3_ATTR_MEM
+        | FLAG_L3_NG
         | 0b11;
-    while data_start < bss_start {
-        table0.map(data_start, data_start, flag);
-        data_start += PAGESIZE;
-    }
+    table.map_range(tramp_start, tramp_start, tramp_end - tramp_start, flag);
 
-    // map .bss section
-    let mut bss_start = get_bss_start();
+    // map .data and .bss sections to EL0's copy
     let end = get_stack_firm_end();
     let flag = FLAG_L3_XN
         | FLAG_L3_PXN
@@ -804,25 +1498,88 @@ fn init_el1(addr: &Addr) -> (TTable, TTable) {
         | FLAG_L3_ISH
         | FLAG_L3_SH_RW_RW
         | FLAG_L3_ATTR_MEM
+        | FLAG_L3_NG
         | 0b11;
-    while bss_start < end {
-        table0.map(bss_start, bss_start, flag);
-        bss_start += PAGESIZE;
-    }
+    table.map_range(data_start, addr.el0_image_start, end - data_start, flag);
+
+    // map device memory
+    let device_addr = DEVICE_MEM_START;
+    let flag = FLAG_L3_NS
+        | FLAG_L3_XN
+        | FLAG_L3_PXN
+        | FLAG_L3_AF
+        | FLAG_L3_OSH
+        | FLAG_L3_SH_RW_RW
+        | FLAG_L3_ATTR_DEV
+        | FLAG_L3_NG
+        | 0b11;
+    table.map_range(device_addr, device_addr, DEVICE_MEM_END - device_addr, flag);
+}
+
+/// map the firmware image and device memory for EL1, EL0 cannot access them
+fn map_el1_image(table: &mut TTable) {
+    // map .init, .text and .rodata sections
+    let ram_start = get_ram_start();
+    let data_start = get_data_start();
+    let flag = FLAG_L3_XN
+        | FLAG_L3_AF
+        | FLAG_L3_ISH
+        | FLAG_L3_SH_R_N
+        | FLAG_L3_ATTR_MEM
+        | FLAG_L3_NG
+        | 0b11;
+    table.map_range(ram_start, ram_start, data_start - ram_start, flag);
+
+    // map .data and .bss sections
+    let end = get_stack_firm_end();
+    let flag = FLAG_L3_XN
+        | FLAG_L3_PXN
+        | FLAG_L3_AF
+        | FLAG_L3_ISH
+        | FLAG_L3_SH_RW_N
+        | FLAG_L3_ATTR_MEM
+        | FLAG_L3_NG
+        | 0b11;
+    table.map_range(data_start, data_start, end - data_start, flag);
+
+    // map device memory
+    let device_addr = DEVICE_MEM_START;
+    let flag = FLAG_L3_NS
+        | FLAG_L3_XN
+        | FLAG_L3_PXN
+        | FLAG_L3_AF
+        | FLAG_L3_OSH
+        | FLAG_L3_SH_RW_N
+        | FLAG_L3_ATTR_DEV
+        | FLAG_L3_NG
+        | 0b11;
+    table.map_range(device_addr, device_addr, DEVICE_MEM_END - device_addr, flag);
+}
+
+/// set up EL1's page table, see GRANULE and VA_BITS for the format,
+/// assume 2MiB stack space per CPU
+///
+/// EL0 and EL1 have their own tables for TTBR0,
+/// the trampoline switches TTBR0_EL1 between them, see asm/device/*.S
+fn init_el1(addr: &Addr) -> (TTable, TTable) {
+    // TTBR0: user space, the initial address space whose ASID is 0
+    let (tt_start, tt_end) = get_ttbr0_pool(0);
+    let mut table0 = TTable::new(tt_start, tt_end, GRANULE, VA_BITS);
+
+    // map the firmware image and device memory
+    map_el0_image(&mut table0);
 
     // map userland stack
-    let mut stack_end = addr.stack_el0_end;
+    let stack_end = addr.stack_el0_end;
     let flag = FLAG_L3_XN
         | FLAG_L3_PXN
         | FLAG_L3_AF
         | FLAG_L3_ISH
         | FLAG_L3_SH_RW_RW
         | FLAG_L3_ATTR_MEM
+        | FLAG_L3_NG
         | 0b11;
-    while stack_end < addr.stack_el0_start {
-        table0.map(stack_end, stack_end, flag);
-        stack_end += PAGESIZE;
-    }
+    table0.map_range(stack_end, stack_end, addr.stack_el0_start - stack_end, flag);
 
     for i in 0..NUM_CPU {
         let addr = addr.stack_el0_end + i * addr.stack_size;
@@ -830,44 +1587,34 @@ fn init_el1(addr: &Addr) -> (TTable, TTable) {
     }
 
     // map userland heap
-    let mut heap_start = addr.el0_heap_start;
+    let heap_start = addr.el0_heap_start;
     let flag = FLAG_L3_XN
         | FLAG_L3_PXN
         | FLAG_L3_AF
         | FLAG_L3_ISH
         | FLAG_L3_SH_RW_RW
         | FLAG_L3_ATTR_MEM
+        | FLAG_L3_NG
         | 0b11;
-    while heap_start < addr.el0_heap_end {
-        table0.map(heap_start, heap_start, flag);
-        heap_start += PAGESIZE;
-    }
+    table0.map_range(heap_start, heap_start, addr.el0_heap_end - heap_start, flag);
 
-    // map device memory
-    let mut device_addr = DEVICE_MEM_START;
-    let flag = FLAG_L3_NS
-        | FLAG_L3_XN
-        | FLAG_L3_PXN
-        | FLAG_L3_AF
-        | FLAG_L3_OSH
-        | FLAG_L3_SH_RW_RW
-        | FLAG_L3_ATTR_DEV
-        | 0b11;
-    while device_addr < DEVICE_MEM_END {
-        table0.map(device_addr, device_addr, flag);
-        device_addr += PAGESIZE;
-    }
+    //-------------------------------------------------------------------------
+    // TTBR0 of EL1, whose ASID is EL1_ASID
+    let (tt_start, tt_end) = get_ttbr0_pool(EL1_ASID);
+    let mut table0_el1 = TTable::new(tt_start, tt_end, GRANULE, VA_BITS);
+    map_el1_image(&mut table0_el1);
 
     //-------------------------------------------------------------------------
     // TTBR1: kernel space
     let mut table1 = TTable::new(
         addr.tt_el1_ttbr1_start,
-        KERN_TTBR1_LV2_TABLE_NUM,
-        KERN_TTBR1_LV3_TABLE_NUM,
+        addr.tt_el1_ttbr1_end,
+        GRANULE,
+        VA_BITS,
     );
 
     // kernel stack
-    let mut stack_end = addr.stack_el1_end;
+    let stack_end = addr.stack_el1_end;
     let flag = FLAG_L3_XN
         | FLAG_L3_PXN
         | FLAG_L3_AF
@@ -875,18 +1622,42 @@ fn init_el1(addr: &Addr) -> (TTable, TTable) {
         | FLAG_L3_SH_RW_N
         | FLAG_L3_ATTR_MEM
         | 0b11;
-    while stack_end < addr.stack_el1_start {
-        table1.map(stack_end, stack_end, flag);
-        stack_end += PAGESIZE;
-    }
+    table1.map_range(stack_end, stack_end, addr.stack_el1_start - stack_end, flag);
 
     for i in 0..NUM_CPU {
         let addr = addr.stack_el1_end + i * addr.stack_size;
         table1.unmap(addr);
     }
 
+    // kernel heap
+    let heap_start = addr.heap_el1_start;
+    let flag = FLAG_L3_XN
+        | FLAG_L3_PXN
+        | FLAG_L3_AF
+        | FLAG_L3_ISH
+        | FLAG_L3_SH_RW_N
+        | FLAG_L3_ATTR_MEM
+        | 0b11;
+    table1.map_range(heap_start, heap_start, addr.heap_el1_end - heap_start, flag);
+
+    // stacks of kernel threads, guard pages are unmapped by kthread
+    let pool_start = addr.stack_pool_start;
+    let flag = FLAG_L3_XN
+        | FLAG_L3_PXN
+        | FLAG_L3_AF
+        | FLAG_L3_ISH
+        | FLAG_L3_SH_RW_N
+        | FLAG_L3_ATTR_MEM
+        | 0b11;
+    table1.map_range(
+        pool_start,
+        pool_start,
+        addr.stack_pool_end - pool_start,
+        flag,
+    );
+
     // map transition table for TTBR0
-    let mut tt_start = addr.tt_el1_ttbr0_start;
+    let tt_start = addr.tt_el1_ttbr0_start;
     let flag = FLAG_L3_XN
         | FLAG_L3_PXN
         | FLAG_L3_AF
@@ -895,13 +1666,10 @@ fn init_el1(addr: &Addr) -> (TTable, TTable) {
         | FLAG_L3_ATTR_MEM
         | FLAG_L3_ATTR_NC
         | 0b11;
-    while tt_start < addr.tt_el1_ttbr0_end {
-        table1.map(tt_start, tt_start, flag);
-        tt_start += PAGESIZE;
-    }
+    table1.map_range(tt_start, tt_start, addr.tt_el1_ttbr0_end - tt_start, flag);
 
     // map transition table for TTBR1
-    let mut tt_start = addr.tt_el1_ttbr1_start;
+    let tt_start = addr.tt_el1_ttbr1_start;
     let flag = FLAG_L3_XN
         | FLAG_L3_PXN
         | FLAG_L3_AF
@@ -910,17 +1678,27 @@ fn init_el1(addr: &Addr) -> (TTable, TTable) {
         | FLAG_L3_ATTR_MEM
         | FLAG_L3_ATTR_NC
         | 0b11;
-    while tt_start < addr.tt_el1_ttbr1_end {
-        table1.map(tt_start, tt_start, flag);
-        tt_start += PAGESIZE;
-    }
+    table1.map_range(tt_start, tt_start, addr.tt_el1_ttbr1_end - tt_start, flag);
+
+    // EL0's copy of .data and .bss, see get_el0_static
+    let el0_image_start = addr.el0_image_start;
+    let flag = FLAG_L3_XN
+        | FLAG_L3_PXN
+        | FLAG_L3_AF
+        | FLAG_L3_ISH
+        | FLAG_L3_SH_RW_N
+        | FLAG_L3_ATTR_MEM
+        | 0b11;
+    table1.map_range(
+        el0_image_start,
+        el0_image_start,
+        addr.el0_image_end - el0_image_start,
+        flag,
+    );
 
     //-------------------------------------------------------------------------
 
-    set_reg_el1(
-        addr.tt_el1_ttbr0_start as usize,
-        addr.tt_el1_ttbr1_start as usize,
-    );
+    set_reg_el1(table0_el1.root() as usize, table1.root() as usize);
 
     (table0, table1)
 }
@@ -934,22 +1712,22 @@ fn set_reg_el1(ttbr0: usize, ttbr1: usize) {
     let b = mmfr & 0xF;
 
     let tcr: u64 = b << 32 |
-         3 << 30 | // 64KiB granule, TTBR1_EL1
+        GRANULE.tg1() << 30 | // granule, TTBR1_EL1
          3 << 28 | // inner shadable, TTBR1_EL1
          1 << 26 | // Normal memory, Outer Write-Back Read-Allocate Write-Allocate Cacheable, TTBR1_EL1
          1 << 24 | // Normal memory, Inner Write-Back Read-Allocate Write-Allocate Cacheable, TTBR1_EL1
-        22 << 16 | // T1SZ = 22, 2 levels (level 2 and 3 translation tables), 2^42B (4TiB) space
-         1 << 14 | // 64KiB granule
+        (64 - VA_BITS) << 16 | // T1SZ, 2^VA_BITS bytes space
+        GRANULE.tg0() << 14 | // granule, TTBR0_EL1
          3 << 12 | // inner shadable, TTBR0_EL1
          1 << 10 | // Normal memory, Outer Write-Back Read-Allocate Write-Allocate Cacheable, TTBR0_EL1
          1 <<  8 | // Normal memory, Inner Write-Back Read-Allocate Write-Allocate Cacheable, TTBR0_EL1
-        22; // T0SZ = 22, 2 levels (level 2 and 3 translation tables), 2^42B (4TiB) space
+        (64 - VA_BITS); // T0SZ, 2^VA_BITS bytes space
 
     // next, specify mapping characteristics in translate control register
     unsafe { llvm_asm!("msr tcr_el1, $0" : : "r" (tcr)) };
 
     // tell the MMU where our translation tables are.
-    unsafe { llvm_asm!("msr ttbr0_el1, $0" : : "r" (ttbr0 | 1)) };
+    unsafe { llvm_asm!("msr ttbr0_el1, $0" : : "r" (ttbr0 as u64 | EL1_ASID << 48 | 1)) };
     unsafe { llvm_asm!("msr ttbr1_el1, $0" : : "r" (ttbr1 | 1)) };
 
     // finally, toggle some bits in system control register to enable page translation
@@ -960,6 +1738,15 @@ fn set_reg_el1(ttbr0: usize, ttbr1: usize) {
         1 << 4
         // clear SA0
     );
+
+    // writable memory is never executable
+    sctlr |= cpu::SCTLR_WXN_BIT;
+
+    // PSTATE.PAN is set on taking an exception to EL1
+    if cpu::is_armv8_1_pan_present() {
+        sctlr &= !cpu::SCTLR_SPAN_BIT;
+    }
+
     unsafe { llvm_asm!("msr sctlr_el1, $0; dsb sy; isb" : : "r" (sctlr)) };
 }
 
@@ -971,3 +1758,611 @@ pub fn get_no_cache<T>() -> &'static mut T {
         (addr as *mut T).as_mut().unwrap()
     }
 }
+
+//-----------------------------------------------------------------------------
+// stage 2 translation for the hypervisor
+
+// [54]: execute never
+const FLAG_S2_XN: u64 = 1 << 54;
+
+// [7:6]: S2AP, access permissions
+const FLAG_S2_AP_RO: u64 = 0b01 << 6;
+const FLAG_S2_AP_RW: u64 = 0b11 << 6;
+
+// [5:2]: MemAttr
+const FLAG_S2_MEM_NORMAL: u64 = 0b1111 << 2; // normal, outer and inner write-back cacheable
+const FLAG_S2_MEM_DEV: u64 = 0b0001 << 2; // device, nGnRE
+
+/// set up the stage 2 table of a guest,
+/// IPA is identical to PA, but the memory of the hypervisor is hidden,
+/// and the image of the guest is backed by its own copy
+pub fn init_stage2() -> TTable {
+    let addr = get_memory_map();
+    if !STAGE2_GRANULE.is_supported() {
+        driver::uart::puts("ERROR: ");
+        driver::uart::puts(STAGE2_GRANULE.name());
+        driver::uart::puts(" granule not supported\n");
+        panic!("unsupported stage 2 granule");
+    }
+
+    let mut table = TTable::new(
+        addr.tt_stage2_start,
+        addr.tt_stage2_end,
+        STAGE2_GRANULE,
+        IPA_BITS,
+    );
+    table.stage2 = true;
+
+    // map DRAM
+    let flag = FLAG_L3_AF | FLAG_L3_ISH | FLAG_S2_AP_RW | FLAG_S2_MEM_NORMAL | 0b11;
+    table.map_range(
+        addr.dram_start,
+        addr.dram_start,
+        addr.dram_end - addr.dram_start,
+        flag,
+    );
+
+    // hide the stack, the heap and the transition tables of EL2
+    let stack_end = get_stack_firm_end();
+    table.unmap_range(stack_end, get_stack_firm_start() - stack_end);
+    table.unmap_range(
+        addr.heap_firm_start,
+        addr.heap_firm_end - addr.heap_firm_start,
+    );
+    table.unmap_range(addr.tt_firm_start, addr.tt_firm_end - addr.tt_firm_start);
+    table.unmap_range(
+        addr.tt_stage2_start,
+        addr.tt_stage2_end - addr.tt_stage2_start,
+    );
+    table.unmap_range(
+        addr.guest_image_start,
+        addr.guest_image_end - addr.guest_image_start,
+    );
+
+    // the guest runs the same image, so give it a copy of the image
+    // and of the current statics instead of the hypervisor's ones
+    let ram_start = get_ram_start();
+    let data_start = get_data_start();
+    let image_end = get_stack_firm_end();
+    let guest_start = addr.guest_image_start;
+    if addr.guest_image_end - guest_start != image_end - ram_start {
+        panic!("no memory for the image of the guest");
+    }
+
+    unsafe {
+        core::ptr::copy_nonoverlapping(
+            ram_start as *const u8,
+            guest_start as *mut u8,
+            (image_end - ram_start) as usize,
+        )
+    };
+    cache::sync_icache(guest_start, data_start - ram_start);
+
+    // .init, .text and .rodata
+    let flag = FLAG_L3_AF | FLAG_L3_ISH | FLAG_S2_AP_RO | FLAG_S2_MEM_NORMAL | 0b11;
+    table.map_range(ram_start, guest_start, data_start - ram_start, flag);
+
+    // .data and .bss
+    let flag = FLAG_S2_XN | FLAG_L3_AF | FLAG_L3_ISH | FLAG_S2_AP_RW | FLAG_S2_MEM_NORMAL | 0b11;
+    table.map_range(
+        data_start,
+        guest_start + (data_start - ram_start),
+        image_end - data_start,
+        flag,
+    );
+
+    // map device memory
+    let device_addr = DEVICE_MEM_START;
+    let flag = FLAG_S2_XN | FLAG_L3_AF | FLAG_L3_OSH | FLAG_S2_AP_RW | FLAG_S2_MEM_DEV | 0b11;
+    table.map_range(device_addr, device_addr, DEVICE_MEM_END - device_addr, flag);
+
+    table
+}
+
+/// unmap [ipa, ipa + size) of the stage 2 table to trap the guest's accesses
+pub fn unmap_stage2(table: &mut TTable, ipa: u64, size: u64) {
+    table.unmap_range(ipa, size);
+    cpu::dsb_ishst();
+    cpu::tlbi_vmalls12e1is();
+    cpu::dsb_ish();
+    cpu::isb();
+}
+
+/// map [ipa, ipa + size) to [phy_addr, phy_addr + size) as read only normal memory
+pub fn map_stage2_ro(table: &mut TTable, ipa: u64, phy_addr: u64, size: u64) {
+    let flag = FLAG_L3_AF | FLAG_L3_ISH | FLAG_S2_AP_RO | FLAG_S2_MEM_NORMAL | 0b11;
+    table.map_range(ipa, phy_addr, size, flag);
+    cpu::dsb_ishst();
+    cpu::tlbi_vmalls12e1is();
+    cpu::dsb_ish();
+    cpu::isb();
+}
+
+/// map [ipa, ipa + size) to [phy_addr, phy_addr + size) as device memory
+pub fn map_stage2_dev(table: &mut TTable, ipa: u64, phy_addr: u64, size: u64) {
+    let flag = FLAG_S2_XN | FLAG_L3_AF | FLAG_L3_OSH | FLAG_S2_AP_RW | FLAG_S2_MEM_DEV | 0b11;
+    table.map_range(ipa, phy_addr, size, flag);
+    cpu::dsb_ishst();
+    cpu::tlbi_vmalls12e1is();
+    cpu::dsb_ish();
+    cpu::isb();
+}
+
+/// set VTCR_EL2 and VTTBR_EL2, stage 2 translation is enabled by HCR_EL2.VM
+pub fn set_reg_stage2(ttbr: u64, vmid: u64) {
+    let mmfr = cpu::id_aa64mmfr0_el1::get();
+    let ps = mmfr & 0xF;
+
+    // SL0, the starting level of the table
+    let start_level = get_start_level(STAGE2_GRANULE, IPA_BITS);
+    let sl0 = match STAGE2_GRANULE {
+        Granule::Size4KiB => 2 - start_level,
+        _ => 3 - start_level,
+    };
+
+    let vtcr = 1 << 31 | // Res1
+        ps << 16 | // PS, physical address size
+        STAGE2_GRANULE.tg0() << 14 | // granule
+        3 << 12 | // inner shadable
+        0 << 10 | // Normal memory, Outer Non-cacheable, same as the mapping of the tables
+        0 << 8 | // Normal memory, Inner Non-cacheable
+        sl0 << 6 | // SL0
+        (64 - IPA_BITS); // T0SZ
+
+    cpu::vtcr_el2::set(vtcr);
+    cpu::vttbr_el2::set(
+        ttbr & cpu::VTTBR_BADDR_MASK | (vmid & cpu::VTTBR_VMID_MASK) << cpu::VTTBR_VMID_SHIFT,
+    );
+
+    cpu::dsb_ishst();
+    cpu::tlbi_vmalls12e1is();
+    cpu::dsb_ish();
+    cpu::isb();
+}
+
+//-----------------------------------------------------------------------------
+// pages of the kernel space of EL1
+
+fn el1_kernel_table() -> TTable {
+    match get_regime_table(Regime::EL1TTBR1) {
+        Some(table) => table,
+        None => panic!("kernel space is not available"),
+    }
+}
+
+fn flush_el1_page(vm_addr: u64) {
+    cpu::dsb_ishst();
+    cpu::tlbi_vaae1is(vm_addr);
+    cpu::dsb_ish();
+    cpu::isb();
+}
+
+/// unmap [vm_addr, vm_addr + size) of the kernel space at EL1 on every core,
+/// used for guard pages, the range must be mapped by pages, not by blocks
+pub fn unmap_el1_pages(vm_addr: u64, size: u64) {
+    let mut table = el1_kernel_table();
+    for vm in (vm_addr..vm_addr + size).step_by(GRANULE.size() as usize) {
+        table.unmap(vm);
+        flush_el1_page(vm);
+    }
+}
+
+/// map [vm_addr, vm_addr + size) of the kernel space at EL1 to its physical address
+/// as data again, the range must have been unmapped by unmap_el1_pages
+pub fn map_el1_pages(vm_addr: u64, size: u64) {
+    let flag = FLAG_L3_XN
+        | FLAG_L3_PXN
+        | FLAG_L3_AF
+        | FLAG_L3_ISH
+        | FLAG_L3_SH_RW_N
+        | FLAG_L3_ATTR_MEM
+        | 0b11;
+    let mut table = el1_kernel_table();
+    for vm in (vm_addr..vm_addr + size).step_by(GRANULE.size() as usize) {
+        table.map(vm, vm & !EL1_ADDR_OFFSET, flag);
+        flush_el1_page(vm);
+    }
+}
+
+//-----------------------------------------------------------------------------
+// inspection of translation tables
+
+/// translation regime
+#[derive(Copy, Clone, PartialEq)]
+pub enum Regime {
+    EL3,
+    EL2,
+    EL1TTBR0,
+    EL1TTBR1,
+}
+
+/// get the table of the regime,
+/// EL3's and EL2's tables are accessible only from the same EL
+fn get_regime_table(regime: Regime) -> Option<TTable> {
+    let el = cpu::get_current_el();
+    let ttbr = match regime {
+        Regime::EL3 if el == 3 => cpu::ttbr0_el3::get(),
+        Regime::EL2 if el == 2 => cpu::ttbr0_el2::get(),
+        Regime::EL1TTBR0 if el >= 1 => cpu::ttbr0_el1::get(),
+        Regime::EL1TTBR1 if el >= 1 => cpu::ttbr1_el1::get(),
+        _ => return None,
+    };
+
+    // EL1 accesses the tables through TTBR1_EL1
+    let offset = if el == 1 { EL1_ADDR_OFFSET } else { 0 };
+    let root = ttbr & DESC_ADDR_MASK;
+    if root == 0 {
+        return None;
+    }
+
+    Some(TTable::from_root(root, GRANULE, VA_BITS, offset))
+}
+
+/// translate a virtual address in the regime to a physical address
+pub fn translate(regime: Regime, vm_addr: u64) -> Option<u64> {
+    let table = get_regime_table(regime)?;
+    let (phy_addr, _, _) = table.translate(vm_addr)?;
+    Some(phy_addr)
+}
+
+/// print the physical address and the attributes of a virtual address
+pub fn print_translation(regime: Regime, vm_addr: u64) {
+    driver::uart::puts("0x");
+    driver::uart::hex(vm_addr);
+
+    let table = match get_regime_table(regime) {
+        Some(t) => t,
+        None => {
+            driver::uart::puts(": translation table is not accessible\n");
+            return;
+        }
+    };
+
+    match table.translate(vm_addr) {
+        Some((phy_addr, desc, level)) => {
+            driver::uart::puts(" -> 0x");
+            driver::uart::hex(phy_addr);
+            driver::uart::puts(", level ");
+            driver::uart::decimal(level);
+            driver::uart::puts(", ");
+            print_attr(desc);
+            driver::uart::puts("\n");
+        }
+        None => {
+            driver::uart::puts(": not mapped\n");
+        }
+    }
+}
+
+/// print valid mappings of the regime, contiguous mappings with the same attributes are coalesced
+pub fn dump(regime: Regime) {
+    let table = match get_regime_table(regime) {
+        Some(t) => t,
+        None => {
+            driver::uart::puts("translation table is not accessible\n");
+            return;
+        }
+    };
+
+    // virtual addresses of TTBR1 have 1s in upper bits
+    let base = if regime == Regime::EL1TTBR1 {
+        EL1_ADDR_OFFSET
+    } else {
+        0
+    };
+
+    // (virtual address, physical address, size, attributes)
+    let mut range: Option<(u64, u64, u64, u64)> = None;
+    table.walk(&mut |vm_addr, phy_addr, size, desc| {
+        let attr = desc & !DESC_ADDR_MASK & !FLAG_TYPE_MASK;
+        if let Some((vm, phy, sz, a)) = range {
+            if vm + sz == vm_addr && phy + sz == phy_addr && a == attr {
+                range = Some((vm, phy, sz + size, a));
+                return;
+            }
+            print_range(base | vm, phy, sz, a);
+        }
+        range = Some((vm_addr, phy_addr, size, attr));
+    });
+
+    if let Some((vm, phy, sz, a)) = range {
+        print_range(base | vm, phy, sz, a);
+    }
+}
+
+/// dump the tables of the current EL on panic,
+/// nothing is printed at EL0 because it cannot read the system registers
+pub fn dump_on_panic() {
+    // do not dump again if dumping panicked
+    static mut DUMPED: bool = false;
+    if unsafe { DUMPED } {
+        return;
+    }
+    unsafe { DUMPED = true };
+
+    // EL0 is identified by its stack because CurrentEL is not accessible from EL0
+    if is_el0() {
+        return;
+    }
+
+    match enabled() {
+        Some(true) => (),
+        _ => return,
+    }
+
+    match cpu::get_current_el() {
+        3 => {
+            driver::uart::puts("translation table of EL3:\n");
+            dump(Regime::EL3);
+        }
+        2 => {
+            driver::uart::puts("translation table of EL2:\n");
+            dump(Regime::EL2);
+        }
+        _ => {
+            driver::uart::puts("translation table of EL1 (TTBR0):\n");
+            dump(Regime::EL1TTBR0);
+            driver::uart::puts("translation table of EL1 (TTBR1):\n");
+            dump(Regime::EL1TTBR1);
+        }
+    }
+}
+
+fn print_range(vm_addr: u64, phy_addr: u64, size: u64, attr: u64) {
+    driver::uart::puts("0x");
+    driver::uart::hex(vm_addr);
+    driver::uart::puts(" - 0x");
+    driver::uart::hex(vm_addr + size - 1);
+    driver::uart::puts(" -> 0x");
+    driver::uart::hex(phy_addr);
+    driver::uart::puts(": ");
+    print_attr(attr);
+    driver::uart::puts("\n");
+}
+
+/// print the attributes of a descriptor
+fn print_attr(desc: u64) {
+    // [7:6]: access permissions
+    match desc & (0b11 << 6) {
+        FLAG_L3_SH_RW_N => driver::uart::puts("EL1 RW, EL0 --"),
+        FLAG_L3_SH_RW_RW => driver::uart::puts("EL1 RW, EL0 RW"),
+        FLAG_L3_SH_R_N => driver::uart::puts("EL1 R-, EL0 --"),
+        _ => driver::uart::puts("EL1 R-, EL0 R-"),
+    }
+
+    if desc & FLAG_L3_XN != 0 {
+        driver::uart::puts(", XN");
+    }
+
+    if desc & FLAG_L3_PXN != 0 {
+        driver::uart::puts(", PXN");
+    }
+
+    // [4:2]: AttrIndx, see get_mair()
+    match desc & (0b111 << 2) {
+        FLAG_L3_ATTR_MEM => driver::uart::puts(", normal"),
+        FLAG_L3_ATTR_DEV => driver::uart::puts(", device"),
+        FLAG_L3_ATTR_NC => driver::uart::puts(", non-cacheable"),
+        _ => driver::uart::puts(", unknown"),
+    }
+
+    // [9:8]: shareability
+    match desc & (0b11 << 8) {
+        FLAG_L3_OSH => driver::uart::puts(", OSH"),
+        FLAG_L3_ISH => driver::uart::puts(", ISH"),
+        _ => driver::uart::puts(", NSH"),
+    }
+
+    if desc & FLAG_L3_NS != 0 {
+        driver::uart::puts(", NS");
+    } else {
+        driver::uart::puts(", S");
+    }
+
+    if desc & FLAG_L3_NG != 0 {
+        driver::uart::puts(", nG");
+    }
+
+    if desc & FLAG_L3_AF == 0 {
+        driver::uart::puts(", no AF");
+    }
+}
+
+//-----------------------------------------------------------------------------
+// user address spaces
+
+// the number of user address spaces including the initial one,
+// ASIDs from 0 to MAX_ADDR_SPACE - 1 are used
+pub const MAX_ADDR_SPACE: usize = 8;
+
+// flags for user address spaces
+// read/write, normal memory
+pub const FLAG_USER_RW: u64 = FLAG_L3_XN
+    | FLAG_L3_PXN
+    | FLAG_L3_AF
+    | FLAG_L3_ISH
+    | FLAG_L3_SH_RW_RW
+    | FLAG_L3_ATTR_MEM
+    | FLAG_L3_NG
+    | 0b11;
+
+// read only, normal memory
+pub const FLAG_USER_R: u64 = FLAG_L3_XN
+    | FLAG_L3_PXN
+    | FLAG_L3_AF
+    | FLAG_L3_ISH
+    | FLAG_L3_SH_R_R
+    | FLAG_L3_ATTR_MEM
+    | FLAG_L3_NG
+    | 0b11;
+
+// read/execute, normal memory
+pub const FLAG_USER_RX: u64 =
+    FLAG_L3_PXN | FLAG_L3_AF | FLAG_L3_ISH | FLAG_L3_SH_R_R | FLAG_L3_ATTR_MEM | FLAG_L3_NG | 0b11;
+
+// ASID of EL1's table for TTBR0, its pages for translation tables follow those of user address spaces,
+// the trampoline finds the table just below the table for TTBR1, see asm/device/*.S
+pub const EL1_ASID: u64 = MAX_ADDR_SPACE as u64;
+
+// ASID 0 is used by the initial address space
+static ASID_USED: Mutex<u64> = Mutex::new(1);
+
+/// pages for translation tables of the address space whose ASID is asid
+fn get_ttbr0_pool(asid: u64) -> (u64, u64) {
+    let addr = get_memory_map();
+    let size = PAGESIZE * KERN_TTBR0_TABLE_NUM as u64;
+    let start = addr.tt_el1_ttbr0_start + size * asid;
+    (start, start + size)
+}
+
+fn alloc_asid() -> Option<u64> {
+    let mut used = ASID_USED.lock();
+    for asid in 1..MAX_ADDR_SPACE as u64 {
+        if *used & (1 << asid) == 0 {
+            *used |= 1 << asid;
+            return Some(asid);
+        }
+    }
+    None
+}
+
+fn free_asid(asid: u64) {
+    *ASID_USED.lock() &= !(1 << asid);
+}
+
+/// ASID of TTBR0_EL1, EL1_ASID at EL1
+pub fn get_current_asid() -> u64 {
+    cpu::ttbr0_el1::get() >> 48
+}
+
+/// TTBR0_EL1 of the initial address space built at boot time, see el0_enter
+pub fn get_el0_ttbr0() -> u64 {
+    let (tt_start, _) = get_ttbr0_pool(0);
+    tt_start | 1
+}
+
+/// switch TTBR0_EL1 to the initial address space and enter EL0 at ELR_EL1,
+/// SP of EL1 is reset to sp_el1 for exceptions from EL0, and arg is passed in x0
+pub fn enter_el0(sp_el1: u64, arg: u64) -> ! {
+    extern "C" {
+        fn el0_enter(ttbr0: u64, sp_el1: u64, arg: u64) -> !;
+    }
+    cpu::dsb_ishst();
+    unsafe { el0_enter(get_el0_ttbr0(), sp_el1, arg) }
+}
+
+/// copy .data and .bss to EL0's copy, called by the primary CPU before any CPU enters EL0,
+/// EL0 starts with the statics of this time
+pub fn init_el0_image() {
+    let addr = get_memory_map();
+    let data_start = get_data_start();
+    let size = get_stack_firm_end() - data_start;
+    unsafe {
+        core::ptr::copy_nonoverlapping(
+            data_start as *const u8,
+            addr.el0_image_start as *mut u8,
+            size as usize,
+        );
+    }
+}
+
+/// EL0's instance of a static of EL1, accessed through TTBR1
+pub fn get_el0_static<T>(obj: &T) -> &'static T {
+    let addr = get_memory_map();
+    let offset = obj as *const T as u64 - get_data_start();
+    unsafe { &*((addr.el0_image_start + offset + EL1_ADDR_OFFSET) as *const T) }
+}
+
+/// user address space, tagged with an ASID in TTBR0_EL1,
+/// must be created and used at EL1 after the MMU is enabled
+///
+/// ```ignore
+/// let mut space = AddrSpace::new().unwrap(); // firmware image and device memory are mapped
+/// space.map_range(vm_addr, phy_addr, size, FLAG_USER_RW);
+/// let ttbr0 = space.ttbr0(); // for el0_enter, TLB entries of other address spaces remain
+///
+/// let child = space.clone_space().unwrap(); // same mappings, physical memory is shared
+/// ```
+pub struct AddrSpace {
+    asid: u64,
+    table: TTable,
+}
+
+impl AddrSpace {
+    /// create an address space which has the mappings of the firmware image and device memory
+    pub fn new() -> Option<AddrSpace> {
+        let mut space = AddrSpace::new_empty()?;
+        map_el0_image(&mut space.table);
+        Some(space)
+    }
+
+    fn new_empty() -> Option<AddrSpace> {
+        let asid = alloc_asid()?;
+
+        // translation tables are accessed through TTBR1_EL1
+        let (tt_start, tt_end) = get_ttbr0_pool(asid);
+        let table = TTable::with_offset(tt_start, tt_end, GRANULE, VA_BITS, EL1_ADDR_OFFSET);
+
+        // flush stale entries of the previous owner of the ASID
+        cpu::dsb_ishst();
+        cpu::tlbi_aside1is(asid);
+        cpu::dsb_ish();
+        cpu::isb();
+
+        Some(AddrSpace {
+            asid: asid,
+            table: table,
+        })
+    }
+
+    /// create an address space which has the same mappings,
+    /// physical memory is not copied but shared
+    pub fn clone_space(&self) -> Option<AddrSpace> {
+        let mut space = AddrSpace::new_empty()?;
+        space.table.copy_from(&self.table);
+        Some(space)
+    }
+
+    pub fn asid(&self) -> u64 {
+        self.asid
+    }
+
+    /// map [vm_addr, vm_addr + size) to [phy_addr, phy_addr + size),
+    /// see FLAG_USER_* for flag, EL1 never executes user pages
+    pub fn map_range(&mut self, vm_addr: u64, phy_addr: u64, size: u64, flag: u64) {
+        self.table
+            .map_range(vm_addr, phy_addr, size, flag | FLAG_L3_PXN | FLAG_L3_NG);
+
+        cpu::dsb_ishst();
+        cpu::tlbi_aside1is(self.asid);
+        cpu::dsb_ish();
+        cpu::isb();
+    }
+
+    /// unmap a page
+    pub fn unmap(&mut self, vm_addr: u64) {
+        self.table.unmap(vm_addr);
+
+        cpu::dsb_ishst();
+        cpu::tlbi_vae1is(self.asid, vm_addr);
+        cpu::dsb_ish();
+        cpu::isb();
+    }
+
+    /// TTBR0_EL1 of this address space, EL0 enters it by el0_enter without flushing TLB
+    pub fn ttbr0(&self) -> u64 {
+        self.table.root() | self.asid << 48 | 1
+    }
+}
+
+// EL0 must not run on the address space when it is dropped
+impl Drop for AddrSpace {
+    fn drop(&mut self) {
+        cpu::dsb_ishst();
+        cpu::tlbi_aside1is(self.asid);
+        cpu::dsb_ish();
+        cpu::isb();
+
+        free_asid(self.asid);
+    }
+}
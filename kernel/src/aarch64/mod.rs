@@ -6,4 +6,7 @@ pub mod cpu;
 pub mod exception;
 pub mod lock;
 pub mod mmu;
+pub mod percpu;
+pub mod preempt;
 pub mod syscall;
+pub mod uaccess;
@@ -0,0 +1,116 @@
+// per-CPU data areas
+//
+// TPIDR_EL3 (TPIDR_EL2 without EL3) and TPIDR_EL1 hold the address of CpuLocal of the core,
+// which is set by init on each core and EL.
+// PerCpu<T> has an instance of T for each core, and a core accesses only its own instance
+// through the index in CpuLocal, without reading MPIDR_EL1.
+// EL0 cannot read these registers, so it is not available for EL0.
+
+use super::cpu;
+use super::preempt::PreemptGuard;
+use crate::driver::topology::{core_pos, CORE_COUNT};
+
+use core::cell::UnsafeCell;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+/// the per-CPU area pointed by TPIDR
+#[repr(C)]
+pub struct CpuLocal {
+    index: usize, // core position
+}
+
+const EMPTY_LOCAL: CpuLocal = CpuLocal { index: 0 };
+static mut CPU_LOCAL: [CpuLocal; CORE_COUNT] = [EMPTY_LOCAL; CORE_COUNT];
+
+/// set up the per-CPU area of the calling core for the current EL
+pub fn init() {
+    let idx = core_pos();
+    let local = unsafe { &mut CPU_LOCAL[idx] };
+    local.index = idx;
+
+    let addr = local as *mut CpuLocal as u64;
+    match cpu::get_current_el() {
+        3 => cpu::tpidr_el3::set(addr),
+        2 => cpu::tpidr_el2::set(addr),
+        _ => cpu::tpidr_el1::set(addr),
+    }
+}
+
+fn get_local() -> Option<&'static CpuLocal> {
+    let addr = match cpu::get_current_el() {
+        3 => cpu::tpidr_el3::get(),
+        2 => cpu::tpidr_el2::get(),
+        _ => cpu::tpidr_el1::get(),
+    };
+
+    if addr == 0 {
+        None
+    } else {
+        Some(unsafe { &*(addr as *const CpuLocal) })
+    }
+}
+
+/// the core position of the calling core
+pub fn index() -> usize {
+    match get_local() {
+        Some(local) => local.index,
+        None => panic!("per-CPU area is not initialized"),
+    }
+}
+
+/// the core position of the calling core, None if init has not been called at the current EL
+pub fn try_index() -> Option<usize> {
+    get_local().map(|local| local.index)
+}
+
+/// an instance of T for each core
+///
+/// ```
+/// static COUNT: PerCpu<u64> = PerCpu::new([0; CORE_COUNT]);
+///
+/// COUNT.with(|n| *n += 1);
+/// ```
+pub struct PerCpu<T> {
+    data: UnsafeCell<[T; CORE_COUNT]>,
+    borrowed: [AtomicBool; CORE_COUNT],
+}
+
+// every core accesses only its own instance
+unsafe impl<T: Send> Sync for PerCpu<T> {}
+
+const NOT_BORROWED: AtomicBool = AtomicBool::new(false);
+
+impl<T> PerCpu<T> {
+    pub const fn new(data: [T; CORE_COUNT]) -> PerCpu<T> {
+        PerCpu {
+            data: UnsafeCell::new(data),
+            borrowed: [NOT_BORROWED; CORE_COUNT],
+        }
+    }
+
+    /// call f with the instance of the calling core,
+    /// panic if it is borrowed already, for example by interrupted code of the same core
+    ///
+    /// preemption is disabled during f, so the task stays on the core until it is released
+    pub fn with<R>(&self, f: impl FnOnce(&mut T) -> R) -> R {
+        let _preempt = PreemptGuard::new();
+        let idx = index();
+        if self.borrowed[idx].swap(true, Ordering::Acquire) {
+            panic!("per-CPU data is borrowed twice");
+        }
+
+        let ret = f(unsafe { &mut (*self.data.get())[idx] });
+
+        self.borrowed[idx].store(false, Ordering::Release);
+        ret
+    }
+
+    /// the instance of the core at idx
+    ///
+    /// # Safety
+    ///
+    /// the caller must synchronize with the core, for example it is offline
+    pub unsafe fn get_of(&self, idx: usize) -> &mut T {
+        &mut (*self.data.get())[idx]
+    }
+}
@@ -0,0 +1,91 @@
+// preemption control
+//
+// A core is not preempted while its count is not zero, so code updating per-core data
+// or holding a lock is neither switched out nor moved to another core in the middle.
+// Locks disable preemption while they are held.
+// The counts are indexed by the core position, and EL0 reads it from TPIDRRO_EL0.
+// EL0 updates its own copy of COUNT, see mmu::init_el0_image, and EL1 reads it through TTBR1,
+// so a task is preempted only if both counts of the core are zero.
+// The scheduler saves and restores the counts of a task when the task switches itself out.
+
+use super::{cpu, mmu};
+use crate::driver::topology::{core_pos, CORE_COUNT};
+
+use core::sync::atomic::{compiler_fence, AtomicUsize, Ordering};
+
+const ZERO: AtomicUsize = AtomicUsize::new(0);
+static COUNT: [AtomicUsize; CORE_COUNT] = [ZERO; CORE_COUNT];
+
+/// the core position of the calling core,
+/// EL1 stores the core position to TPIDRRO_EL0, because EL0 cannot read MPIDR_EL1
+pub fn core_id() -> usize {
+    if mmu::is_el0() {
+        cpu::tpidrro_el0::get() as usize
+    } else {
+        core_pos()
+    }
+}
+
+/// disable preemption of the calling core, calls may be nested
+pub fn disable() {
+    loop {
+        let id = core_id();
+        COUNT[id].fetch_add(1, Ordering::Relaxed);
+
+        // the task may have been moved to another core before the count was incremented
+        if core_id() == id {
+            break;
+        }
+        COUNT[id].fetch_sub(1, Ordering::Relaxed);
+    }
+    compiler_fence(Ordering::SeqCst);
+}
+
+/// enable preemption disabled by disable
+pub fn enable() {
+    compiler_fence(Ordering::SeqCst);
+    COUNT[core_id()].fetch_sub(1, Ordering::Relaxed);
+}
+
+/// the counts of EL0, called by EL1
+fn el0_count() -> &'static [AtomicUsize; CORE_COUNT] {
+    mmu::get_el0_static(&COUNT)
+}
+
+/// whether the calling core may be preempted, called by IRQ handlers of EL1
+pub fn is_enabled() -> bool {
+    let id = core_pos();
+    COUNT[id].load(Ordering::Relaxed) == 0 && el0_count()[id].load(Ordering::Relaxed) == 0
+}
+
+/// the counts of EL1 and EL0 of the calling core, saved by the scheduler for the task switched out
+pub fn get_count() -> (usize, usize) {
+    let id = core_pos();
+    (
+        COUNT[id].load(Ordering::Relaxed),
+        el0_count()[id].load(Ordering::Relaxed),
+    )
+}
+
+/// restore the counts of the task switched in, IRQs must be masked
+pub fn set_count(n: (usize, usize)) {
+    let id = core_pos();
+    COUNT[id].store(n.0, Ordering::Relaxed);
+    el0_count()[id].store(n.1, Ordering::Relaxed);
+}
+
+/// disable preemption until dropped
+pub struct PreemptGuard;
+
+impl PreemptGuard {
+    pub fn new() -> PreemptGuard {
+        disable();
+        PreemptGuard
+    }
+}
+
+impl Drop for PreemptGuard {
+    fn drop(&mut self) {
+        enable();
+    }
+}
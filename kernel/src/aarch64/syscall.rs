@@ -3,21 +3,115 @@ pub mod svc {
     use crate::aarch64::context;
     use crate::driver::uart;
     use crate::el1;
+    use crate::memalloc;
+    use crate::sched;
+
+    use alloc::boxed::Box;
 
     pub const SYS_SWITCH_WORLD: u64 = 1;
+    pub const SYS_DUMP_MMU: u64 = 2;
+    pub const SYS_TRANSLATE: u64 = 3;
+    pub const SYS_MEM_STATS: u64 = 4;
+    pub const SYS_MEM_LEAKS: u64 = 5;
+    pub const SYS_SLEEP: u64 = 6;
+    pub const SYS_YIELD: u64 = 7;
+    pub const SYS_SPAWN: u64 = 8;
+    pub const SYS_EXIT: u64 = 9;
 
     /// switch to normal mode
     pub fn switch_world() {
         unsafe { asm!("svc #1") }
     }
 
-    pub fn handle64(id: u64, _ctx: &context::GpRegs, _sp: usize) {
-        uart::puts("Sycall #");
-        uart::decimal(id);
-        uart::puts("\n");
+    /// dump the translation table of EL1, 0: TTBR0, 1: TTBR1
+    pub fn dump_mmu(ttbr: u64) {
+        unsafe { asm!("svc #2", in("x0") ttbr) }
+    }
+
+    /// translate a virtual address of EL1&0 to a physical address
+    pub fn translate(vm_addr: u64) -> Option<u64> {
+        let phy_addr: u64;
+        unsafe { asm!("svc #3", inout("x0") vm_addr => phy_addr) }
+        if phy_addr == !0 {
+            None
+        } else {
+            Some(phy_addr)
+        }
+    }
+
+    /// query the statistics of the memory allocator, see memalloc::stats for queries
+    pub fn mem_stats(query: u64) -> Option<u64> {
+        let addr = memalloc::stats::get_addr();
+        let val: u64;
+        unsafe { asm!("svc #4", inout("x0") addr => val, in("x1") query) }
+        if val == !0 {
+            None
+        } else {
+            Some(val)
+        }
+    }
+
+    /// print live allocations and their call sites, return the number of them,
+    /// nothing is traced without the mem_trace feature
+    pub fn mem_leaks() -> u64 {
+        let (addr, num) = if cfg!(feature = "mem_trace") {
+            memalloc::trace::get_records()
+        } else {
+            (0, 0)
+        };
+
+        let n: u64;
+        unsafe { asm!("svc #5", inout("x0") addr => n, in("x1") num) }
+        n
+    }
+
+    /// sleep for usec microseconds by the kernel timer service
+    pub fn sleep(usec: u64) {
+        unsafe { asm!("svc #6", in("x0") usec) }
+    }
+
+    /// give the core to another ready task
+    pub fn yield_now() {
+        unsafe { asm!("svc #7") }
+    }
+
+    /// run entry(arg) as a new task of EL0 on stack, return the task ID,
+    /// the stack is allocated from EL0's heap and never freed
+    pub fn spawn(entry: fn(u64) -> !, arg: u64, stack: Box<[u8]>) -> Option<u64> {
+        let stack = Box::leak(stack);
+        let sp = (stack.as_mut_ptr() as u64 + stack.len() as u64) & !0xf;
+        let id: u64;
+        unsafe { asm!("svc #8", inout("x0") entry as u64 => id, in("x1") arg, in("x2") sp) }
+        if id == !0 {
+            drop(unsafe { Box::from_raw(stack) });
+            None
+        } else {
+            Some(id)
+        }
+    }
+
+    /// finish the calling task
+    pub fn exit() -> ! {
+        unsafe { asm!("svc #9") };
+        unreachable!()
+    }
 
+    pub fn handle64(id: u64, ctx: &mut context::GpRegs, _sp: usize) {
         match id {
-            SYS_SWITCH_WORLD => el1::sys_switch(),
+            SYS_SWITCH_WORLD => {
+                uart::puts("Sycall #");
+                uart::decimal(id);
+                uart::puts("\n");
+                el1::sys_switch()
+            }
+            SYS_DUMP_MMU => el1::sys_dump_mmu(ctx.x0),
+            SYS_TRANSLATE => ctx.x0 = el1::sys_translate(ctx.x0),
+            SYS_MEM_STATS => ctx.x0 = el1::sys_mem_stats(ctx.x0, ctx.x1),
+            SYS_MEM_LEAKS => ctx.x0 = el1::sys_mem_leaks(ctx.x0, ctx.x1),
+            SYS_SLEEP => el1::sys_sleep(ctx.x0),
+            SYS_YIELD => sched::yield_now(),
+            SYS_SPAWN => ctx.x0 = el1::sys_spawn(ctx.x0, ctx.x1, ctx.x2),
+            SYS_EXIT => sched::exit(),
             _ => (),
         }
     }
@@ -0,0 +1,65 @@
+// access to user memory from EL1
+//
+// EL1 must not dereference pointers passed from EL0 directly,
+// because PSTATE.PAN forbids it and the pointers may not be mapped.
+// Moreover, EL1's table for TTBR0 maps only EL1's image, see mmu::init_el1.
+// The following functions switch to EL0's table in the trampoline for each byte,
+// check EL0's permissions by the AT instruction,
+// and then copy through unprivileged loads and stores (LDTR/STTR).
+
+use super::mmu;
+
+extern "C" {
+    fn el0_load_byte(addr: u64, ttbr0: u64) -> i64;
+    fn el0_store_byte(addr: u64, val: u64, ttbr0: u64) -> i64;
+}
+
+/// check [addr, addr + size) is in TTBR0's space
+fn is_user_range(addr: u64, size: u64) -> bool {
+    // EL0 cannot access TTBR1's space
+    match addr.checked_add(size) {
+        Some(end) => end <= (1 << mmu::VA_BITS),
+        None => false,
+    }
+}
+
+/// copy user memory at src to dst,
+/// return false if EL0 cannot read [src, src + dst.len())
+pub fn copy_from_user(dst: &mut [u8], src: u64) -> bool {
+    if !is_user_range(src, dst.len() as u64) {
+        return false;
+    }
+
+    let ttbr0 = mmu::get_el0_ttbr0();
+    let mut addr = src;
+    for d in dst.iter_mut() {
+        let v = unsafe { el0_load_byte(addr, ttbr0) };
+        if v < 0 {
+            return false;
+        }
+        *d = v as u8;
+        addr += 1;
+    }
+
+    true
+}
+
+/// copy src to user memory at dst,
+/// return false if EL0 cannot write [dst, dst + src.len()),
+/// bytes before the first inaccessible one are written
+pub fn copy_to_user(dst: u64, src: &[u8]) -> bool {
+    if !is_user_range(dst, src.len() as u64) {
+        return false;
+    }
+
+    let ttbr0 = mmu::get_el0_ttbr0();
+    let mut addr = dst;
+    for s in src.iter() {
+        if unsafe { el0_store_byte(addr, *s as u64, ttbr0) } < 0 {
+            return false;
+        }
+        addr += 1;
+    }
+
+    true
+}
@@ -1,9 +1,11 @@
 use core::mem::size_of;
 use core::ptr::{copy_nonoverlapping, read_volatile, write_volatile};
 
-use crate::aarch64::cache;
+use crate::aarch64::{cache, cpu};
 use crate::driver::arm::gic;
 
+pub mod virt;
+
 // GICv2 specific Distributor interface register offsets and constants.
 const GICD_ITARGETSR: usize = 0x800;
 const GICD_SGIR: usize = 0xF00;
@@ -11,6 +13,9 @@ const GICD_CPENDSGIR: usize = 0xF10;
 const GICD_SPENDSGIR: usize = 0xF20;
 const GICD_PIDR2_GICV2: usize = 0xFE8;
 
+// Size of the Distributor interface
+pub const GICD_SIZE: u64 = 0x1000;
+
 // GICv2 specific CPU interface register offsets and constants.
 
 // Physical CPU Interface registers
@@ -38,6 +43,13 @@ const FIQ_EN_SHIFT: u32 = 3;
 const FIQ_EN_BIT: u32 = 1 << FIQ_EN_SHIFT;
 const ACK_CTL: u32 = 1 << 2;
 
+// GICC_CTLR bit definitions of the Non-secure copy
+const NS_ENABLE_GRP1: u32 = 1 << 0;
+const NS_EOI_MODE_NS: u32 = 1 << 9;
+
+const GICC_IAR_INTID_MASK: u32 = 0x3ff;
+pub const GIC_SPURIOUS_INTR: u32 = 1023;
+
 const ITARGETSR_SHIFT: u32 = 2;
 const GIC_TARGET_CPU_MASK: u32 = 0xff;
 
@@ -49,6 +61,7 @@ const SGIR_TGTLSTFLT_MASK: u32 = 0x3;
 const SGIR_TGTLST_SHIFT: u32 = 16;
 const SGIR_TGTLST_MASK: u32 = 0xff;
 const SGIR_INTID_MASK: u32 = 0xf;
+const SGIR_NSATT: u32 = 1 << 15;
 
 // Interrupt group definitions
 const GICV2_INTR_GROUP0: u32 = 0;
@@ -354,3 +367,77 @@ pub fn cpuif_disable() {
         | IRQ_BYP_DIS_GRP1;
     gicc_write_ctlr(base, val);
 }
+
+/// Enable Non-secure interrupts for the hypervisor at EL2. EOImodeNS is set,
+/// so writing GICC_EOIR only drops the running priority and an interrupt
+/// forwarded to a guest is deactivated by the guest through the virtual CPU
+/// interface.
+pub fn cpuif_enable_hyp() {
+    let base = get_gicc_base();
+    gicc_write_pmr(base, gic::GIC_PRI_MASK);
+    gicc_write_ctlr(base, NS_ENABLE_GRP1 | NS_EOI_MODE_NS);
+}
+
+/// Signal Group 1 interrupts as IRQs to the secure EL1 kernel, in addition to
+/// Group 0 ones as FIQs. AckCtl is set so that GICC_IAR of the secure copy
+/// returns Group 1 interrupts too. This must be called in the secure state.
+pub fn cpuif_enable_grp1() {
+    let base = get_gicc_base();
+    let val = gicc_read_ctlr(base) | CTLR_ENABLE_G1_BIT | ACK_CTL;
+    gicc_write_ctlr(base, val);
+}
+
+/// Enable a Non-secure SGI or PPI of the current CPU, or an SPI.
+pub fn enable_interrupt(id: u32) {
+    gic::gicd_set_isenabler(get_gicd_base(), id);
+}
+
+/// Acknowledge the highest priority pending interrupt, return GICC_IAR.
+pub fn cpuif_acknowledge() -> u32 {
+    let ptr = (get_gicc_base() + GICC_IAR) as *const u32;
+    unsafe { read_volatile(ptr) }
+}
+
+/// Interrupt ID of the value of GICC_IAR
+pub fn get_intr_id(iar: u32) -> u32 {
+    iar & GICC_IAR_INTID_MASK
+}
+
+/// Priority drop, and deactivation if EOImode is 0.
+pub fn cpuif_end_of_interrupt(iar: u32) {
+    let ptr = (get_gicc_base() + GICC_EOIR) as *mut u32;
+    unsafe { write_volatile(ptr, iar) };
+}
+
+/// Deactivate an interrupt if EOImode is 1.
+pub fn cpuif_deactivate(iar: u32) {
+    let ptr = (get_gicc_base() + GICC_DIR) as *mut u32;
+    unsafe { write_volatile(ptr, iar) };
+}
+
+/// Get the CPU interface mask of the current CPU, used as a target list of SGIs.
+pub fn get_cpuif_mask() -> u32 {
+    gicd_get_cpuif_id(get_gicd_base())
+}
+
+/// Generate the SGI `id` on the CPU interfaces in the target list `targets`.
+/// NSATT is set so that the SGI is forwarded if it is configured as Group 1,
+/// which is the case for the secure EL1 kernel (see cpuif_enable_grp1).
+pub fn raise_sgi(id: u32, targets: u32) {
+    let val = (targets & SGIR_TGTLST_MASK) << SGIR_TGTLST_SHIFT | SGIR_NSATT | id & SGIR_INTID_MASK;
+    let ptr = (get_gicd_base() + GICD_SGIR) as *mut u32;
+
+    // make the stores before the SGI visible to the targets
+    cpu::dsb_ishst();
+    unsafe { write_volatile(ptr, val) };
+}
+
+/// Base address of GICC
+pub fn get_cpuif_base() -> usize {
+    get_gicc_base()
+}
+
+/// Base address of GICD
+pub fn get_distif_base() -> usize {
+    get_gicd_base()
+}
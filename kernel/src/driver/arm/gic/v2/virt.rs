@@ -0,0 +1,133 @@
+use core::ptr::{read_volatile, write_volatile};
+
+// GICv2 virtualization extensions.
+// The virtual interface control registers (GICH) are accessed by the hypervisor
+// to inject virtual interrupts through list registers, and the virtual CPU
+// interface (GICV) is mapped to the guest at the address of GICC.
+
+// Virtual interface control registers
+const GICH_HCR: usize = 0x0;
+const GICH_VTR: usize = 0x4;
+const GICH_VMCR: usize = 0x8;
+const GICH_MISR: usize = 0x10;
+const GICH_EISR0: usize = 0x20;
+const GICH_ELRSR0: usize = 0x30;
+const GICH_APR: usize = 0xF0;
+const GICH_LR0: usize = 0x100;
+
+// GICH_HCR bit definitions
+pub const HCR_EN: u32 = 1 << 0;
+pub const HCR_UIE: u32 = 1 << 1; // maintenance interrupt on underflow
+
+// GICH_VTR bit definitions
+const VTR_LISTREGS_MASK: u32 = 0x3f;
+
+// GICH_MISR bit definitions
+pub const MISR_EOI: u32 = 1 << 0;
+pub const MISR_U: u32 = 1 << 1;
+
+// GICH_LR bit definitions
+pub const LR_VIRTUALID_MASK: u32 = 0x3ff;
+pub const LR_PHYSICALID_SHIFT: u32 = 10;
+pub const LR_EOI: u32 = 1 << 19; // maintenance interrupt on EOI, if HW is 0
+pub const LR_PRIORITY_SHIFT: u32 = 23; // [27:23], upper 5 bits of priority
+pub const LR_STATE_PENDING: u32 = 0b01 << 28;
+pub const LR_STATE_ACTIVE: u32 = 0b10 << 28;
+pub const LR_STATE_MASK: u32 = 0b11 << 28;
+pub const LR_GRP1: u32 = 1 << 30;
+pub const LR_HW: u32 = 1 << 31; // deactivate the physical interrupt on EOI of the guest
+
+// Maintenance interrupt of the virtual interface
+pub const MAINTENANCE_INTR_ID: u32 = 25;
+
+// Size of the virtual CPU interface, same as GICC
+pub const GICV_SIZE: u64 = 0x2000;
+
+// Size of the virtual interface control, with the aliases for each processor
+pub const GICH_SIZE: u64 = 0x2000;
+
+static mut GICH_BASE: usize = 0;
+static mut GICV_BASE: usize = 0;
+
+/// Set the base addresses of the virtual interface control registers and
+/// the virtual CPU interface, called by the platform port if supported.
+pub fn driver_init(gich_base: usize, gicv_base: usize) {
+    unsafe {
+        write_volatile(&mut GICH_BASE, gich_base);
+        write_volatile(&mut GICV_BASE, gicv_base);
+    }
+}
+
+/// Whether the virtualization extensions are available
+pub fn is_present() -> bool {
+    get_gich_base() != 0
+}
+
+pub fn get_gich_base() -> usize {
+    unsafe { read_volatile(&GICH_BASE) }
+}
+
+pub fn get_gicv_base() -> usize {
+    unsafe { read_volatile(&GICV_BASE) }
+}
+
+fn gich_read(offset: usize) -> u32 {
+    let ptr = (get_gich_base() + offset) as *const u32;
+    unsafe { read_volatile(ptr) }
+}
+
+fn gich_write(offset: usize, val: u32) {
+    let ptr = (get_gich_base() + offset) as *mut u32;
+    unsafe { write_volatile(ptr, val) };
+}
+
+/// Number of the list registers
+pub fn num_lrs() -> usize {
+    ((gich_read(GICH_VTR) & VTR_LISTREGS_MASK) + 1) as usize
+}
+
+/// Reset the list registers and enable the virtual CPU interface
+pub fn enable() {
+    for i in 0..num_lrs() {
+        write_lr(i, 0);
+    }
+    gich_write(GICH_APR, 0);
+    gich_write(GICH_VMCR, 0);
+    gich_write(GICH_HCR, HCR_EN);
+}
+
+pub fn read_hcr() -> u32 {
+    gich_read(GICH_HCR)
+}
+
+pub fn write_hcr(val: u32) {
+    gich_write(GICH_HCR, val);
+}
+
+/// Maintenance interrupt status
+pub fn read_misr() -> u32 {
+    gich_read(GICH_MISR)
+}
+
+/// Bitmap of the list registers whose interrupts were EOIed by the guest.
+/// Only list registers with LR_EOI set are reported.
+pub fn read_eisr() -> u64 {
+    let lo = gich_read(GICH_EISR0) as u64;
+    let hi = gich_read(GICH_EISR0 + 4) as u64;
+    hi << 32 | lo
+}
+
+/// Bitmap of the empty list registers
+pub fn read_elrsr() -> u64 {
+    let lo = gich_read(GICH_ELRSR0) as u64;
+    let hi = gich_read(GICH_ELRSR0 + 4) as u64;
+    hi << 32 | lo
+}
+
+pub fn read_lr(n: usize) -> u32 {
+    gich_read(GICH_LR0 + (n << 2))
+}
+
+pub fn write_lr(n: usize, val: u32) {
+    gich_write(GICH_LR0 + (n << 2), val);
+}
@@ -4,6 +4,9 @@ use super::device::generic::delays;
 #[cfg(feature = "raspi4")]
 use super::device::raspi::delays;
 
+use crate::aarch64::{mmu, syscall};
+use crate::{ktimer, sched};
+
 pub fn init() {
     delays::init();
 }
@@ -12,9 +15,18 @@ pub fn get_timer_value() -> u64 {
     delays::get_timer_value()
 }
 
-/// wait microsec
+/// wait microsec, sleep by the scheduler or the kernel timer service at EL0 and EL1
+/// if they are ready, otherwise busy-wait
 pub fn wait_microsec(usec: u32) {
-    delays::wait_microsec(usec);
+    if mmu::is_el0() {
+        syscall::svc::sleep(usec as u64);
+    } else if sched::is_ready() {
+        sched::sleep_usec(usec as u64);
+    } else if ktimer::is_ready() {
+        ktimer::sleep_usec(usec as u64);
+    } else {
+        delays::wait_microsec(usec);
+    }
 }
 
 /// wait milisec
@@ -253,7 +253,8 @@ static mut ARISC_LOCK: lock::BakeryTicket = lock::BakeryTicket::new();
 fn sunxi_execute_arisc_code(code: &mut [u32], param: u32) {
     let arisc_reset_vec = (SUNXI_SRAM_A2_BASE + 0x100) as *mut u32;
 
-    let _ = unsafe { ARISC_LOCK.lock() };
+    // the lock is held until the arisc is started
+    let _lock = unsafe { ARISC_LOCK.lock() };
 
     loop {
         // Wait until the arisc is in reset state.
@@ -0,0 +1,55 @@
+// interrupts of EL1 through GIC-400
+//
+// SGIs and PPIs are Group 1 (see gic::v2::pcpu_distif_init),
+// and the secure EL1 kernel receives them as IRQs.
+// SGIs are sent to the CPU interfaces recorded by init_cpu of the target cores.
+
+use crate::aarch64::percpu;
+use crate::driver::arm::gic::v2;
+use crate::driver::topology::CORE_COUNT;
+
+/// the number of SGI IDs
+pub const NUM_SGIS: u32 = 16;
+
+// CPU interface masks of the cores, 0 until init_cpu is called
+static mut CPUIF_MASKS: [u32; CORE_COUNT] = [0; CORE_COUNT];
+
+pub fn init_cpu() {
+    v2::cpuif_enable_grp1();
+    for id in 0..NUM_SGIS {
+        v2::enable_interrupt(id);
+    }
+
+    unsafe { CPUIF_MASKS[percpu::index()] = v2::get_cpuif_mask() };
+}
+
+/// send the SGI to the cores of the bit mask of core positions
+pub fn send_sgi(id: u32, cores: u32) {
+    let mut targets = 0;
+    for core in (0..CORE_COUNT).filter(|c| cores & 1 << c != 0) {
+        targets |= unsafe { CPUIF_MASKS[core] };
+    }
+
+    if targets != 0 {
+        v2::raise_sgi(id, targets);
+    }
+}
+
+/// enable the PPI of the calling core
+pub fn enable_ppi(id: u32) {
+    v2::enable_interrupt(id);
+}
+
+/// acknowledge pending interrupts and call dispatch with their IDs
+pub fn handle_irq(dispatch: fn(u32)) {
+    loop {
+        let iar = v2::cpuif_acknowledge();
+        let id = v2::get_intr_id(iar);
+        if id == v2::GIC_SPURIOUS_INTR {
+            break;
+        }
+
+        dispatch(id);
+        v2::cpuif_end_of_interrupt(iar);
+    }
+}
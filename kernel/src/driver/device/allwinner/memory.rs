@@ -1,5 +1,7 @@
 // Allwinner A64
 
+use core::ptr::read_volatile;
+
 pub const DEVICE_MEM_START: u64 = 0x01000000;
 pub const DEVICE_MEM_END: u64 = 0x02000000;
 pub const ROM_START: u64 = 0x00000000;
@@ -58,6 +60,8 @@ pub const SUNXI_SPI1_BASE: u32 = 0x01c69000;
 pub const SUNXI_SCU_BASE: u32 = 0x01c80000;
 pub const SUNXI_GICD_BASE: u32 = 0x01c81000;
 pub const SUNXI_GICC_BASE: u32 = 0x01c82000;
+pub const SUNXI_GICH_BASE: u32 = 0x01c84000;
+pub const SUNXI_GICV_BASE: u32 = 0x01c86000;
 pub const SUNXI_RTC_BASE: u32 = 0x01f00000;
 pub const SUNXI_R_TIMER_BASE: u32 = 0x01f00800;
 pub const SUNXI_R_INTC_BASE: u32 = 0x01f00c00;
@@ -73,3 +77,37 @@ pub const SUNXI_R_RSB_BASE: u32 = 0x01f03400;
 pub const SUNXI_R_PWM_BASE: u32 = 0x01f03800;
 
 pub const DRAM_BASE: u64 = 0x40000000;
+
+// the maximum size of DRAM, 0x40000000 - 0xFFFFFFFF
+const DRAM_SIZE_MAX: u64 = 0xC0000000;
+
+/// DRAM is contiguous
+pub fn get_high_dram() -> Option<(u64, u64)> {
+    None
+}
+
+// configuration register of the DRAM controller
+const MCTL_COM_CR: *const u32 = SUNXI_DRAMCOM_BASE as *const u32;
+
+/// get the base address and the size of DRAM,
+/// the size is calculated from the geometry the SPL configured the DRAM controller with
+pub fn get_dram() -> (u64, u64) {
+    let cr = unsafe { read_volatile(MCTL_COM_CR) } as u64;
+
+    let ranks = (cr & 1) + 1; // [0]: dual rank
+    let banks = if (cr >> 2) & 1 == 1 { 8 } else { 4 }; // [2]: eight banks
+    let row_bits = ((cr >> 4) & 0xF) + 1; // [7:4]: row bits - 1
+    let page_size = 1 << (((cr >> 8) & 0xF) + 3); // [11:8]: log2(page size) - 3
+    let full_width = (cr >> 12) & 1 == 1; // [12]: 32 bits, or 16 bits if 0
+
+    // the page size is of the full width, a half width bus has the half of it
+    let mut size = (1 << row_bits) * banks * page_size * ranks;
+    if !full_width {
+        size /= 2;
+    }
+    if size > DRAM_SIZE_MAX {
+        (DRAM_BASE, DRAM_SIZE_MAX)
+    } else {
+        (DRAM_BASE, size)
+    }
+}
@@ -5,6 +5,7 @@ use crate::bits;
 pub(crate) mod axp;
 pub(crate) mod cpu;
 pub(crate) mod defs;
+pub(crate) mod irq;
 pub(crate) mod mbox;
 pub(crate) mod memory;
 pub(crate) mod mhu;
@@ -29,6 +29,12 @@ pub fn platform_setup() {
     gic::v2::pcpu_distif_init();
     gic::v2::cpuif_enable();
 
+    // GIC-400 has the virtualization extensions
+    gic::v2::virt::driver_init(
+        memory::SUNXI_GICH_BASE as usize,
+        memory::SUNXI_GICV_BASE as usize,
+    );
+
     security::init();
 
     let soc_id = read_soc_id();
@@ -38,3 +38,16 @@ pub fn recv() -> u32 {
     }
     c as u32
 }
+
+/// receive a character if one is in the buffer
+pub fn try_recv() -> Option<u32> {
+    if unsafe { read_volatile(UART0_LSR) } & 1 == 0 {
+        return None;
+    }
+
+    let c;
+    unsafe {
+        c = read_volatile(UART0_RBR);
+    }
+    Some(c as u32)
+}
@@ -0,0 +1,98 @@
+// interrupts of EL1 through the local interrupt controller of BCM2836,
+// see "Quad-A7 control" (QA7_rev3.4.pdf)
+//
+// The controller has no PPI IDs, so timer interrupts are translated to
+// the PPI IDs of the GIC to share the dispatcher with Pine64.
+// They are level-sensitive, and are cleared by the handlers of the timers.
+//
+// SGIs are emulated by mailbox 0 of each core, whose bit n is SGI n.
+// Bits are set by other cores, and cleared before dispatching them.
+
+use super::memory;
+use crate::aarch64::{cpu, percpu};
+use crate::driver::topology::CORE_COUNT;
+
+use core::ptr::{read_volatile, write_volatile};
+
+/// PPI IDs of bit 0 to 3 of the timer control and the IRQ source registers,
+/// CNTPSIRQ, CNTPNSIRQ, CNTHPIRQ and CNTVIRQ
+const TIMER_PPIS: [u32; 4] = [29, 30, 26, 27];
+
+/// mailbox 0 of core 0 in bit 4 of the IRQ source register
+const MBOX0_SOURCE: u32 = 1 << 4;
+
+/// the number of SGI IDs
+pub const NUM_SGIS: u32 = 16;
+
+fn timer_cntl() -> *mut u32 {
+    (memory::CORE0_TIMER_IRQCNTL + percpu::index() as u32 * 4) as *mut u32
+}
+
+fn irq_source() -> *const u32 {
+    (memory::CORE0_IRQ_SOURCE + percpu::index() as u32 * 4) as *const u32
+}
+
+fn mbox_cntl() -> *mut u32 {
+    (memory::CORE0_MBOX_IRQCNTL + percpu::index() as u32 * 4) as *mut u32
+}
+
+fn mbox_set(core: usize) -> *mut u32 {
+    (memory::CORE0_MBOX0_SET + core as u32 * 0x10) as *mut u32
+}
+
+fn mbox_rdclr() -> *mut u32 {
+    (memory::CORE0_MBOX0_RDCLR + percpu::index() as u32 * 0x10) as *mut u32
+}
+
+/// enable the IRQ of mailbox 0 of the calling core
+pub fn init_cpu() {
+    let ptr = mbox_cntl();
+    unsafe { write_volatile(ptr, read_volatile(ptr) | 1) };
+}
+
+/// send the SGI to the cores of the bit mask of core positions
+pub fn send_sgi(id: u32, cores: u32) {
+    // make the stores before the SGI visible to the targets
+    cpu::dsb_ishst();
+    for core in (0..CORE_COUNT).filter(|c| cores & 1 << c != 0) {
+        unsafe { write_volatile(mbox_set(core), 1 << id) };
+    }
+}
+
+/// enable the PPI of the calling core
+pub fn enable_ppi(id: u32) {
+    let bit = match TIMER_PPIS.iter().position(|ppi| *ppi == id) {
+        Some(bit) => bit,
+        None => panic!("unsupported PPI"),
+    };
+
+    let ptr = timer_cntl();
+    unsafe { write_volatile(ptr, read_volatile(ptr) | 1 << bit) };
+}
+
+/// call dispatch with the IDs of pending interrupts
+pub fn handle_irq(dispatch: fn(u32)) {
+    loop {
+        let src = unsafe { read_volatile(irq_source()) };
+        let mut handled = false;
+        for (bit, id) in TIMER_PPIS.iter().enumerate() {
+            if src & 1 << bit != 0 {
+                dispatch(*id);
+                handled = true;
+            }
+        }
+
+        if src & MBOX0_SOURCE != 0 {
+            let sgis = unsafe { read_volatile(mbox_rdclr()) };
+            unsafe { write_volatile(mbox_rdclr(), sgis) };
+            for id in (0..NUM_SGIS).filter(|id| sgis & 1 << id != 0) {
+                dispatch(id);
+            }
+            handled = true;
+        }
+
+        if !handled {
+            break;
+        }
+    }
+}
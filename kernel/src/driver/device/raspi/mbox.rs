@@ -1,8 +1,5 @@
 use core::ptr::{read_volatile, write_volatile};
 
-use core::slice;
-
-use super::graphics;
 use super::memory::*;
 
 use crate::aarch64::mmu;
@@ -148,20 +145,9 @@ pub fn get_memory() -> usize {
         Some(rev) => {
             // https://www.raspberrypi.org/documentation/hardware/raspberrypi/revision-codes/README.md
             if (rev >> 23) & 1 == 0 {
-                let m = mmu::get_no_cache::<[u32; 8]>();
-                m[0] = 8 * 4; // length of the message
-                m[1] = MBOX_REQUEST; // this is a request message
-                m[2] = MBOX_TAG_GETMEM; // get memory
-                m[3] = 8; // buffer size
-                m[4] = 8;
-                m[5] = 0; // clear output buffer
-                m[6] = 0;
-                m[7] = MBOX_TAG_LAST;
-
-                if call(&mut (m[0]) as *mut u32, MBOX_CH_PROP) {
-                    m[6] as usize
-                } else {
-                    256 * 1024 * 1024 // 256MiB
+                match get_arm_memory() {
+                    Some((_, size)) => size as usize,
+                    None => 256 * 1024 * 1024, // 256MiB
                 }
             } else {
                 match (rev >> 20) & 0b111 {
@@ -178,6 +164,26 @@ pub fn get_memory() -> usize {
     }
 }
 
+/// get the base address and the size of the memory assigned to ARM,
+/// the memory assigned to the VideoCore is excluded
+pub fn get_arm_memory() -> Option<(u32, u32)> {
+    let m = mmu::get_no_cache::<[u32; 8]>();
+    m[0] = 8 * 4; // length of the message
+    m[1] = MBOX_REQUEST; // this is a request message
+    m[2] = MBOX_TAG_GETMEM; // get memory
+    m[3] = 8; // buffer size
+    m[4] = 8;
+    m[5] = 0; // clear output buffer
+    m[6] = 0;
+    m[7] = MBOX_TAG_LAST;
+
+    if call(&mut (m[0]) as *mut u32, MBOX_CH_PROP) {
+        Some((m[5], m[6]))
+    } else {
+        None
+    }
+}
+
 pub fn set_uart_clock(clock: u32) {
     let m = mmu::get_no_cache::<[u32; 9]>();
     m[0] = 9 * 4;
@@ -207,75 +213,3 @@ pub fn set_power_off(n: u32) {
 
     call(&mut (m[0]) as *mut u32, MBOX_CH_PROP);
 }
-
-/// set display's setting
-pub fn set_display(
-    width_phy: u32,
-    height_phy: u32,
-    width_virt: u32,
-    height_virt: u32,
-    offset_x: u32,
-    offset_y: u32,
-) -> Option<graphics::Display> {
-    let m = mmu::get_no_cache::<[u32; 35]>();
-    m[0] = 35 * 4;
-    m[1] = MBOX_REQUEST;
-
-    m[2] = MBOX_TAG_SETPHY_WH; // set physical display's width and height
-    m[3] = 8;
-    m[4] = 8;
-    m[5] = width_phy;
-    m[6] = height_phy;
-
-    m[7] = MBOX_TAG_SETVIRT_WH; // set virttual display's width and height
-    m[8] = 8;
-    m[9] = 8;
-    m[10] = width_virt;
-    m[11] = height_virt;
-
-    m[12] = MBOX_TAG_SETVIRT_OFFSET; // set virtual display's offset
-    m[13] = 8;
-    m[14] = 8;
-    m[15] = offset_x;
-    m[16] = offset_y;
-
-    m[17] = MBOX_TAG_SETDEPTH; // set depth
-    m[18] = 4;
-    m[19] = 4;
-    m[20] = 32; // 32 bits per pixel
-
-    m[21] = MBOX_TAG_SETPIXELORDER; // set pixel order
-    m[22] = 4;
-    m[23] = 4;
-    m[24] = 1; // 0: BGR, 1: RGB
-
-    m[25] = MBOX_TAG_ALLOCFB; // allocate frame buffer
-    m[26] = 8;
-    m[27] = 8;
-    m[28] = 4096; // request: align 4096 bytes, responce: frame buffer base address
-    m[29] = 0; // responce: frame buffer size
-
-    m[30] = MBOX_TAG_GETPITCH; // get pitch
-    m[31] = 4;
-    m[32] = 4;
-    m[33] = 0; // bytes per line
-
-    m[34] = MBOX_TAG_LAST;
-
-    if call(&mut (m[0]) as *mut u32, MBOX_CH_PROP) && m[20] == 32 && m[28] != 0 {
-        let ptr = m[28] & 0x3FFFFFFF;
-        let slice =
-            unsafe { slice::from_raw_parts_mut(ptr as *mut u8, m[33] as usize * m[11] as usize) };
-        Some(graphics::Display {
-            size_phy: (m[5], m[6]),
-            size_virt: (m[10], m[11]),
-            offset: (m[15], m[16]),
-            depth: m[20],
-            pitch: m[33],
-            ptr: ptr,
-            buffer: slice,
-        })
-    } else {
-        None
-    }
-}
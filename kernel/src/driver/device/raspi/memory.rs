@@ -5,8 +5,9 @@
 #[cfg(feature = "raspi3")]
 mod raspi {
     pub const MMIO_BASE: u32 = 0x3F000000;
+    pub const LOCAL_BASE: u32 = 0x40000000; // local peripherals of BCM2836
     pub const DEVICE_MEM_START: u64 = 0x3C000000;
-    pub const DEVICE_MEM_END: u64 = 0x40000000;
+    pub const DEVICE_MEM_END: u64 = 0x40010000;
 }
 //-----------------------------------------------------------------------------
 
@@ -15,6 +16,9 @@ mod raspi {
 #[cfg(feature = "raspi4")]
 mod raspi {
     pub const MMIO_BASE: u32 = 0xFE000000;
+    pub const LOCAL_BASE: u32 = 0xFF800000; // legacy local peripherals, enable_gic=0 in config.txt
+    pub const GICD_BASE: u32 = 0xFF841000; // distributor of GIC-400
+    pub const GICC_BASE: u32 = 0xFF842000; // CPU interface of GIC-400
     pub const DEVICE_MEM_START: u64 = 0x0fd000000; // maybe...
     pub const DEVICE_MEM_END: u64 = 0x100000000; // maybe...
 }
@@ -26,9 +30,25 @@ pub const ROM_START: u64 = 0;
 pub const ROM_END: u64 = 0;
 
 pub const MMIO_BASE: u32 = raspi::MMIO_BASE;
+pub const LOCAL_BASE: u32 = raspi::LOCAL_BASE;
 pub const DEVICE_MEM_START: u64 = raspi::DEVICE_MEM_START;
 pub const DEVICE_MEM_END: u64 = raspi::DEVICE_MEM_END;
 
+// local interrupt controller, registers of core 0, followed by cores 1 to 3 at every 4 bytes
+pub const CORE0_TIMER_IRQCNTL: u32 = LOCAL_BASE + 0x40;
+pub const CORE0_MBOX_IRQCNTL: u32 = LOCAL_BASE + 0x50;
+pub const CORE0_IRQ_SOURCE: u32 = LOCAL_BASE + 0x60;
+
+// mailboxes 0 to 3 of core 0, followed by cores 1 to 3 at every 16 bytes
+pub const CORE0_MBOX0_SET: u32 = LOCAL_BASE + 0x80;
+pub const CORE0_MBOX0_RDCLR: u32 = LOCAL_BASE + 0xC0;
+
+// GIC-400 of BCM2711, used unless enable_gic=0
+#[cfg(feature = "raspi4")]
+pub const GICD_BASE: u32 = raspi::GICD_BASE;
+#[cfg(feature = "raspi4")]
+pub const GICC_BASE: u32 = raspi::GICC_BASE;
+
 pub const GPFSEL0: *mut u32 = (MMIO_BASE + 0x00200000) as *mut u32;
 pub const GPFSEL1: *mut u32 = (MMIO_BASE + 0x00200004) as *mut u32;
 pub const GPFSEL2: *mut u32 = (MMIO_BASE + 0x00200008) as *mut u32;
@@ -62,3 +82,25 @@ pub const AUX_MU_STAT: *mut u32 = (MMIO_BASE + 0x00215064) as *mut u32;
 pub const AUX_MU_BAUD: *mut u32 = (MMIO_BASE + 0x00215068) as *mut u32;
 
 pub const DRAM_BASE: u64 = 0;
+
+// the memory assigned to the VideoCore is below this
+const LOW_DRAM_END: u64 = 0x40000000;
+
+/// get the base address and the size of the memory assigned to ARM by the VideoCore
+pub fn get_dram() -> (u64, u64) {
+    match super::mbox::get_arm_memory() {
+        Some((base, size)) => (base as u64, size as u64),
+        None => (DRAM_BASE, 256 * 1024 * 1024), // 256MiB
+    }
+}
+
+/// get the memory above 1GiB of boards with more than 1GiB,
+/// which is not contiguous with get_dram because of the memory of the VideoCore
+pub fn get_high_dram() -> Option<(u64, u64)> {
+    let total = super::mbox::get_memory() as u64;
+    if total > LOW_DRAM_END {
+        Some((LOW_DRAM_END, total - LOW_DRAM_END))
+    } else {
+        None
+    }
+}
@@ -1,5 +1,10 @@
 pub(crate) mod defs;
 pub(crate) mod delays;
+#[cfg(feature = "raspi4")]
+pub(crate) mod gic;
+#[cfg(feature = "raspi3")]
+pub(crate) mod irq;
+pub(crate) mod mbox;
 pub(crate) mod memory;
 pub(crate) mod psci;
 pub(crate) mod setup;
@@ -1,4 +1,28 @@
+#[cfg(feature = "raspi4")]
+use super::memory;
+#[cfg(feature = "raspi4")]
+use crate::driver::arm::gic;
+
 // TODO:
 // dummy
 pub(crate) fn early_platform_setup() {}
+
+#[cfg(feature = "raspi3")]
 pub(crate) fn platform_setup() {}
+
+#[cfg(feature = "raspi4")]
+pub(crate) fn platform_setup() {
+    // GIC-400 is configured by setup_gic in raspi.S,
+    // which enables the distributor only on the secondary cores
+    let driver_data = gic::v2::GICv2DriverData::new_gicd_gicc(
+        memory::GICD_BASE as usize,
+        memory::GICC_BASE as usize,
+    );
+    gic::v2::driver_init(&driver_data);
+
+    let ctlr = gic::gicd_read_ctlr(driver_data.gicd_base);
+    gic::gicd_write_ctlr(
+        driver_data.gicd_base,
+        ctlr | gic::CTLR_ENABLE_G0_BIT | gic::v2::CTLR_ENABLE_G1_BIT,
+    );
+}
@@ -79,3 +79,16 @@ pub fn recv() -> u32 {
     }
     c as u32
 }
+
+/// receive a character if one is in the buffer
+pub fn try_recv() -> Option<u32> {
+    if unsafe { read_volatile(UART0_FR) } & 0x10 != 0 {
+        return None;
+    }
+
+    let c;
+    unsafe {
+        c = read_volatile(UART0_DR);
+    }
+    Some(c as u32)
+}
@@ -0,0 +1,177 @@
+// minimal parser of flattened device tree blob
+// see https://devicetree-specification.readthedocs.io/en/latest/chapter5-flattened-format.html
+
+use super::memory::{DEVICE_MEM_END, DEVICE_MEM_START, DRAM_BASE};
+
+const FDT_MAGIC: u32 = 0xd00dfeed;
+
+// structure block tokens
+const FDT_BEGIN_NODE: u32 = 1;
+const FDT_END_NODE: u32 = 2;
+const FDT_PROP: u32 = 3;
+const FDT_NOP: u32 = 4;
+
+// header offsets
+const FDT_TOTALSIZE: u64 = 4;
+const FDT_OFF_DT_STRUCT: u64 = 8;
+const FDT_OFF_DT_STRINGS: u64 = 12;
+const FDT_HEADER_SIZE: u64 = 40;
+
+// a blob larger than this is regarded as broken
+const FDT_MAX_SIZE: u64 = 16 * 1024 * 1024;
+
+/// address of the device tree blob passed by the boot loader through x0,
+/// written by _start of the primary CPU
+#[no_mangle]
+static mut DTB_ADDR: u64 = 0;
+
+fn read_be32(addr: u64) -> u32 {
+    u32::from_be(unsafe { *(addr as *const u32) })
+}
+
+fn read_cells(addr: u64, cells: u32) -> u64 {
+    let mut val = 0;
+    for i in 0..cells as u64 {
+        val = (val << 32) | read_be32(addr + i * 4) as u64;
+    }
+    val
+}
+
+fn align4(n: u64) -> u64 {
+    (n + 3) & !3
+}
+
+fn strlen(addr: u64) -> u64 {
+    let mut n = 0;
+    while unsafe { *((addr + n) as *const u8) } != 0 {
+        n += 1;
+    }
+    n
+}
+
+fn str_eq(addr: u64, s: &str) -> bool {
+    let len = strlen(addr);
+    if len != s.len() as u64 {
+        return false;
+    }
+    let bytes = unsafe { core::slice::from_raw_parts(addr as *const u8, len as usize) };
+    bytes == s.as_bytes()
+}
+
+// "memory" or "memory@<unit-address>"
+fn is_memory_node(addr: u64) -> bool {
+    let len = strlen(addr);
+    let bytes = unsafe { core::slice::from_raw_parts(addr as *const u8, len as usize) };
+    bytes == b"memory" || bytes.starts_with(b"memory@")
+}
+
+/// get the address of the device tree blob if a valid one was passed
+pub fn get_addr() -> Option<u64> {
+    let addr = unsafe { DTB_ADDR };
+
+    // x0 may hold garbage if the boot loader does not pass a blob
+    if addr == 0 || addr & 0b111 != 0 || addr < DRAM_BASE {
+        return None;
+    }
+
+    // the header must not overlap devices nor wrap around
+    let header_end = addr.checked_add(FDT_HEADER_SIZE)?;
+    if addr < DEVICE_MEM_END && DEVICE_MEM_START < header_end {
+        return None;
+    }
+
+    if read_be32(addr) != FDT_MAGIC {
+        return None;
+    }
+
+    // neither does the whole blob, and the blocks must be in it
+    let size = read_be32(addr + FDT_TOTALSIZE) as u64;
+    if size < FDT_HEADER_SIZE || size > FDT_MAX_SIZE {
+        return None;
+    }
+
+    let end = addr.checked_add(size)?;
+    if addr < DEVICE_MEM_END && DEVICE_MEM_START < end {
+        return None;
+    }
+
+    let off_struct = read_be32(addr + FDT_OFF_DT_STRUCT) as u64;
+    let off_strings = read_be32(addr + FDT_OFF_DT_STRINGS) as u64;
+    if off_struct >= size || off_strings >= size {
+        return None;
+    }
+
+    Some(addr)
+}
+
+/// get the region of the /memory node containing addr,
+/// return Some((base address, size)) or None if no blob is passed
+///
+/// the blob is read only at boot time, so it may be overwritten afterwards
+pub fn get_memory(addr: u64) -> Option<(u64, u64)> {
+    let dtb = get_addr()?;
+    let end = dtb + read_be32(dtb + FDT_TOTALSIZE) as u64;
+    let strings = dtb + read_be32(dtb + FDT_OFF_DT_STRINGS) as u64;
+    let mut p = dtb + read_be32(dtb + FDT_OFF_DT_STRUCT) as u64;
+
+    // the root node is at depth 1
+    let mut depth = 0;
+    let mut in_memory = false;
+
+    // default values of the root node
+    let mut addr_cells = 2;
+    let mut size_cells = 1;
+
+    while p < end {
+        let token = read_be32(p);
+        p += 4;
+
+        match token {
+            FDT_BEGIN_NODE => {
+                depth += 1;
+                in_memory = depth == 2 && is_memory_node(p);
+                p += align4(strlen(p) + 1);
+            }
+            FDT_END_NODE => {
+                depth -= 1;
+                in_memory = false;
+            }
+            FDT_PROP => {
+                let len = read_be32(p) as u64;
+                let name = strings + read_be32(p + 4) as u64;
+                let val = p + 8;
+                p = val + align4(len);
+
+                if depth == 1 {
+                    if str_eq(name, "#address-cells") {
+                        addr_cells = read_be32(val);
+                    } else if str_eq(name, "#size-cells") {
+                        size_cells = read_be32(val);
+                    }
+                } else if in_memory && str_eq(name, "reg") {
+                    // reg = <base size>, <base size>, ...
+                    let entry_size = (addr_cells + size_cells) as u64 * 4;
+                    if entry_size == 0 {
+                        return None;
+                    }
+
+                    for i in 0..(len / entry_size) {
+                        let e = val + i * entry_size;
+                        let base = read_cells(e, addr_cells);
+                        let size = read_cells(e + addr_cells as u64 * 4, size_cells);
+                        if base <= addr && addr < base + size {
+                            return Some((base, size));
+                        }
+                    }
+                }
+            }
+            FDT_NOP => (),
+            _ => {
+                // FDT_END or broken blob
+                return None;
+            }
+        }
+    }
+
+    None
+}
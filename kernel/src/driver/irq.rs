@@ -0,0 +1,70 @@
+// interrupts of EL1
+//
+// IRQs are routed through the GIC on Pine64 and Raspberry Pi 4,
+// and through the local interrupt controller on Raspberry Pi 3.
+// SGIs 0 to NUM_SGIS - 1 are sent between cores, and each ID has a handler set by
+// set_sgi_handler, which is called with the ID with IRQs masked.
+
+#[cfg(feature = "raspi3")]
+use super::device::raspi::irq;
+
+#[cfg(feature = "raspi4")]
+use super::device::raspi::gic as irq;
+
+#[cfg(feature = "pine64")]
+use super::device::allwinner::irq;
+
+use super::{timer, uart};
+use crate::aarch64::lock::Mutex;
+
+pub use irq::NUM_SGIS;
+
+static SGI_HANDLERS: Mutex<[Option<fn(u32)>; NUM_SGIS as usize]> =
+    Mutex::new([None; NUM_SGIS as usize]);
+
+/// enable IRQs to EL1 on the calling core
+pub fn init() {
+    irq::init_cpu();
+}
+
+/// enable the PPI of the calling core
+pub fn enable_ppi(id: u32) {
+    irq::enable_ppi(id);
+}
+
+/// call handler when the SGI is received, on any core
+pub fn set_sgi_handler(id: u32, handler: fn(u32)) {
+    SGI_HANDLERS.lock_irq()[id as usize] = Some(handler);
+}
+
+/// send the SGI to the cores of the bit mask of core positions
+pub fn send_sgi(id: u32, cores: u32) {
+    if id >= NUM_SGIS {
+        panic!("invalid SGI ID");
+    }
+    irq::send_sgi(id, cores);
+}
+
+/// called by the IRQ exception handlers of EL1
+pub fn handle() {
+    irq::handle_irq(dispatch);
+}
+
+fn dispatch(id: u32) {
+    if id < NUM_SGIS {
+        let handler = SGI_HANDLERS.lock()[id as usize];
+        if let Some(handler) = handler {
+            handler(id);
+            return;
+        }
+    }
+
+    match id {
+        timer::PHYS_PPI | timer::VIRT_PPI => timer::handle_irq(id),
+        _ => {
+            uart::puts("unexpected IRQ: ");
+            uart::decimal(id as u64);
+            uart::puts("\n");
+        }
+    }
+}
@@ -12,5 +12,15 @@ pub const ROM_START: u64 = memory::ROM_START;
 pub const ROM_END: u64 = memory::ROM_END;
 pub const DRAM_BASE: u64 = memory::DRAM_BASE;
 
+/// get the base address and the size of DRAM available to the CPUs
+pub fn get_dram() -> (u64, u64) {
+    memory::get_dram()
+}
+
+/// get the base address and the size of DRAM which is not contiguous with get_dram
+pub fn get_high_dram() -> Option<(u64, u64)> {
+    memory::get_high_dram()
+}
+
 #[cfg(feature = "pine64")]
 pub const CSS_SCP_COM_SHARED_MEM_BASE: u32 = memory::CSS_SCP_COM_SHARED_MEM_BASE;
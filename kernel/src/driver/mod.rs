@@ -2,9 +2,12 @@ pub(crate) mod arm;
 pub mod defs;
 pub mod delays;
 mod device;
+pub mod dtb;
+pub mod irq;
 pub mod memory;
 pub mod psci;
 mod setup;
+pub mod timer;
 pub mod topology;
 pub mod uart;
 
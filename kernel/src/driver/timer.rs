@@ -0,0 +1,171 @@
+// per-core generic timers of EL1
+//
+// The EL1 physical timer (CNTP_*) and the virtual timer (CNTV_*) of each core
+// raise PPIs, which are handled by driver::irq.
+// A timer fires once at a deadline, or periodically at every interval.
+// Deadlines and intervals are in ticks of the counter of the timer, see ticks_per_sec.
+// Callbacks are called by the IRQ handler of the core which armed the timer,
+// so they must not block, but they may arm the timer again.
+
+use super::{irq, topology::CORE_COUNT};
+use crate::aarch64::{cpu, lock::IrqMask, percpu::PerCpu};
+
+/// PPI of the EL1 physical timer
+pub const PHYS_PPI: u32 = 30;
+
+/// PPI of the virtual timer
+pub const VIRT_PPI: u32 = 27;
+
+// CNTP_CTL_EL0 and CNTV_CTL_EL0
+const CTL_ENABLE: u64 = 1 << 0;
+const CTL_IMASK: u64 = 1 << 1;
+
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub enum Timer {
+    Physical = 0,
+    Virtual = 1,
+}
+
+#[derive(Copy, Clone)]
+struct Entry {
+    callback: Option<fn()>,
+    deadline: u64,
+    period: u64, // 0 if one-shot
+}
+
+const EMPTY_ENTRY: Entry = Entry {
+    callback: None,
+    deadline: 0,
+    period: 0,
+};
+
+const NO_ENTRIES: [Entry; 2] = [EMPTY_ENTRY; 2];
+static TIMERS: PerCpu<[Entry; 2]> = PerCpu::new([NO_ENTRIES; CORE_COUNT]);
+
+/// disable the timers of the calling core and enable their interrupts,
+/// called on each core at EL1
+pub fn init() {
+    cpu::cntp_ctl_el0::set(CTL_IMASK);
+    cpu::cntv_ctl_el0::set(CTL_IMASK);
+
+    irq::init();
+    irq::enable_ppi(PHYS_PPI);
+    irq::enable_ppi(VIRT_PPI);
+}
+
+/// frequency of the counters in Hz
+pub fn ticks_per_sec() -> u64 {
+    cpu::cntfrq_el0::get()
+}
+
+/// microseconds in ticks, rounded up
+pub fn usec_to_ticks(usec: u64) -> u64 {
+    (usec * ticks_per_sec() + 999_999) / 1_000_000
+}
+
+/// the current count of the counter of the timer
+pub fn now(timer: Timer) -> u64 {
+    match timer {
+        Timer::Physical => cpu::cntpct_el0::get(),
+        Timer::Virtual => cpu::cntvct_el0::get(),
+    }
+}
+
+fn arm(timer: Timer, deadline: u64) {
+    match timer {
+        Timer::Physical => {
+            cpu::cntp_cval_el0::set(deadline);
+            cpu::cntp_ctl_el0::set(CTL_ENABLE);
+        }
+        Timer::Virtual => {
+            cpu::cntv_cval_el0::set(deadline);
+            cpu::cntv_ctl_el0::set(CTL_ENABLE);
+        }
+    }
+}
+
+fn disarm(timer: Timer) {
+    match timer {
+        Timer::Physical => cpu::cntp_ctl_el0::set(CTL_IMASK),
+        Timer::Virtual => cpu::cntv_ctl_el0::set(CTL_IMASK),
+    }
+}
+
+fn set(timer: Timer, entry: Entry) {
+    let _mask = IrqMask::new();
+    TIMERS.with(|t| t[timer as usize] = entry);
+    arm(timer, entry.deadline);
+}
+
+/// call callback once when the counter reaches deadline,
+/// this replaces the previous setting of the timer of the calling core
+pub fn set_oneshot(timer: Timer, deadline: u64, callback: fn()) {
+    set(
+        timer,
+        Entry {
+            callback: Some(callback),
+            deadline,
+            period: 0,
+        },
+    );
+}
+
+/// call callback every interval ticks from now,
+/// ticks missed by slow handling are skipped
+pub fn set_periodic(timer: Timer, interval: u64, callback: fn()) {
+    if interval == 0 {
+        panic!("interval of a periodic timer is 0");
+    }
+
+    set(
+        timer,
+        Entry {
+            callback: Some(callback),
+            deadline: now(timer) + interval,
+            period: interval,
+        },
+    );
+}
+
+/// stop the timer of the calling core
+pub fn cancel(timer: Timer) {
+    let _mask = IrqMask::new();
+    disarm(timer);
+    TIMERS.with(|t| t[timer as usize] = EMPTY_ENTRY);
+}
+
+/// called by driver::irq with IRQs masked
+pub fn handle_irq(id: u32) {
+    let timer = if id == PHYS_PPI {
+        Timer::Physical
+    } else {
+        Timer::Virtual
+    };
+
+    let now = now(timer);
+    let callback = TIMERS.with(|t| {
+        let e = &mut t[timer as usize];
+        if e.callback.is_none() {
+            disarm(timer);
+            return None;
+        }
+
+        if now < e.deadline {
+            return None; // the timer has been set again
+        }
+
+        if e.period == 0 {
+            disarm(timer);
+            return e.callback.take();
+        }
+
+        let missed = (now - e.deadline) / e.period;
+        e.deadline += (missed + 1) * e.period;
+        arm(timer, e.deadline);
+        e.callback
+    });
+
+    if let Some(f) = callback {
+        f();
+    }
+}
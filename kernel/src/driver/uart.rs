@@ -17,10 +17,20 @@ pub fn recv() -> u32 {
     return uart::recv();
 }
 
+/// receive a character without waiting
+pub fn try_recv() -> Option<u32> {
+    uart::try_recv()
+}
+
 pub fn init() {
     uart::init(UART_CLOCK, UART_BAUD);
 }
 
+/// send a raw byte to serial console
+pub fn putc(c: u8) {
+    send(c as u32);
+}
+
 /// print characters to serial console
 pub fn puts(s: &str) {
     for c in s.bytes() {
@@ -1,4 +1,4 @@
-use crate::aarch64::{mmu, syscall};
+use crate::aarch64::syscall;
 use crate::driver::{delays, uart};
 use crate::memalloc;
 
@@ -24,18 +24,61 @@ const GLOBAL_CODE: &str = "
 (export switch-world () (IO (-> () Int))
     (call-rust 1 0 0))
 
+; dump the translation table of EL1, 0: TTBR0, 1: TTBR1
+(export dump-mmu (n) (IO (-> (Int) Int))
+    (call-rust 2 n 0))
+
+; translate a virtual address to a physical address, -1 if not mapped
+(export translate (addr) (IO (-> (Int) Int))
+    (call-rust 3 addr 0))
+
+; print statistics of the memory allocator, return bytes in use
+(export mem-stats () (IO (-> () Int))
+    (call-rust 4 0 0))
+
+; a counter of the memory allocator
+; 1: bytes in use, 2: peak bytes, 3: allocations, 4: deallocations, 5: failures
+(export mem-counter (n) (IO (-> (Int) Int))
+    (call-rust 4 n 0))
+
+; print live allocations and their call sites, needs the mem_trace feature
+(export mem-leaks () (IO (-> () Int))
+    (call-rust 5 0 0))
+
+; sleep for n milliseconds
+(export sleep (n) (IO (-> (Int) Int))
+    (call-rust 6 n 0))
+
 (export factorial (n) (Pure (-> (Int) Int))
     (if (<= n 0)
         1
         (* n (factorial (- n 1)))))
 ";
 
-fn callback(x: i64, _y: i64, _z: i64) -> i64 {
-    if x == 1 {
-        syscall::svc::switch_world();
-        0
-    } else {
-        -1
+fn callback(x: i64, y: i64, _z: i64) -> i64 {
+    match x {
+        1 => {
+            syscall::svc::switch_world();
+            0
+        }
+        2 => {
+            syscall::svc::dump_mmu(y as u64);
+            0
+        }
+        3 => match syscall::svc::translate(y as u64) {
+            Some(addr) => addr as i64,
+            None => -1,
+        },
+        4 => match syscall::svc::mem_stats(y as u64) {
+            Some(val) => val as i64,
+            None => -1,
+        },
+        5 => syscall::svc::mem_leaks() as i64,
+        6 => {
+            delays::wait_milisec(y as u32);
+            0
+        }
+        _ => -1,
     }
 }
 
@@ -93,10 +136,7 @@ fn repl_uart(ctx: &blisp::semantics::Context) -> ! {
 #[no_mangle]
 pub fn el0_entry_core_0() -> ! {
     // initialize memory allocator
-    let addr = mmu::get_memory_map();
-    let size = addr.el0_heap_end - addr.el0_heap_start;
-    let mid = (addr.el0_heap_start + (size >> 1)) as usize;
-    memalloc::init(addr.el0_heap_start as usize, mid, mid);
+    memalloc::init();
 
     uart::puts("global code:\n");
     uart::puts(GLOBAL_CODE);
@@ -1,5 +1,8 @@
-use crate::aarch64::{cpu, mmu};
-use crate::driver::{delays, topology, uart};
+use crate::aarch64::{cpu, mmu, percpu, uaccess};
+use crate::driver::{timer, uart};
+use crate::memalloc::{self, stats, trace};
+use crate::sched;
+use crate::{ipi, kthread, ktimer};
 
 #[cfg(not(feature = "raspi3"))]
 use crate::aarch64::syscall;
@@ -13,10 +16,35 @@ extern "C" {
 pub fn el1_entry() -> ! {
     cpu::init_cpacr_el1(); // enable NEON
 
+    // EL1 cannot access user memory except through copy_from_user and copy_to_user
+    if cpu::is_armv8_1_pan_present() {
+        cpu::set_pan();
+    }
+
+    // unprivileged loads and stores are checked with EL0's permissions
+    if cpu::is_armv8_2_uao_present() {
+        cpu::clear_uao();
+    }
+
+    percpu::init();
+
     let addr = mmu::get_memory_map();
-    let aff = topology::core_pos() as u64;
+    let aff = percpu::index() as u64;
+
+    if aff == 0 {
+        memalloc::init();
+        kthread::init();
+    }
+
+    timer::init();
+    ipi::init();
+    ktimer::init();
+    sched::init();
+
+    // the global allocator of EL0 finds its per-core cache by this
+    cpu::tpidrro_el0::set(aff);
     let stack = addr.stack_el0_start - addr.stack_size * aff;
-    let entry = if topology::core_pos() == 0 {
+    let entry = if aff == 0 {
         el0_entry_core_0
     } else {
         el0_entry_core_x
@@ -26,9 +54,10 @@ pub fn el1_entry() -> ! {
     cpu::sp_el0::set(stack);
     cpu::spsr_el1::set(0); // EL0t
     cpu::elr_el1::set(entry);
-    cpu::eret();
 
-    delays::forever()
+    // exceptions from EL0 start from the top of EL1's stack
+    let stack_el1 = addr.stack_el1_start - addr.stack_size * aff + mmu::EL1_ADDR_OFFSET;
+    mmu::enter_el0(stack_el1, 0)
 }
 
 #[cfg(not(feature = "raspi3"))]
@@ -42,3 +71,90 @@ pub fn sys_switch() {
 pub fn sys_switch() {
     uart::puts("sys_switch is not supported for Qemu (Raspi3)\n")
 }
+
+/// dump the translation table, 0: TTBR0, 1: TTBR1
+pub fn sys_dump_mmu(ttbr: u64) {
+    if ttbr == 0 {
+        mmu::dump(mmu::Regime::EL1TTBR0);
+    } else {
+        mmu::dump(mmu::Regime::EL1TTBR1);
+    }
+}
+
+/// translate a virtual address, return !0 if it is not mapped
+pub fn sys_translate(vm_addr: u64) -> u64 {
+    let regime = if vm_addr & mmu::EL1_ADDR_OFFSET == mmu::EL1_ADDR_OFFSET {
+        mmu::Regime::EL1TTBR1
+    } else {
+        mmu::Regime::EL1TTBR0
+    };
+
+    mmu::print_translation(regime, vm_addr);
+    match mmu::translate(regime, vm_addr) {
+        Some(phy_addr) => phy_addr,
+        None => !0,
+    }
+}
+
+/// report the statistics of the memory allocator of EL0 at addr,
+/// return !0 if the query is unknown or addr is not accessible
+pub fn sys_mem_stats(addr: u64, query: u64) -> u64 {
+    let mut words = [0; stats::NUM_WORDS];
+    let mut bytes = [0; stats::NUM_WORDS * 8];
+    if !uaccess::copy_from_user(&mut bytes, addr) {
+        return !0;
+    }
+
+    for (w, b) in words.iter_mut().zip(bytes.chunks(8)) {
+        let mut buf = [0; 8];
+        buf.copy_from_slice(b);
+        *w = u64::from_le_bytes(buf);
+    }
+
+    stats::query(&words, query)
+}
+
+/// print num records of live allocations of EL0 at addr,
+/// return the number of live allocations
+pub fn sys_mem_leaks(addr: u64, num: u64) -> u64 {
+    if num == 0 {
+        uart::puts("allocations are not traced, enable the mem_trace feature\n");
+        return 0;
+    }
+
+    let mut n = 0;
+    let mut bytes = [0; trace::RECORD_SIZE];
+    for i in 0..num {
+        if !uaccess::copy_from_user(&mut bytes, addr + i * trace::RECORD_SIZE as u64) {
+            break;
+        }
+        if trace::print(&bytes) {
+            n += 1;
+        }
+    }
+    n
+}
+
+/// sleep for usec microseconds
+pub fn sys_sleep(usec: u64) {
+    if sched::is_ready() {
+        sched::sleep_usec(usec);
+    } else {
+        ktimer::sleep_usec(usec);
+    }
+}
+
+/// run pc at EL0 on the stack sp as a new task, return the task ID or !0
+///
+/// the stack must be in EL0's heap, because mmu::is_el0 identifies EL0 by the stack
+pub fn sys_spawn(pc: u64, arg: u64, sp: u64) -> u64 {
+    let addr = mmu::get_memory_map();
+    if !(addr.el0_heap_start < sp && sp <= addr.el0_heap_end) {
+        return !0;
+    }
+
+    match sched::spawn_user("user", pc, arg, sp) {
+        Some(id) => id as u64,
+        None => !0,
+    }
+}
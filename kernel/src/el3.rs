@@ -1,7 +1,6 @@
 use crate::aarch64::syscall::smc;
-use crate::aarch64::{context, cpu, mmu};
+use crate::aarch64::{context, cpu, mmu, percpu};
 use crate::driver::delays;
-use crate::driver::topology;
 use crate::psci;
 
 extern "C" {
@@ -11,12 +10,12 @@ extern "C" {
 
 pub fn el3_to_el1() -> ! {
     let addr = mmu::get_memory_map();
-    let idx = topology::core_pos() as u64;
+    let idx = percpu::index() as u64;
     let stack = addr.stack_el1_start - addr.stack_size * idx + mmu::EL1_ADDR_OFFSET;
     let entry = el1_entry as *const () as u64;
     let stack_el3 = mmu::get_stack_firm_start() - addr.stack_size * idx;
 
-    let ctx = context::get_ctx(idx as usize, true);
+    let ctx = context::get_my_ctx(true);
     ctx.set_sp_el1(stack); // set stack pointer of EL1
     ctx.set_elr(entry); // set entry point of EL1
     ctx.restore_and_eret(stack_el3); // enter EL1
@@ -38,13 +37,12 @@ pub fn switch_world(ctx: &context::GpRegs, sp: usize, to_secure: bool) {
         return;
     }
 
-    let idx = topology::core_pos();
-    let c = context::get_ctx(idx, !to_secure);
+    let c = context::get_my_ctx(!to_secure);
     c.save_fpregs(); // save SIMD registers
     c.save_sysregs(); // save system registers
     c.save_gpregs(ctx); // save general purpose registers
 
-    let c = context::get_ctx(idx, to_secure);
+    let c = context::get_my_ctx(to_secure);
     c.restore_and_eret(sp as u64);
 }
 
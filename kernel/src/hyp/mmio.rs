@@ -0,0 +1,105 @@
+// emulation of memory mapped devices for the guest
+//
+// The IPA windows of emulated devices are not mapped by the stage 2 table,
+// so the guest's loads and stores to them cause data aborts to EL2.
+// The aborted access is decoded from ESR_EL2 and passed to the device model.
+
+use crate::aarch64::context::GpRegs;
+
+/// model of a device, offset is relative to the base of the window
+pub trait MmioDevice {
+    fn read(&mut self, offset: u64, size: u64) -> u64;
+    fn write(&mut self, offset: u64, size: u64, val: u64);
+}
+
+const MAX_DEVICES: usize = 8;
+
+// ISS of data aborts
+const ISS_ISV: u64 = 1 << 24; // the syndrome below is valid
+const ISS_SAS_SHIFT: u64 = 22; // [23:22]: access size, 1 << SAS bytes
+const ISS_SSE: u64 = 1 << 21; // sign extension
+const ISS_SRT_SHIFT: u64 = 16; // [20:16]: register number
+const ISS_SF: u64 = 1 << 15; // 64-bit register
+const ISS_WNR: u64 = 1 << 6; // write
+const ISS_DFSC_MASK: u64 = 0b111111;
+const ISS_DFSC_TRANS: u64 = 0b000100; // translation fault, [1:0] is the level
+
+struct Region {
+    base: u64,
+    size: u64,
+    dev: &'static mut dyn MmioDevice,
+}
+
+const NO_REGION: Option<Region> = None;
+static mut REGIONS: [Option<Region>; MAX_DEVICES] = [NO_REGION; MAX_DEVICES];
+
+/// emulate dev at [base, base + size) of IPA,
+/// the window must not be mapped by the stage 2 table
+pub fn register(base: u64, size: u64, dev: &'static mut dyn MmioDevice) {
+    let regions = unsafe { &mut REGIONS };
+
+    for r in regions.iter() {
+        if let Some(r) = r {
+            if base < r.base + r.size && r.base < base + size {
+                panic!("overlapped MMIO regions");
+            }
+        }
+    }
+
+    for r in regions.iter_mut() {
+        if r.is_none() {
+            *r = Some(Region { base, size, dev });
+            return;
+        }
+    }
+
+    panic!("too many emulated devices");
+}
+
+/// emulate the access aborted at ipa,
+/// return false if no device is at ipa or the access cannot be decoded
+pub fn handle(ctx: &mut GpRegs, esr: u64, ipa: u64) -> bool {
+    // only translation faults are caused by emulated devices
+    if esr & ISS_DFSC_MASK & !0b11 != ISS_DFSC_TRANS {
+        return false;
+    }
+
+    // ISV is 0 for load/store pair, writeback and so on
+    if esr & ISS_ISV == 0 {
+        return false;
+    }
+
+    let regions = unsafe { &mut REGIONS };
+    let region = match regions.iter_mut().find(|r| match r {
+        Some(r) => r.base <= ipa && ipa < r.base + r.size,
+        None => false,
+    }) {
+        Some(Some(r)) => r,
+        _ => return false,
+    };
+
+    let size = 1 << ((esr >> ISS_SAS_SHIFT) & 0b11);
+    let srt = ((esr >> ISS_SRT_SHIFT) & 0b11111) as usize;
+    let mask = if size == 8 { !0 } else { (1 << (size * 8)) - 1 };
+    let offset = ipa - region.base;
+
+    if esr & ISS_WNR != 0 {
+        region.dev.write(offset, size, ctx.get(srt) & mask);
+    } else {
+        let mut val = region.dev.read(offset, size) & mask;
+
+        if esr & ISS_SSE != 0 && size < 8 {
+            let shift = 64 - size * 8;
+            val = (((val << shift) as i64) >> shift) as u64;
+        }
+
+        if esr & ISS_SF == 0 {
+            val &= 0xffffffff; // Wt
+        }
+
+        ctx.set(srt, val);
+    }
+
+    ctx.elr += 4; // ELR points the aborted instruction
+    true
+}
@@ -0,0 +1,218 @@
+// minimal type-1 hypervisor at EL2
+//
+// The guest runs at EL1 in an isolated IPA space translated by stage 2 tables.
+// Its data aborts on stage 2 faults, HVCs, SMCs and accesses to some system registers
+// are trapped into the handlers below.
+// Currently, our own EL1 kernel is run as the guest.
+//
+// emulated devices are placed out of the physical address map as follows
+//
+// IPA
+// 0x10_0000_0000 +------------------+
+//                | virtual PL011    | 4KiB
+// 0x10_0000_1000 +------------------+
+//                | virtual timer    | 4KiB
+// 0x10_0000_2000 +------------------+
+//
+// if the GIC has the virtualization extensions, the virtual CPU interface is
+// mapped at the IPA of GICC, and interrupts are injected by the vGIC.
+// GICH and the physical GICV are not mapped, and GICD is emulated at its own IPA
+
+pub mod mmio;
+pub mod vgic;
+pub mod vgicd;
+pub mod vtimer;
+pub mod vuart;
+
+use crate::aarch64::context::GpRegs;
+use crate::aarch64::{cpu, mmu};
+use crate::driver::arm::gic::v2;
+use crate::driver::uart;
+use crate::{el2, print_msg};
+
+const VMID: u64 = 1;
+
+pub const VUART_BASE: u64 = 0x10_0000_0000;
+pub const VTIMER_BASE: u64 = VUART_BASE + vuart::VUART_SIZE;
+
+static mut VUART: vuart::VPl011 = vuart::VPl011::new();
+static mut VTIMER: vtimer::VTimer = vtimer::VTimer::new();
+static mut VGICD: vgicd::VGicd = vgicd::VGicd::new();
+
+// ESR_EL2
+const ESR_EC_SHIFT: u64 = 26;
+const ESR_EC_MASK: u64 = 0b111111;
+const ESR_EC_HVC64: u64 = 0b010110;
+const ESR_EC_SMC64: u64 = 0b010111;
+const ESR_EC_SYSREG: u64 = 0b011000;
+const ESR_EC_DATA_ABORT_LOWER: u64 = 0b100100;
+
+// ISS of trapped MSR, MRS and system instructions
+const ISS_SYSREG_DIR_READ: u64 = 1; // [0]: 1 is MRS
+const ISS_SYSREG_RT_SHIFT: u64 = 5; // [9:5]: Rt
+
+// key of ISS, Op0[21:20], Op2[19:17], Op1[16:14], CRn[13:10] and CRm[4:1]
+const ISS_SYSREG_MASK: u64 = 0x3ffc1e;
+
+const fn sysreg_key(op0: u64, op1: u64, crn: u64, crm: u64, op2: u64) -> u64 {
+    op0 << 20 | op2 << 17 | op1 << 14 | crn << 10 | crm << 1
+}
+
+const SYSREG_ID_AA64PFR0_EL1: u64 = sysreg_key(3, 0, 0, 4, 0);
+const SYSREG_ID_AA64PFR1_EL1: u64 = sysreg_key(3, 0, 0, 4, 1);
+const SYSREG_ID_AA64DFR0_EL1: u64 = sysreg_key(3, 0, 0, 5, 0);
+const SYSREG_ID_AA64ISAR0_EL1: u64 = sysreg_key(3, 0, 0, 6, 0);
+const SYSREG_ID_AA64ISAR1_EL1: u64 = sysreg_key(3, 0, 0, 6, 1);
+const SYSREG_ID_AA64MMFR0_EL1: u64 = sysreg_key(3, 0, 0, 7, 0);
+const SYSREG_ID_AA64MMFR1_EL1: u64 = sysreg_key(3, 0, 0, 7, 1);
+const SYSREG_ID_AA64MMFR2_EL1: u64 = sysreg_key(3, 0, 0, 7, 2);
+const SYSREG_ACTLR_EL1: u64 = sysreg_key(3, 0, 1, 0, 1);
+
+// hypercalls, the function ID is passed by x0
+pub const HVC_VERSION: u64 = 0; // return the version of the hypervisor
+pub const HVC_PUTC: u64 = 1; // print x1 as a character
+
+const HYP_VERSION: u64 = 1;
+const NOT_SUPPORTED: u64 = !0;
+
+static mut STAGE2: Option<mmu::TTable> = None;
+
+/// whether a guest is running under the hypervisor
+pub fn is_enabled() -> bool {
+    cpu::hcr_el2::get() & cpu::HCR_VM_BIT != 0
+}
+
+/// the stage 2 table of the guest
+pub fn get_stage2() -> Option<&'static mut mmu::TTable> {
+    unsafe { STAGE2.as_mut() }
+}
+
+/// build the stage 2 table and enter the guest
+pub fn run_guest() {
+    let mut table = mmu::init_stage2();
+    let vgic = vgic::init(&mut table);
+    mmu::set_reg_stage2(table.root(), VMID);
+    unsafe { STAGE2 = Some(table) };
+
+    // the windows of emulated devices are not mapped by the stage 2 table
+    unsafe {
+        mmio::register(VUART_BASE, vuart::VUART_SIZE, &mut VUART);
+        mmio::register(VTIMER_BASE, vtimer::VTIMER_SIZE, &mut VTIMER);
+        if vgic {
+            mmio::register(v2::get_distif_base() as u64, v2::GICD_SIZE, &mut VGICD);
+        }
+    }
+
+    // trap SMC, ID group 3 registers and ACTLR_EL1
+    let mut hcr = cpu::hcr_el2::get()
+        | cpu::HCR_RW_BIT
+        | cpu::HCR_VM_BIT
+        | cpu::HCR_SWIO_BIT
+        | cpu::HCR_TSC_BIT
+        | cpu::HCR_TID3_BIT
+        | cpu::HCR_TACR_BIT;
+
+    // take physical IRQs to EL2 and deliver virtual IRQs to EL1
+    if vgic {
+        hcr |= cpu::HCR_IMO_BIT;
+        print_msg("vGIC", "enabled");
+    }
+
+    cpu::hcr_el2::set(hcr);
+    cpu::isb();
+
+    el2::el2_to_el1();
+}
+
+/// handle a synchronous exception from the guest
+pub fn handle_sync(ctx: &mut GpRegs) {
+    let esr = cpu::esr_el2::get();
+    match (esr >> ESR_EC_SHIFT) & ESR_EC_MASK {
+        ESR_EC_HVC64 => handle_hvc(ctx),
+        ESR_EC_SMC64 => {
+            // no secure monitor for the guest
+            ctx.x0 = NOT_SUPPORTED;
+            ctx.elr += 4; // ELR points SMC
+        }
+        ESR_EC_SYSREG => handle_sysreg(ctx, esr),
+        ESR_EC_DATA_ABORT_LOWER => handle_data_abort(ctx, esr),
+        _ => {
+            uart::puts("unexpected exception from the guest\nESR = 0x");
+            uart::hex(esr);
+            uart::puts("\nELR = 0x");
+            uart::hex(ctx.elr);
+            uart::puts("\n");
+            panic!("unexpected exception from the guest");
+        }
+    }
+
+    vgic::sync();
+}
+
+/// handle a physical IRQ taken while the guest is running
+pub fn handle_irq() {
+    vgic::handle_irq();
+}
+
+/// ELR points the next instruction of HVC
+fn handle_hvc(ctx: &mut GpRegs) {
+    ctx.x0 = match ctx.x0 {
+        HVC_VERSION => HYP_VERSION,
+        HVC_PUTC => {
+            let c = [ctx.x1 as u8];
+            if let Ok(s) = core::str::from_utf8(&c) {
+                uart::puts(s);
+            }
+            0
+        }
+        _ => NOT_SUPPORTED,
+    };
+}
+
+/// emulate MSR and MRS trapped by HCR_EL2.TID3 and TACR
+fn handle_sysreg(ctx: &mut GpRegs, esr: u64) {
+    let rt = ((esr >> ISS_SYSREG_RT_SHIFT) & 0b11111) as usize;
+    let key = esr & ISS_SYSREG_MASK;
+
+    if esr & ISS_SYSREG_DIR_READ != 0 {
+        let val = match key {
+            SYSREG_ID_AA64PFR0_EL1 => cpu::id_aa64pfr0_el1::get(),
+            SYSREG_ID_AA64PFR1_EL1 => cpu::id_aa64pfr1_el1::get(),
+            SYSREG_ID_AA64DFR0_EL1 => cpu::id_aa64dfr0_el1::get(),
+            SYSREG_ID_AA64ISAR0_EL1 => cpu::id_aa64isar0_el1::get(),
+            SYSREG_ID_AA64ISAR1_EL1 => cpu::id_aa64isar1_el1::get(),
+            SYSREG_ID_AA64MMFR0_EL1 => cpu::id_aa64mmfr0_el1::get(),
+            SYSREG_ID_AA64MMFR1_EL1 => cpu::id_aa64mmfr1_el1::get(),
+            SYSREG_ID_AA64MMFR2_EL1 => cpu::id_aa64mmfr2_el1::get(),
+            SYSREG_ACTLR_EL1 => cpu::actlr_el1::get(),
+            _ => 0, // read as zero
+        };
+        ctx.set(rt, val);
+    } else {
+        match key {
+            SYSREG_ACTLR_EL1 => cpu::actlr_el1::set(ctx.get(rt)),
+            _ => (), // write ignored
+        }
+    }
+
+    ctx.elr += 4; // ELR points the trapped instruction
+}
+
+/// stage 2 fault, emulate MMIO if a device is at the faulting IPA
+fn handle_data_abort(ctx: &mut GpRegs, esr: u64) {
+    // HPFAR_EL2[43:4] holds IPA[47:12]
+    let ipa = (cpu::hpfar_el2::get() >> 4) << 12 | cpu::far_el2::get() & 0xfff;
+
+    if mmio::handle(ctx, esr, ipa) {
+        return;
+    }
+
+    uart::puts("data abort from the guest\nIPA = 0x");
+    uart::hex(ipa);
+    uart::puts("\nESR = 0x");
+    uart::hex(esr);
+    uart::puts("\nELR = 0x");
+    uart::hex(ctx.elr);
+    uart::puts("\n");
+    panic!("unhandled data abort from the guest");
+}
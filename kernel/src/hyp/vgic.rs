@@ -0,0 +1,217 @@
+// virtual GIC, interrupts are injected to the guest through the list registers of GICv2
+//
+// Physical interrupts are taken to EL2 by HCR_EL2.IMO, and forwarded to the guest
+// as hardware interrupts, so the guest deactivates them through GICV.
+// Interrupts of emulated devices are level sensitive, and sampled on every exit
+// from the guest. The hypervisor timer wakes EL2 at the deadline of the virtual timer.
+
+use super::{VTIMER, VUART};
+use crate::aarch64::{cpu, mmu};
+use crate::driver::arm::gic::v2::{self, virt};
+use crate::driver::uart;
+
+// interrupt IDs seen by the guest
+pub const VTIMER_INTID: u32 = 27; // same as the virtual timer PPI
+pub const VUART_INTID: u32 = 33;
+
+// the hypervisor timer PPI
+pub const HYP_TIMER_INTID: u32 = 26;
+
+// CNTHP_CTL_EL2
+const CNTHP_CTL_ENABLE: u64 = 1;
+
+const VIRQ_PRIORITY: u32 = 0xa0;
+
+static mut ENABLED: bool = false;
+
+/// map GICV to the guest at the address of GICC, hide GICH and GICD from the guest,
+/// and enable the virtual interface,
+/// return false if the GIC does not have the virtualization extensions
+pub fn init(table: &mut mmu::TTable) -> bool {
+    if !virt::is_present() {
+        return false;
+    }
+
+    let gicd = v2::get_distif_base() as u64;
+    let gicc = v2::get_cpuif_base() as u64;
+    let gich = virt::get_gich_base() as u64;
+    let gicv = virt::get_gicv_base() as u64;
+    let page = mmu::STAGE2_GRANULE.size();
+    if (gicd | gicc | gich | gicv) & (page - 1) != 0 {
+        uart::puts("vGIC: GIC cannot be mapped by the granule\n");
+        return false;
+    }
+
+    // the guest accesses GICV instead of GICC
+    mmu::unmap_stage2(table, gicc, virt::GICV_SIZE);
+    mmu::map_stage2_dev(table, gicc, gicv, virt::GICV_SIZE);
+
+    // the list registers and the physical GICV are only for the hypervisor,
+    // and GICD is emulated by vgicd
+    mmu::unmap_stage2(table, gich, virt::GICH_SIZE);
+    mmu::unmap_stage2(table, gicv, virt::GICV_SIZE);
+    mmu::unmap_stage2(table, gicd, v2::GICD_SIZE);
+
+    virt::enable();
+    v2::cpuif_enable_hyp();
+    v2::enable_interrupt(virt::MAINTENANCE_INTR_ID);
+    v2::enable_interrupt(HYP_TIMER_INTID);
+
+    unsafe { ENABLED = true };
+    true
+}
+
+fn lr_priority() -> u32 {
+    (VIRQ_PRIORITY >> 3) << virt::LR_PRIORITY_SHIFT
+}
+
+enum Inject {
+    Written,
+    Pending, // the virtual interrupt is already pending or active
+    Full,    // every list register is in use
+}
+
+/// write val to an empty list register
+fn inject(val: u32) -> Inject {
+    let vid = val & virt::LR_VIRTUALID_MASK;
+    let n = virt::num_lrs();
+
+    for i in 0..n {
+        let lr = virt::read_lr(i);
+        if lr & virt::LR_STATE_MASK != 0 && lr & virt::LR_VIRTUALID_MASK == vid {
+            return Inject::Pending;
+        }
+    }
+
+    let elrsr = virt::read_elrsr();
+    for i in 0..n {
+        if elrsr & (1 << i) != 0 {
+            virt::write_lr(i, val);
+            return Inject::Written;
+        }
+    }
+
+    Inject::Full
+}
+
+/// withdraw a pending virtual interrupt whose level is deasserted
+fn retract(vid: u32) {
+    for i in 0..virt::num_lrs() {
+        let lr = virt::read_lr(i);
+        if lr & virt::LR_HW == 0
+            && lr & virt::LR_VIRTUALID_MASK == vid
+            && lr & virt::LR_STATE_MASK == virt::LR_STATE_PENDING
+        {
+            virt::write_lr(i, 0);
+        }
+    }
+}
+
+/// inject or retract a level sensitive interrupt of an emulated device
+fn set_level(vid: u32, level: bool) {
+    if !level {
+        retract(vid);
+        return;
+    }
+
+    // a maintenance interrupt on EOI samples the level again
+    let val = vid | virt::LR_EOI | lr_priority() | virt::LR_STATE_PENDING;
+    if let Inject::Full = inject(val) {
+        // sample again when the list registers become empty
+        virt::write_hcr(virt::read_hcr() | virt::HCR_UIE);
+    }
+}
+
+/// forward a physical interrupt acknowledged by EL2 to the guest
+fn forward(iar: u32) {
+    let id = v2::get_intr_id(iar);
+    let val =
+        id | id << virt::LR_PHYSICALID_SHIFT | virt::LR_HW | lr_priority() | virt::LR_STATE_PENDING;
+
+    v2::cpuif_end_of_interrupt(iar);
+    match inject(val) {
+        Inject::Written => (),
+        Inject::Pending => {
+            // the guest does not deactivate it through a HW list register
+            v2::cpuif_deactivate(iar);
+        }
+        Inject::Full => {
+            uart::puts("vGIC: list registers are full, dropped interrupt ");
+            uart::decimal(id as u64);
+            uart::puts("\n");
+            v2::cpuif_deactivate(iar);
+        }
+    }
+}
+
+fn maintenance() {
+    let misr = virt::read_misr();
+
+    // free list registers EOIed by the guest
+    if misr & virt::MISR_EOI != 0 {
+        let eisr = virt::read_eisr();
+        for i in 0..virt::num_lrs() {
+            if eisr & (1 << i) != 0 {
+                virt::write_lr(i, 0);
+            }
+        }
+    }
+
+    if misr & virt::MISR_U != 0 {
+        virt::write_hcr(virt::read_hcr() & !virt::HCR_UIE);
+    }
+}
+
+/// sample the interrupt lines of emulated devices,
+/// called on every exit from the guest
+pub fn sync() {
+    if unsafe { !ENABLED } {
+        return;
+    }
+
+    let (timer, uart) = unsafe { (VTIMER.is_pending(), VUART.is_pending()) };
+    set_level(VTIMER_INTID, timer);
+    set_level(VUART_INTID, uart);
+
+    // wake up at the deadline of the virtual timer,
+    // an expired timer is kept pending until the guest changes it
+    let now = cpu::cntpct_el0::get();
+    match unsafe { VTIMER.get_deadline() } {
+        Some(deadline) if now < deadline => {
+            cpu::cnthp_cval_el2::set(deadline);
+            cpu::cnthp_ctl_el2::set(CNTHP_CTL_ENABLE);
+        }
+        _ => cpu::cnthp_ctl_el2::set(0),
+    }
+}
+
+/// handle physical interrupts taken to EL2
+pub fn handle_irq() {
+    if unsafe { !ENABLED } {
+        return;
+    }
+
+    loop {
+        let iar = v2::cpuif_acknowledge();
+        let id = v2::get_intr_id(iar);
+        if id == v2::GIC_SPURIOUS_INTR {
+            break;
+        }
+
+        match id {
+            virt::MAINTENANCE_INTR_ID => {
+                maintenance();
+                v2::cpuif_end_of_interrupt(iar);
+                v2::cpuif_deactivate(iar);
+            }
+            HYP_TIMER_INTID => {
+                cpu::cnthp_ctl_el2::set(0);
+                v2::cpuif_end_of_interrupt(iar);
+                v2::cpuif_deactivate(iar);
+            }
+            _ => forward(iar),
+        }
+    }
+
+    sync();
+}
@@ -0,0 +1,124 @@
+// virtual GIC distributor
+// see ARM Generic Interrupt Controller Architecture Specification v2, 4.3 Distributor register descriptions
+//
+// GICD is not mapped by the stage 2 table, and the guest's accesses are passed to
+// the physical distributor, except for the fields of the interrupts used by the hypervisor.
+// The guest cannot disable the distributor nor change the groups of interrupts.
+
+use core::ptr::{read_volatile, write_volatile};
+
+use super::mmio::MmioDevice;
+use super::vgic;
+use crate::driver::arm::gic::v2::{self, virt};
+
+// registers
+const GICD_CTLR: u64 = 0x000;
+const GICD_IIDR: u64 = 0x008;
+const GICD_IGROUPR: u64 = 0x080;
+const GICD_ISENABLER: u64 = 0x100;
+const GICD_IPRIORITYR: u64 = 0x400;
+const GICD_ITARGETSR: u64 = 0x800;
+const GICD_ICFGR: u64 = 0xc00;
+const GICD_ICFGR_END: u64 = 0xcfc;
+const GICD_SGIR: u64 = 0xf00;
+const GICD_CPENDSGIR: u64 = 0xf10;
+const GICD_SPENDSGIR_END: u64 = 0xf2c;
+const GICD_PIDR4: u64 = 0xfd0; // identification registers
+
+// interrupts hidden from the guest
+const RESERVED: [u32; 4] = [
+    virt::MAINTENANCE_INTR_ID,
+    vgic::HYP_TIMER_INTID,
+    vgic::VTIMER_INTID,
+    vgic::VUART_INTID,
+];
+
+pub struct VGicd;
+
+impl VGicd {
+    pub const fn new() -> VGicd {
+        VGicd
+    }
+}
+
+// the distributor supports byte and word accesses
+fn dist_read(offset: u64, size: u64) -> u64 {
+    let addr = v2::get_distif_base() + offset as usize;
+    match size {
+        1 => unsafe { read_volatile(addr as *const u8) as u64 },
+        4 => unsafe { read_volatile(addr as *const u32) as u64 },
+        _ => 0,
+    }
+}
+
+fn dist_write(offset: u64, size: u64, val: u64) {
+    let addr = v2::get_distif_base() + offset as usize;
+    match size {
+        1 => unsafe { write_volatile(addr as *mut u8, val as u8) },
+        4 => unsafe { write_volatile(addr as *mut u32, val as u32) },
+        _ => (),
+    }
+}
+
+/// the start of the per-interrupt registers at offset and the bits per interrupt
+fn layout(offset: u64) -> Option<(u64, u64)> {
+    match offset {
+        // IGROUPR, set-enable, clear-enable, set-pending, clear-pending, set-active and clear-active
+        GICD_IGROUPR..=0x3ff => Some((offset & !0x7f, 1)),
+        GICD_IPRIORITYR..=0x7ff => Some((GICD_IPRIORITYR, 8)),
+        GICD_ITARGETSR..=0xbff => Some((GICD_ITARGETSR, 8)),
+        GICD_ICFGR..=GICD_ICFGR_END => Some((GICD_ICFGR, 2)),
+        _ => None,
+    }
+}
+
+/// the mask of the fields of the reserved interrupts in the access
+fn reserved_mask(offset: u64, size: u64) -> u64 {
+    let (start, bits) = match layout(offset) {
+        Some(l) => l,
+        None => return 0,
+    };
+
+    let first = (offset - start) * 8 / bits;
+    let count = size * 8 / bits;
+    let field = (1 << bits) - 1;
+
+    let mut mask = 0;
+    for id in RESERVED.iter().map(|id| *id as u64) {
+        if first <= id && id < first + count {
+            mask |= field << ((id - first) * bits);
+        }
+    }
+    mask
+}
+
+impl MmioDevice for VGicd {
+    fn read(&mut self, offset: u64, size: u64) -> u64 {
+        match offset {
+            GICD_CTLR..=GICD_IIDR
+            | GICD_IGROUPR..=GICD_ICFGR_END
+            | GICD_CPENDSGIR..=GICD_SPENDSGIR_END
+            | GICD_PIDR4..=0xffc => dist_read(offset, size) & !reserved_mask(offset, size),
+            _ => 0,
+        }
+    }
+
+    fn write(&mut self, offset: u64, size: u64, val: u64) {
+        match offset {
+            GICD_ISENABLER..=GICD_ICFGR_END => {
+                let mask = reserved_mask(offset, size);
+                if mask == 0 {
+                    dist_write(offset, size, val);
+                } else if offset < GICD_IPRIORITYR {
+                    // writing 0 to the set and clear registers has no effect
+                    dist_write(offset, size, val & !mask);
+                } else {
+                    let old = dist_read(offset, size);
+                    dist_write(offset, size, (old & mask) | (val & !mask));
+                }
+            }
+            GICD_SGIR | GICD_CPENDSGIR..=GICD_SPENDSGIR_END => dist_write(offset, size, val),
+            _ => (), // GICD_CTLR, GICD_IGROUPR, GICD_NSACR and read only registers
+        }
+    }
+}
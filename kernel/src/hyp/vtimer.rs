@@ -0,0 +1,155 @@
+// virtual memory mapped timer, a frame of the generic timer (CNTBaseN)
+// see Arm Architecture Reference Manual, I2.3 The CNTBaseN and CNTEL0BaseN frames
+//
+// the counter is the physical system counter of the host
+
+use super::mmio::MmioDevice;
+use crate::aarch64::cpu;
+
+// registers
+const CNTPCT: u64 = 0x000;
+const CNTVCT: u64 = 0x008;
+const CNTFRQ: u64 = 0x010;
+const CNTVOFF: u64 = 0x018;
+const CNTP_CVAL: u64 = 0x020;
+const CNTP_TVAL: u64 = 0x028;
+const CNTP_CTL: u64 = 0x02c;
+const CNTV_CVAL: u64 = 0x030;
+const CNTV_TVAL: u64 = 0x038;
+const CNTV_CTL: u64 = 0x03c;
+
+// upper halves of 64-bit registers
+const CNTPCT_HI: u64 = CNTPCT + 4;
+const CNTVCT_HI: u64 = CNTVCT + 4;
+const CNTVOFF_HI: u64 = CNTVOFF + 4;
+const CNTP_CVAL_HI: u64 = CNTP_CVAL + 4;
+const CNTV_CVAL_HI: u64 = CNTV_CVAL + 4;
+
+// CNTP_CTL and CNTV_CTL
+const CTL_ENABLE: u64 = 1 << 0;
+const CTL_IMASK: u64 = 1 << 1;
+const CTL_ISTATUS: u64 = 1 << 2;
+
+pub const VTIMER_SIZE: u64 = 0x1000;
+
+struct Timer {
+    cval: u64,
+    ctl: u64,
+}
+
+impl Timer {
+    const fn new() -> Timer {
+        Timer { cval: 0, ctl: 0 }
+    }
+
+    fn get_ctl(&self, now: u64) -> u64 {
+        if self.ctl & CTL_ENABLE != 0 && now >= self.cval {
+            self.ctl | CTL_ISTATUS
+        } else {
+            self.ctl
+        }
+    }
+
+    fn set_ctl(&mut self, val: u64) {
+        self.ctl = val & (CTL_ENABLE | CTL_IMASK);
+    }
+
+    // TVAL is a signed 32-bit down counter
+    fn get_tval(&self, now: u64) -> u64 {
+        self.cval.wrapping_sub(now) & 0xffffffff
+    }
+
+    fn set_tval(&mut self, now: u64, val: u64) {
+        self.cval = now.wrapping_add(val as u32 as i32 as i64 as u64);
+    }
+
+    fn is_pending(&self, now: u64) -> bool {
+        self.get_ctl(now) & (CTL_ISTATUS | CTL_IMASK) == CTL_ISTATUS
+    }
+}
+
+pub struct VTimer {
+    phys: Timer,
+    virt: Timer,
+}
+
+/// 64-bit registers can be accessed by a pair of 32-bit accesses
+fn read64(reg: u64, offset: u64, size: u64) -> u64 {
+    if size == 4 && offset & 4 != 0 {
+        reg >> 32
+    } else {
+        reg
+    }
+}
+
+fn write64(reg: &mut u64, offset: u64, size: u64, val: u64) {
+    if size == 8 {
+        *reg = val;
+    } else if offset & 4 != 0 {
+        *reg = (*reg & 0xffffffff) | (val & 0xffffffff) << 32;
+    } else {
+        *reg = (*reg & !0xffffffff) | (val & 0xffffffff);
+    }
+}
+
+impl VTimer {
+    pub const fn new() -> VTimer {
+        VTimer {
+            phys: Timer::new(),
+            virt: Timer::new(),
+        }
+    }
+
+    /// whether the guest should be interrupted by the physical or virtual timer
+    pub fn is_pending(&self) -> bool {
+        let now = cpu::cntpct_el0::get();
+        self.phys.is_pending(now) || self.virt.is_pending(now)
+    }
+
+    /// the earliest compare value of unmasked timers
+    pub fn get_deadline(&self) -> Option<u64> {
+        let mut deadline = None;
+        for t in [&self.phys, &self.virt].iter() {
+            if t.ctl & (CTL_ENABLE | CTL_IMASK) == CTL_ENABLE {
+                deadline = match deadline {
+                    Some(d) if d <= t.cval => Some(d),
+                    _ => Some(t.cval),
+                };
+            }
+        }
+        deadline
+    }
+}
+
+impl MmioDevice for VTimer {
+    fn read(&mut self, offset: u64, size: u64) -> u64 {
+        // CNTVOFF is 0, so the virtual count equals the physical one
+        let now = cpu::cntpct_el0::get();
+        match offset & !0b11 {
+            CNTPCT | CNTPCT_HI => read64(now, offset, size),
+            CNTVCT | CNTVCT_HI => read64(now, offset, size),
+            CNTFRQ => cpu::cntfrq_el0::get() & 0xffffffff,
+            CNTVOFF | CNTVOFF_HI => 0,
+            CNTP_CVAL | CNTP_CVAL_HI => read64(self.phys.cval, offset, size),
+            CNTP_TVAL => self.phys.get_tval(now),
+            CNTP_CTL => self.phys.get_ctl(now),
+            CNTV_CVAL | CNTV_CVAL_HI => read64(self.virt.cval, offset, size),
+            CNTV_TVAL => self.virt.get_tval(now),
+            CNTV_CTL => self.virt.get_ctl(now),
+            _ => 0,
+        }
+    }
+
+    fn write(&mut self, offset: u64, size: u64, val: u64) {
+        let now = cpu::cntpct_el0::get();
+        match offset & !0b11 {
+            CNTP_CVAL | CNTP_CVAL_HI => write64(&mut self.phys.cval, offset, size, val),
+            CNTP_TVAL => self.phys.set_tval(now, val),
+            CNTP_CTL => self.phys.set_ctl(val),
+            CNTV_CVAL | CNTV_CVAL_HI => write64(&mut self.virt.cval, offset, size, val),
+            CNTV_TVAL => self.virt.set_tval(now, val),
+            CNTV_CTL => self.virt.set_ctl(val),
+            _ => (), // CNTPCT, CNTVCT, CNTFRQ and CNTVOFF are read only
+        }
+    }
+}
@@ -0,0 +1,134 @@
+// virtual PL011 UART multiplexed onto the console of the hypervisor
+// see ARM PrimeCell UART (PL011) Technical Reference Manual
+
+use super::mmio::MmioDevice;
+use crate::driver::uart;
+
+// registers
+const UART_DR: u64 = 0x000; // data
+const UART_RSR: u64 = 0x004; // receive status / error clear
+const UART_FR: u64 = 0x018; // flag
+const UART_IBRD: u64 = 0x024; // integer baud rate
+const UART_FBRD: u64 = 0x028; // fractional baud rate
+const UART_LCRH: u64 = 0x02c; // line control
+const UART_CR: u64 = 0x030; // control
+const UART_IFLS: u64 = 0x034; // interrupt FIFO level select
+const UART_IMSC: u64 = 0x038; // interrupt mask set/clear
+const UART_RIS: u64 = 0x03c; // raw interrupt status
+const UART_MIS: u64 = 0x040; // masked interrupt status
+const UART_ICR: u64 = 0x044; // interrupt clear
+const UART_PERIPH_ID: u64 = 0xfe0; // peripheral and PrimeCell identification
+
+// UART_FR
+const FR_TXFE: u64 = 1 << 7; // transmit FIFO empty
+const FR_RXFE: u64 = 1 << 4; // receive FIFO empty
+
+// UART_RIS, UART_MIS, UART_IMSC and UART_ICR
+const INT_TX: u64 = 1 << 5;
+const INT_RX: u64 = 1 << 4;
+
+// UART_CR
+const CR_UARTEN: u64 = 1 << 0;
+const CR_TXE: u64 = 1 << 8;
+const CR_RXE: u64 = 1 << 9;
+
+// UARTPeriphID0-3 and UARTPCellID0-3
+const ID: [u64; 8] = [0x11, 0x10, 0x14, 0x00, 0x0d, 0xf0, 0x05, 0xb1];
+
+pub const VUART_SIZE: u64 = 0x1000;
+
+pub struct VPl011 {
+    rx: Option<u8>, // 1 byte receive FIFO
+    ibrd: u64,
+    fbrd: u64,
+    lcrh: u64,
+    cr: u64,
+    ifls: u64,
+    imsc: u64,
+}
+
+impl VPl011 {
+    pub const fn new() -> VPl011 {
+        VPl011 {
+            rx: None,
+            ibrd: 0,
+            fbrd: 0,
+            lcrh: 0,
+            cr: CR_UARTEN | CR_TXE | CR_RXE, // reset value
+            ifls: 0b010010,
+            imsc: 0,
+        }
+    }
+
+    /// poll the physical UART
+    fn fill(&mut self) {
+        if self.rx.is_none() && self.cr & CR_RXE != 0 {
+            self.rx = uart::try_recv().map(|c| c as u8);
+        }
+    }
+
+    /// the transmit FIFO is always empty because writes go to the console immediately
+    fn raw_int(&mut self) -> u64 {
+        self.fill();
+        if self.rx.is_some() {
+            INT_TX | INT_RX
+        } else {
+            INT_TX
+        }
+    }
+
+    /// whether the guest should be interrupted
+    pub fn is_pending(&mut self) -> bool {
+        self.raw_int() & self.imsc != 0
+    }
+}
+
+impl MmioDevice for VPl011 {
+    fn read(&mut self, offset: u64, _size: u64) -> u64 {
+        match offset {
+            UART_DR => {
+                self.fill();
+                match self.rx.take() {
+                    Some(c) => c as u64,
+                    None => 0,
+                }
+            }
+            UART_RSR => 0,
+            UART_FR => {
+                self.fill();
+                if self.rx.is_some() {
+                    FR_TXFE
+                } else {
+                    FR_TXFE | FR_RXFE
+                }
+            }
+            UART_IBRD => self.ibrd,
+            UART_FBRD => self.fbrd,
+            UART_LCRH => self.lcrh,
+            UART_CR => self.cr,
+            UART_IFLS => self.ifls,
+            UART_IMSC => self.imsc,
+            UART_RIS => self.raw_int(),
+            UART_MIS => self.raw_int() & self.imsc,
+            UART_PERIPH_ID..=0xffc => ID[((offset - UART_PERIPH_ID) >> 2) as usize],
+            _ => 0,
+        }
+    }
+
+    fn write(&mut self, offset: u64, _size: u64, val: u64) {
+        match offset {
+            UART_DR => {
+                if self.cr & (CR_UARTEN | CR_TXE) == CR_UARTEN | CR_TXE {
+                    uart::putc(val as u8);
+                }
+            }
+            UART_IBRD => self.ibrd = val & 0xffff,
+            UART_FBRD => self.fbrd = val & 0x3f,
+            UART_LCRH => self.lcrh = val & 0xff,
+            UART_CR => self.cr = val & 0xff87,
+            UART_IFLS => self.ifls = val & 0x3f,
+            UART_IMSC => self.imsc = val & 0x7ff,
+            _ => (), // UART_RSR, UART_ICR and read only registers
+        }
+    }
+}
@@ -0,0 +1,137 @@
+// inter-processor interrupts of EL1
+//
+// Cores are given as bit masks of core positions, and SGIs are sent only to the cores
+// which have called init. SGI IDs are assigned as follows,
+// and the others are free for drivers through driver::irq::set_sgi_handler.
+// - SGI_RESCHED: the target switches tasks at the end of the IRQ
+// - SGI_CALL: the target runs the function of a cross-call
+//
+// A cross-call runs a function on the target cores with IRQs masked, and waits for all of them.
+// One cross-call runs at once, and the caller must not mask IRQs,
+// otherwise two cores calling each other wait forever.
+
+use crate::aarch64::{cpu, lock::IrqMask, lock::Mutex, percpu};
+use crate::driver::irq;
+use crate::driver::topology::CORE_COUNT;
+use crate::sched;
+
+use core::hint::spin_loop;
+use core::sync::atomic::{AtomicU32, Ordering};
+
+pub const SGI_RESCHED: u32 = 0;
+pub const SGI_CALL: u32 = 1;
+
+// cores which have called init
+static ONLINE: AtomicU32 = AtomicU32::new(0);
+
+// the cross-call, written under CALL_LOCK and published by PENDING
+static CALL_LOCK: Mutex<()> = Mutex::new(());
+static mut CALL_FUNC: fn(usize) = nop;
+static mut CALL_ARG: usize = 0;
+static PENDING: AtomicU32 = AtomicU32::new(0); // cores which have not run the call
+
+fn nop(_: usize) {}
+
+/// receive IPIs on the calling core, called on each core at EL1 after driver::timer::init
+pub fn init() {
+    irq::set_sgi_handler(SGI_RESCHED, handle_resched);
+    irq::set_sgi_handler(SGI_CALL, handle_call);
+    ONLINE.fetch_or(1 << percpu::index(), Ordering::Release);
+}
+
+/// all the cores
+pub fn all() -> u32 {
+    (1 << CORE_COUNT) - 1
+}
+
+/// all the cores but the calling one
+pub fn others() -> u32 {
+    all() & !(1 << percpu::index())
+}
+
+/// send the SGI to the online cores of cores
+pub fn send(id: u32, cores: u32) {
+    let cores = cores & ONLINE.load(Ordering::Acquire);
+    if cores != 0 {
+        irq::send_sgi(id, cores);
+    }
+}
+
+/// make the cores switch tasks, for example to run a task woken by another core
+pub fn resched(cores: u32) {
+    send(SGI_RESCHED, cores);
+}
+
+fn handle_resched(_: u32) {
+    sched::set_need_resched();
+}
+
+/// run func(arg) on the online cores of cores, including the calling core,
+/// and return after all of them have returned
+pub fn call(cores: u32, func: fn(usize), arg: usize) {
+    if cpu::daif::get() & (1 << 7) != 0 {
+        panic!("cross-call with IRQs masked");
+    }
+
+    // IRQs are taken while waiting, so calls from other cores are served
+    let _lock = CALL_LOCK.lock();
+    {
+        // the calling core must not change until the local call
+        let _mask = IrqMask::new();
+        let me = 1 << percpu::index();
+        let remote = cores & !me & ONLINE.load(Ordering::Acquire);
+
+        unsafe {
+            CALL_FUNC = func;
+            CALL_ARG = arg;
+        }
+        PENDING.store(remote, Ordering::Release);
+        send(SGI_CALL, remote);
+
+        if cores & me != 0 {
+            func(arg);
+        }
+    }
+
+    while PENDING.load(Ordering::Acquire) != 0 {
+        spin_loop();
+    }
+}
+
+fn handle_call(_: u32) {
+    let me = 1 << percpu::index();
+    if PENDING.load(Ordering::Acquire) & me == 0 {
+        return; // the call has been served
+    }
+
+    let (func, arg) = unsafe { (CALL_FUNC, CALL_ARG) };
+    func(arg);
+    PENDING.fetch_and(!me, Ordering::Release);
+}
+
+fn flush_page(arg: usize) {
+    let arg = arg as u64;
+    cpu::tlbi_vae1(arg >> 48, (arg & 0xFFF_FFFF_FFFF) << 12);
+    cpu::dsb_nsh();
+    cpu::isb();
+}
+
+fn flush_asid(asid: usize) {
+    cpu::tlbi_aside1(asid as u64);
+    cpu::dsb_nsh();
+    cpu::isb();
+}
+
+/// invalidate TLB entries of the page tagged with the ASID on the cores,
+/// each core invalidates its own TLB, and they have finished when this returns
+pub fn shootdown_page(cores: u32, asid: u64, vm_addr: u64) {
+    let arg = asid << 48 | (vm_addr >> 12) & 0xFFF_FFFF_FFFF;
+    cpu::dsb_ishst(); // the change of the table is visible to the cores
+    call(cores, flush_page, arg as usize);
+}
+
+/// invalidate TLB entries tagged with the ASID on the cores
+pub fn shootdown_asid(cores: u32, asid: u64) {
+    cpu::dsb_ishst();
+    call(cores, flush_asid, asid as usize);
+}
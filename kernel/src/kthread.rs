@@ -0,0 +1,241 @@
+// kernel threads of EL1
+//
+// A thread runs func(arg) at EL1 as a task of the scheduler on any core,
+// and join waits for it to finish and takes the value returned by func.
+// Stacks are allocated by a page manager from the stack pool of the memory map,
+// and the lowest page of each stack is unmapped as a guard page,
+// so an overflow of the stack causes a data abort instead of breaking other memory.
+// The stack is freed by join, so every thread must be joined.
+//
+// Each thread has MAX_LOCALS words of thread-local storage, see get_local and set_local.
+
+use crate::aarch64::{lock::Mutex, mmu};
+use crate::driver::uart;
+use crate::sched::{self, TaskId};
+
+use allocator::pager::{PageManager, PAGE_SIZE};
+
+/// the maximum number of threads
+pub const MAX_THREADS: usize = 16;
+
+/// the size of a stack, excluding the guard page
+pub const STACK_SIZE: usize = 128 * 1024;
+
+/// the number of words of thread-local storage
+pub const MAX_LOCALS: usize = 4;
+
+// pages of the page manager, the guard page is one of them
+const STACK_PAGES: usize = (STACK_SIZE + PAGE_SIZE - 1) / PAGE_SIZE;
+
+#[derive(Copy, Clone, PartialEq, Eq)]
+enum State {
+    Free,
+    Running,
+    Done,
+}
+
+#[derive(Copy, Clone)]
+struct Thread {
+    state: State,
+    name: &'static str,
+    task: TaskId,
+    func: fn(usize) -> usize,
+    arg: usize,
+    ret: usize,
+    joiner: Option<TaskId>, // the task blocked in join
+    stack: usize,           // the lowest address, which is the guard page
+    locals: [usize; MAX_LOCALS],
+}
+
+fn nop(_: usize) -> usize {
+    0
+}
+
+const EMPTY_THREAD: Thread = Thread {
+    state: State::Free,
+    name: "",
+    task: 0,
+    func: nop,
+    arg: 0,
+    ret: 0,
+    joiner: None,
+    stack: 0,
+    locals: [0; MAX_LOCALS],
+};
+
+/// a thread spawned by spawn, consumed by join
+pub struct Handle {
+    slot: usize,
+}
+
+static THREADS: Mutex<[Thread; MAX_THREADS]> = Mutex::new([EMPTY_THREAD; MAX_THREADS]);
+
+// the lock also serializes changes of the kernel's translation table for guard pages
+static STACKS: Mutex<PageManager> = Mutex::new(PageManager::new());
+
+/// set up the stack pool, called by the primary core at EL1
+pub fn init() {
+    let addr = mmu::get_memory_map();
+    let start = addr.stack_pool_start + mmu::EL1_ADDR_OFFSET;
+    let end = addr.stack_pool_end + mmu::EL1_ADDR_OFFSET;
+    STACKS.lock_irq().set_range(start as usize, end as usize);
+}
+
+/// allocate a stack and unmap its guard page, return the address of the guard page
+fn alloc_stack() -> Option<usize> {
+    let mut pages = STACKS.lock_irq();
+    let stack = pages.alloc_contiguous(STACK_PAGES + 1, PAGE_SIZE)?;
+    mmu::unmap_el1_pages(stack as u64, PAGE_SIZE as u64);
+    Some(stack)
+}
+
+fn free_stack(stack: usize) {
+    let mut pages = STACKS.lock_irq();
+    mmu::map_el1_pages(stack as u64, PAGE_SIZE as u64);
+    pages.free_contiguous(stack, STACK_PAGES + 1);
+}
+
+/// run func(arg) at EL1 as a new thread,
+/// None if there is no free slot, stack or task
+pub fn spawn(name: &'static str, func: fn(usize) -> usize, arg: usize) -> Option<Handle> {
+    let slot = {
+        let mut threads = THREADS.lock_irq();
+        let slot = threads.iter().position(|t| t.state == State::Free)?;
+        threads[slot] = Thread {
+            state: State::Running,
+            name,
+            func,
+            arg,
+            ..EMPTY_THREAD
+        };
+        slot
+    };
+
+    let stack = match alloc_stack() {
+        Some(stack) => stack,
+        None => {
+            THREADS.lock_irq()[slot].state = State::Free;
+            return None;
+        }
+    };
+
+    // the thread waits for the lock until its task is recorded
+    let mut threads = THREADS.lock_irq();
+    let kstack = (stack + PAGE_SIZE) as *mut u8;
+    let size = STACK_PAGES * PAGE_SIZE;
+    let task = match sched::spawn_on_stack(name, thread_main, slot, true, kstack, size) {
+        Some(task) => task,
+        None => {
+            threads[slot].state = State::Free;
+            drop(threads);
+            free_stack(stack);
+            return None;
+        }
+    };
+
+    threads[slot].task = task;
+    threads[slot].stack = stack;
+    Some(Handle { slot })
+}
+
+/// the first code of a thread, run by the scheduler
+fn thread_main(slot: usize) {
+    let (func, arg) = {
+        let threads = THREADS.lock_irq();
+        (threads[slot].func, threads[slot].arg)
+    };
+
+    let ret = func(arg);
+
+    let joiner = {
+        let mut threads = THREADS.lock_irq();
+        let t = &mut threads[slot];
+        t.ret = ret;
+        t.state = State::Done;
+        t.joiner
+    };
+
+    if let Some(task) = joiner {
+        sched::wake(task);
+    }
+}
+
+/// wait for the thread to finish, free its stack and return the value of its func
+pub fn join(handle: Handle) -> usize {
+    let slot = handle.slot;
+    loop {
+        let mut threads = THREADS.lock_irq();
+        let t = &mut threads[slot];
+        if t.state == State::Done {
+            break;
+        }
+
+        t.joiner = Some(sched::current());
+        drop(threads);
+        sched::block();
+    }
+
+    let t = THREADS.lock_irq()[slot];
+
+    // the thread may still be running on its stack before it is switched out
+    while !sched::reap(t.task) {
+        sched::yield_now();
+    }
+    free_stack(t.stack);
+
+    THREADS.lock_irq()[slot] = EMPTY_THREAD;
+    t.ret
+}
+
+/// the name of the thread
+pub fn name(handle: &Handle) -> &'static str {
+    THREADS.lock_irq()[handle.slot].name
+}
+
+/// call f with the thread of the calling task, panic if the caller is not a thread
+fn with_current<R>(f: impl FnOnce(&mut Thread) -> R) -> R {
+    let task = sched::current();
+    let mut threads = THREADS.lock_irq();
+    match threads
+        .iter_mut()
+        .find(|t| t.state == State::Running && t.task == task)
+    {
+        Some(t) => f(t),
+        None => panic!("not a kernel thread"),
+    }
+}
+
+/// the name of the calling thread
+pub fn current_name() -> &'static str {
+    with_current(|t| t.name)
+}
+
+/// the word at key of the thread-local storage of the calling thread, 0 at first
+pub fn get_local(key: usize) -> usize {
+    with_current(|t| t.locals[key])
+}
+
+/// set the word at key of the thread-local storage of the calling thread
+pub fn set_local(key: usize, val: usize) {
+    with_current(|t| t.locals[key] = val)
+}
+
+/// print the threads and their stacks, for debugging
+pub fn print() {
+    let threads = THREADS.lock_irq();
+    for t in threads.iter().filter(|t| t.state != State::Free) {
+        uart::puts(t.name);
+        uart::puts(": task ");
+        uart::decimal(t.task as u64);
+        uart::puts(if t.state == State::Done {
+            ", done"
+        } else {
+            ", running"
+        });
+        uart::puts(", stack 0x");
+        uart::hex((t.stack + PAGE_SIZE) as u64);
+        uart::puts(" - 0x");
+        uart::hex((t.stack + (STACK_PAGES + 1) * PAGE_SIZE) as u64);
+        uart::puts("\n");
+    }
+}
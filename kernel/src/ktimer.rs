@@ -0,0 +1,347 @@
+// kernel timer service of EL1
+//
+// Each core has a min-heap of the deadlines of its timers, and the EL1 physical timer
+// of the core (driver::timer) is armed at the earliest one, so there is no periodic tick.
+// A timer runs on the core which added it, and it can be cancelled from any core.
+// - callbacks of Context::Irq are called by the timer interrupt with IRQs masked
+// - callbacks of Context::Deferred are called by run_deferred after interrupts are
+//   handled, with IRQs unmasked and preemption disabled, if the interrupted code
+//   may be preempted
+//
+// Deadlines are in ticks of the physical counter, see driver::timer::ticks_per_sec.
+
+use crate::aarch64::preempt::{self, PreemptGuard};
+use crate::aarch64::{cpu, lock::IrqMask, lock::Mutex, percpu};
+use crate::driver::timer::{self, Timer};
+use crate::driver::topology::CORE_COUNT;
+
+use core::hint::spin_loop;
+
+/// the maximum number of timers of a core
+pub const MAX_TIMERS: usize = 64;
+
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub enum Context {
+    Irq,
+    Deferred,
+}
+
+/// a timer added by add_timer
+#[derive(Copy, Clone)]
+pub struct TimerId {
+    core: usize,
+    slot: usize,
+    seq: u64, // distinguishes timers reusing the slot
+}
+
+#[derive(Copy, Clone, PartialEq, Eq)]
+enum State {
+    Free,
+    Queued(usize), // index in the heap
+    Deferred,      // expired, waiting for run_deferred
+}
+
+#[derive(Copy, Clone)]
+struct Slot {
+    state: State,
+    deadline: u64,
+    callback: fn(usize),
+    arg: usize,
+    context: Context,
+    seq: u64,
+}
+
+fn nop(_: usize) {}
+
+const EMPTY_SLOT: Slot = Slot {
+    state: State::Free,
+    deadline: 0,
+    callback: nop,
+    arg: 0,
+    context: Context::Irq,
+    seq: 0,
+};
+
+struct Queue {
+    slots: [Slot; MAX_TIMERS],
+    heap: [usize; MAX_TIMERS], // indices of slots ordered by deadlines
+    len: usize,
+    seq: u64,
+}
+
+const EMPTY_QUEUE: Mutex<Queue> = Mutex::new(Queue::new());
+static QUEUES: [Mutex<Queue>; CORE_COUNT] = [EMPTY_QUEUE; CORE_COUNT];
+
+static mut READY: [bool; CORE_COUNT] = [false; CORE_COUNT];
+
+impl Queue {
+    const fn new() -> Queue {
+        Queue {
+            slots: [EMPTY_SLOT; MAX_TIMERS],
+            heap: [0; MAX_TIMERS],
+            len: 0,
+            seq: 0,
+        }
+    }
+
+    fn deadline(&self, pos: usize) -> u64 {
+        self.slots[self.heap[pos]].deadline
+    }
+
+    fn put(&mut self, pos: usize, slot: usize) {
+        self.heap[pos] = slot;
+        self.slots[slot].state = State::Queued(pos);
+    }
+
+    fn sift_up(&mut self, mut pos: usize) {
+        let slot = self.heap[pos];
+        let deadline = self.slots[slot].deadline;
+        while pos > 0 {
+            let parent = (pos - 1) / 2;
+            if self.deadline(parent) <= deadline {
+                break;
+            }
+            self.put(pos, self.heap[parent]);
+            pos = parent;
+        }
+        self.put(pos, slot);
+    }
+
+    fn sift_down(&mut self, mut pos: usize) {
+        let slot = self.heap[pos];
+        let deadline = self.slots[slot].deadline;
+        loop {
+            let mut child = pos * 2 + 1;
+            if child >= self.len {
+                break;
+            }
+            if child + 1 < self.len && self.deadline(child + 1) < self.deadline(child) {
+                child += 1;
+            }
+            if deadline <= self.deadline(child) {
+                break;
+            }
+            self.put(pos, self.heap[child]);
+            pos = child;
+        }
+        self.put(pos, slot);
+    }
+
+    fn push(&mut self, slot: usize) {
+        self.heap[self.len] = slot;
+        self.len += 1;
+        self.sift_up(self.len - 1);
+    }
+
+    /// remove the slot from the heap, the state of the slot is not changed
+    fn remove(&mut self, pos: usize) {
+        self.len -= 1;
+        if pos == self.len {
+            return;
+        }
+
+        self.heap[pos] = self.heap[self.len];
+        if pos > 0 && self.deadline(pos) < self.deadline((pos - 1) / 2) {
+            self.sift_up(pos);
+        } else {
+            self.sift_down(pos);
+        }
+    }
+
+    fn earliest(&self) -> Option<u64> {
+        if self.len == 0 {
+            None
+        } else {
+            Some(self.deadline(0))
+        }
+    }
+
+    /// pop the earliest timer if it has expired
+    fn pop_expired(&mut self, now: u64) -> Option<usize> {
+        if self.earliest()? > now {
+            return None;
+        }
+
+        let slot = self.heap[0];
+        self.remove(0);
+        Some(slot)
+    }
+}
+
+/// arm the physical timer of the calling core at the earliest deadline
+fn rearm(q: &Queue) {
+    match q.earliest() {
+        Some(deadline) => timer::set_oneshot(Timer::Physical, deadline, expire),
+        None => timer::cancel(Timer::Physical),
+    }
+}
+
+/// called on each core at EL1 after driver::timer::init
+pub fn init() {
+    unsafe { READY[percpu::index()] = true };
+}
+
+/// whether timers are available on the calling core
+pub fn is_ready() -> bool {
+    if cpu::get_current_el() != 1 {
+        return false;
+    }
+
+    match percpu::try_index() {
+        Some(idx) => unsafe { READY[idx] },
+        None => false,
+    }
+}
+
+/// the current count of the physical counter
+pub fn now() -> u64 {
+    timer::now(Timer::Physical)
+}
+
+/// call callback(arg) in context on the calling core when the counter reaches deadline,
+/// None if the calling core has MAX_TIMERS timers already
+pub fn add_timer(
+    deadline: u64,
+    context: Context,
+    callback: fn(usize),
+    arg: usize,
+) -> Option<TimerId> {
+    // the task must not be moved to another core before the timer is queued
+    let _mask = IrqMask::new();
+    let core = percpu::index();
+    let mut q = QUEUES[core].lock_irq();
+
+    let slot = q.slots.iter().position(|s| s.state == State::Free)?;
+    q.seq += 1;
+    let seq = q.seq;
+    q.slots[slot] = Slot {
+        state: State::Free,
+        deadline,
+        callback,
+        arg,
+        context,
+        seq,
+    };
+    q.push(slot);
+
+    if q.heap[0] == slot {
+        rearm(&q);
+    }
+
+    Some(TimerId { core, slot, seq })
+}
+
+/// cancel the timer on any core,
+/// return false if its callback has been called or is being called
+pub fn cancel_timer(id: TimerId) -> bool {
+    let mut q = QUEUES[id.core].lock_irq();
+    let s = q.slots[id.slot];
+    if s.seq != id.seq {
+        return false;
+    }
+
+    match s.state {
+        State::Free => return false,
+        State::Queued(pos) => q.remove(pos),
+        State::Deferred => (),
+    }
+    q.slots[id.slot].state = State::Free;
+
+    // the timer of another core fires at the old deadline, and is armed again then
+    if id.core == percpu::index() {
+        rearm(&q);
+    }
+
+    true
+}
+
+/// called by driver::timer on the expiry of the physical timer
+fn expire() {
+    let core = percpu::index();
+    loop {
+        let mut q = QUEUES[core].lock();
+        let slot = match q.pop_expired(now()) {
+            Some(slot) => slot,
+            None => {
+                rearm(&q);
+                return;
+            }
+        };
+
+        let s = q.slots[slot];
+        if s.context == Context::Deferred {
+            q.slots[slot].state = State::Deferred;
+            continue;
+        }
+
+        q.slots[slot].state = State::Free;
+        drop(q);
+        (s.callback)(s.arg);
+    }
+}
+
+/// call deferred callbacks of the calling core in the order of deadlines,
+/// called by the IRQ handlers of EL1 after the interrupts are handled
+///
+/// Nothing is called if the interrupted code has disabled preemption, because it may hold
+/// locks or per-core data used by the callbacks. They are called at a later interrupt.
+/// Preemption is disabled while they run, so nested interrupts neither call them again
+/// nor switch the task.
+pub fn run_deferred() {
+    if !preempt::is_enabled() {
+        return;
+    }
+
+    let _preempt = PreemptGuard::new();
+    let core = percpu::index();
+    loop {
+        let mut q = QUEUES[core].lock_irq();
+        let found = q
+            .slots
+            .iter()
+            .enumerate()
+            .filter(|(_, s)| s.state == State::Deferred)
+            .min_by_key(|(_, s)| s.deadline)
+            .map(|(i, s)| (i, *s));
+
+        let (slot, s) = match found {
+            Some(f) => f,
+            None => break,
+        };
+        q.slots[slot].state = State::Free;
+        drop(q);
+
+        unsafe { asm!("msr daifclr, #2") };
+        (s.callback)(s.arg);
+        unsafe { asm!("msr daifset, #2") };
+    }
+}
+
+/// sleep on the calling core until the counter reaches deadline,
+/// interrupts are taken while sleeping even if IRQs are masked by the caller
+pub fn sleep_until(deadline: u64) {
+    let _mask = IrqMask::new();
+    if add_timer(deadline, Context::Irq, nop, 0).is_none() {
+        // no timer is left
+        while now() < deadline {
+            spin_loop();
+        }
+        return;
+    }
+
+    while now() < deadline {
+        unsafe {
+            asm!(
+                "wfi
+                 msr daifclr, #2
+                 isb
+                 msr daifset, #2"
+            )
+        };
+    }
+}
+
+/// sleep on the calling core for usec microseconds
+pub fn sleep_usec(usec: u64) {
+    sleep_until(now() + timer::usec_to_ticks(usec));
+}
@@ -14,9 +14,13 @@ mod el0;
 mod el1;
 mod el2;
 mod el3;
+mod hyp;
+mod ipi;
+mod kthread;
+mod ktimer;
 mod memalloc;
-mod pager;
 mod psci;
+mod sched;
 
 #[macro_use]
 extern crate alloc;
@@ -70,7 +74,10 @@ fn init_primary() {
             panic!("failed to initialize MMU");
         }
     };
+    aarch64::percpu::init();
     driver::init();
+    memalloc::init();
+    aarch64::mmu::init_el0_image();
 
     // examples
     // driver::psci::pwr_domain_on(1); // wake up CPU #1 (Pine64)
@@ -92,7 +99,12 @@ fn init_primary() {
             print_msg("PSCI", "disabled");
             boot::run();
             aarch64::context::init_el2_regs();
-            el2::el2_to_el1();
+            if cfg!(feature = "hypervisor") {
+                print_msg("Hypervisor", "enabled");
+                hyp::run_guest();
+            } else {
+                el2::el2_to_el1();
+            }
         }
         _ => {
             panic!("execution level is not EL3");
@@ -106,6 +118,7 @@ fn init_secondary() -> ! {
     aarch64::cache::invalidate_l2_cache();
     aarch64::cache::invalidate_icache();
     aarch64::mmu::set_regs();
+    aarch64::percpu::init();
 
     match aarch64::cpu::get_current_el() {
         3 => {
@@ -238,6 +251,8 @@ fn panic(info: &PanicInfo) -> ! {
         driver::uart::puts("\n");
     }
 
+    aarch64::mmu::dump_on_panic();
+
     driver::delays::forever();
 }
 
@@ -0,0 +1,137 @@
+// debug heap, enabled by the debug_heap feature
+//
+// Every allocation is surrounded by redzones filled with canaries,
+// and the header just before the data records the size and the state.
+//
+// +-----------------+ <- base, allocated by the slab or buddy allocator
+// | canary          |
+// +-----------------+
+// | size            | 8 bytes
+// | state           | 8 bytes, STATE_ALIVE or STATE_FREED
+// +-----------------+ <- returned to the user, aligned to layout.align()
+// | data            |
+// +-----------------+
+// | canary          | redzone bytes
+// +-----------------+
+//
+// Freed data is filled by POISON. Objects of the slab allocator keep it until
+// they are allocated again, so it is checked at that time to find writes after free.
+// Blocks of the buddy allocator are not checked, because the free lists are
+// written into free blocks.
+
+use super::trace;
+use crate::driver::uart;
+
+use allocator::slab;
+use core::alloc::Layout;
+use core::cmp;
+
+const CANARY: u8 = 0xcb;
+const POISON: u8 = 0xdd;
+
+const STATE_ALIVE: u64 = 0xa11c_a7ed_a11c_a7ed;
+const STATE_FREED: u64 = 0xf4ee_df4e_edf4_eedf;
+
+/// the minimum redzone, the header and 16 bytes of canaries
+const MIN_REDZONE: usize = 32;
+const HEADER_SIZE: usize = 16;
+
+fn redzone(layout: &Layout) -> usize {
+    cmp::max(MIN_REDZONE, layout.align())
+}
+
+/// layout of the memory including redzones, None if it overflows
+pub fn outer_layout(layout: &Layout) -> Option<Layout> {
+    let size = layout.size().checked_add(redzone(layout) * 2)?;
+    Layout::from_size_align(size, layout.align()).ok()
+}
+
+unsafe fn get_size(ptr: *mut u8) -> *mut u64 {
+    ptr.sub(HEADER_SIZE) as *mut u64
+}
+
+unsafe fn get_state(ptr: *mut u8) -> *mut u64 {
+    ptr.sub(HEADER_SIZE - 8) as *mut u64
+}
+
+unsafe fn is_filled(start: *const u8, len: usize, val: u8) -> bool {
+    core::slice::from_raw_parts(start, len)
+        .iter()
+        .all(|b| *b == val)
+}
+
+/// print the offending allocation and its call sites, and stop
+fn report(msg: &str, ptr: *mut u8, size: u64) -> ! {
+    uart::puts("debug heap: ");
+    uart::puts(msg);
+    uart::puts(": 0x");
+    uart::hex(ptr as u64);
+    uart::puts(", ");
+    uart::decimal(size);
+    uart::puts(" bytes\n");
+
+    if cfg!(feature = "mem_trace") {
+        trace::print_site(ptr);
+    }
+
+    panic!("heap corruption");
+}
+
+/// set up redzones of the memory at base allocated by outer_layout(layout),
+/// return the pointer to the data
+pub unsafe fn init(base: *mut u8, layout: &Layout) -> *mut u8 {
+    let rz = redzone(layout);
+    let ptr = base.add(rz);
+
+    // the data of a freed slab object must be still poisoned
+    let outer = outer_layout(layout).unwrap();
+    if slab::fits(&outer) && *get_state(ptr) == STATE_FREED {
+        let old_size = *get_size(ptr);
+        let len = cmp::min(old_size as usize, layout.size());
+        if !is_filled(ptr, len, POISON) {
+            report("write after free", ptr, old_size);
+        }
+    }
+
+    base.write_bytes(CANARY, rz - HEADER_SIZE);
+    *get_size(ptr) = layout.size() as u64;
+    *get_state(ptr) = STATE_ALIVE;
+    ptr.add(layout.size()).write_bytes(CANARY, rz);
+
+    ptr
+}
+
+/// check the redzones of ptr returned by init, and poison the data,
+/// return the base address to be freed
+pub unsafe fn check_free(ptr: *mut u8, layout: &Layout) -> *mut u8 {
+    let rz = redzone(layout);
+    let base = ptr.sub(rz);
+    let size = *get_size(ptr);
+
+    match *get_state(ptr) {
+        STATE_ALIVE => (),
+        STATE_FREED => report("double free", ptr, size),
+        _ => report(
+            "freed invalid address or broken header",
+            ptr,
+            layout.size() as u64,
+        ),
+    }
+
+    if size != layout.size() as u64 {
+        report("freed by a different size", ptr, size);
+    }
+
+    if !is_filled(base, rz - HEADER_SIZE, CANARY) {
+        report("buffer underflow", ptr, size);
+    }
+
+    if !is_filled(ptr.add(layout.size()), rz, CANARY) {
+        report("buffer overflow", ptr, size);
+    }
+
+    ptr.write_bytes(POISON, layout.size());
+    *get_state(ptr) = STATE_FREED;
+
+    base
+}
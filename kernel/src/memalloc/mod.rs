@@ -1,15 +1,44 @@
-use crate::aarch64::lock;
-use crate::aarch64::mmu::PAGESIZE;
+pub mod debug;
+pub mod stats;
+pub mod trace;
+
+use crate::aarch64::mmu::{self, PAGESIZE};
+use crate::aarch64::preempt::PreemptGuard;
+use crate::aarch64::{cpu, lock, percpu};
+use crate::driver::topology::CORE_COUNT;
 use crate::driver::{delays, uart};
 
 use alloc::alloc::handle_alloc_error;
+use allocator::{buddy, magazine, slab};
 use core::alloc::{GlobalAlloc, Layout};
+use core::cmp;
+use core::mem::size_of;
+use core::ptr::{copy_nonoverlapping, null_mut};
+
+/// memory below this address is reachable by 32-bit DMA masters
+const DMA_LIMIT: usize = 1 << 32;
+
+/// allocators of a heap, placed at the head of the heap
+///
+/// EL3 (or EL2 without EL3), EL1 and EL0 have their own heaps in the memory map,
+/// so memory of the secure firmware is never handed to the normal world and vice versa.
+struct Arena {
+    lock: lock::LockVar,
+    buddy: buddy::Zones,
+    slab: slab::SlabAllocator,
 
-mod buddy;
-mod slab;
+    // caches of small objects, each core accesses only its own cache without the lock,
+    // so the allocator must not be called by interrupt handlers of the same core,
+    // and preemption is disabled while the cache is used
+    caches: [magazine::CpuCache; CORE_COUNT],
+    stats: stats::Stats, // counters of each core, updated only for EL0's heap
 
-static mut LOCK_VAR: lock::LockVar = lock::LockVar::new();
-static mut BUDDY_ALLOC: buddy::BuddyAlloc = buddy::BuddyAlloc::new(0, 0);
+    start: usize, // [start, end) is the heap
+    end: usize,
+    user: bool, // heap of EL0, statistics and call sites are recorded only for it
+}
+
+const EMPTY_CACHE: magazine::CpuCache = magazine::CpuCache::new();
 
 struct Allocator {}
 
@@ -18,84 +47,280 @@ static GLOBAL: Allocator = Allocator {};
 
 unsafe impl GlobalAlloc for Allocator {
     unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
-        LOCK_VAR.lock();
-        if slab::MAX_SLAB_SIZE >= layout.size() {
-            slab::slab_alloc(layout)
+        let arena = get_arena();
+        let ptr = if cfg!(feature = "debug_heap") {
+            arena.alloc_debug(&layout)
         } else {
-            match BUDDY_ALLOC.mem_alloc(layout.size()) {
-                Some(addr) => addr,
-                None => {
-                    handle_alloc_error(layout);
-                }
+            arena.alloc(&layout)
+        };
+
+        let class = slab::get_class(&layout);
+        if ptr.is_null() {
+            if arena.user {
+                arena.stats.on_fail(arena.cpu_id(), class);
+            }
+            handle_alloc_error(layout);
+        }
+
+        if arena.user {
+            arena.stats.on_alloc(arena.cpu_id(), class, layout.size());
+            if cfg!(feature = "mem_trace") {
+                trace::record(ptr, layout.size());
             }
         }
+        ptr
     }
 
     unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
-        LOCK_VAR.lock();
-        if slab::MAX_SLAB_SIZE >= layout.size() {
-            slab::slab_dealloc(ptr, layout)
+        let arena = get_arena();
+        if !arena.contains(ptr) {
+            panic!("freed memory of another exception level");
+        }
+
+        // check before forgetting the call site, to report it
+        let base = if cfg!(feature = "debug_heap") {
+            debug::check_free(ptr, &layout)
+        } else {
+            ptr
+        };
+
+        if arena.user {
+            let class = slab::get_class(&layout);
+            arena.stats.on_free(arena.cpu_id(), class, layout.size());
+            if cfg!(feature = "mem_trace") {
+                trace::forget(ptr);
+            }
+        }
+
+        if cfg!(feature = "debug_heap") {
+            arena.free(base, &debug::outer_layout(&layout).unwrap());
         } else {
-            BUDDY_ALLOC.mem_free(ptr);
+            arena.free(ptr, &layout);
         }
     }
-}
 
-#[alloc_error_handler]
-fn on_oom(layout: Layout) -> ! {
-    unsafe { LOCK_VAR.force_unlock() };
-    let size = layout.size() as u64;
-    uart::puts("memory allocation error: size = ");
-    uart::decimal(size);
-    uart::puts("\n");
-    delays::forever()
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let arena = get_arena();
+        if !arena.contains(ptr) {
+            panic!("resized memory of another exception level");
+        }
+
+        // redzones are moved together
+        if cfg!(feature = "debug_heap") {
+            return self.move_to(ptr, layout, new_size);
+        }
+
+        // the object stays in the same slab
+        let class = slab::get_class(&layout);
+        let cpu = arena.cpu_id();
+        if slab::can_resize(&layout, new_size) {
+            if arena.user {
+                arena.stats.on_resize(cpu, class, layout.size(), new_size);
+            }
+            return ptr;
+        }
+
+        // grow or shrink the buddy block
+        let new_layout = Layout::from_size_align_unchecked(new_size, layout.align());
+        if class.is_none() && !slab::fits(&new_layout) {
+            let _lock = arena.lock.lock();
+            if arena.buddy.mem_realloc(ptr, new_size) {
+                if arena.user {
+                    arena.stats.on_resize(cpu, class, layout.size(), new_size);
+                }
+                return ptr;
+            }
+        }
+
+        self.move_to(ptr, layout, new_size)
+    }
 }
 
-pub fn init(slab_start: usize, slab_size: usize, buddy_start: usize) {
-    unsafe {
-        slab::init(slab_start, slab_size);
-        BUDDY_ALLOC = buddy::BuddyAlloc::new(PAGESIZE as usize, buddy_start);
+impl Allocator {
+    /// move the object at ptr to a new region of new_size bytes
+    unsafe fn move_to(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let new_layout = Layout::from_size_align_unchecked(new_size, layout.align());
+        let new_ptr = self.alloc(new_layout);
+        copy_nonoverlapping(ptr, new_ptr, cmp::min(layout.size(), new_size));
+        self.dealloc(ptr, layout);
+        new_ptr
     }
 }
 
-pub fn test() {
-    let mut allc = buddy::BuddyAlloc::new(PAGESIZE as usize, 0);
+impl Arena {
+    const fn new() -> Arena {
+        Arena {
+            lock: lock::LockVar::new(),
+            buddy: buddy::Zones::new(),
+            slab: slab::SlabAllocator::new(),
+            caches: [EMPTY_CACHE; CORE_COUNT],
+            stats: stats::Stats::new(),
+            start: 0,
+            end: 0,
+            user: false,
+        }
+    }
 
-    let addr1 = match allc.mem_alloc(PAGESIZE as usize * 2) {
-        Some(addr) => addr,
-        None => {
-            uart::puts("failed alloc addr1\n");
-            return;
+    fn contains(&self, ptr: *mut u8) -> bool {
+        let addr = ptr as usize;
+        self.start <= addr && addr < self.end
+    }
+
+    /// EL1 stores the core position to TPIDRRO_EL0, which is read only for EL0,
+    /// and EL0 cannot use the per-CPU area
+    fn cpu_id(&self) -> usize {
+        if self.user {
+            cpu::tpidrro_el0::get() as usize
+        } else {
+            percpu::index()
         }
-    };
+    }
 
-    let addr2 = match allc.mem_alloc(PAGESIZE as usize * 3) {
-        Some(addr) => addr,
-        None => {
-            uart::puts("failed alloc addr2\n");
-            return;
+    /// allocate memory with redzones
+    unsafe fn alloc_debug(&mut self, layout: &Layout) -> *mut u8 {
+        let outer = match debug::outer_layout(layout) {
+            Some(outer) => outer,
+            None => return null_mut(),
+        };
+
+        let base = self.alloc(&outer);
+        if base.is_null() {
+            base
+        } else {
+            debug::init(base, layout)
+        }
+    }
+
+    unsafe fn alloc(&mut self, layout: &Layout) -> *mut u8 {
+        // lock free fast path, the task must not be switched or moved while using the cache
+        let _preempt = PreemptGuard::new();
+        let id = self.cpu_id();
+        let cache = &mut self.caches[id];
+        if let Some(ptr) = cache.alloc(layout) {
+            return ptr;
         }
-    };
 
-    let addr3 = match allc.mem_alloc(PAGESIZE as usize * 8) {
-        Some(addr) => addr,
-        None => {
-            uart::puts("failed alloc addr3\n");
+        let _lock = self.lock.lock();
+        if magazine::is_cached(layout) {
+            cache.refill(layout, &mut self.slab)
+        } else if slab::fits(layout) {
+            self.slab.alloc(*layout)
+        } else {
+            match self.buddy.mem_alloc_aligned(layout.size(), layout.align()) {
+                Some(addr) => addr,
+                None => null_mut(),
+            }
+        }
+    }
+
+    unsafe fn free(&mut self, ptr: *mut u8, layout: &Layout) {
+        // lock free fast path, the task must not be switched or moved while using the cache
+        let _preempt = PreemptGuard::new();
+        let id = self.cpu_id();
+        let cache = &mut self.caches[id];
+        if cache.free(ptr, layout) {
             return;
         }
-    };
 
-    allc.print();
-    uart::puts("\n");
+        let _lock = self.lock.lock();
+        if magazine::is_cached(layout) {
+            cache.drain(ptr, layout, &mut self.slab)
+        } else if slab::fits(layout) {
+            self.slab.dealloc(ptr, *layout)
+        } else {
+            self.buddy.mem_free(ptr);
+        }
+    }
+}
 
-    allc.mem_free(addr2);
-    allc.print();
-    uart::puts("\n");
+/// the heap of the current exception level, (start, end, whether it is of EL0)
+///
+/// EL1 accesses its heap through TTBR1.
+fn get_heap() -> (usize, usize, bool) {
+    let addr = mmu::get_memory_map();
 
-    allc.mem_free(addr1);
-    allc.print();
+    if mmu::is_el0() {
+        return (
+            addr.el0_heap_start as usize,
+            addr.el0_heap_end as usize,
+            true,
+        );
+    }
+
+    if cpu::get_current_el() == 1 {
+        let start = addr.heap_el1_start + mmu::EL1_ADDR_OFFSET;
+        let end = addr.heap_el1_end + mmu::EL1_ADDR_OFFSET;
+        (start as usize, end as usize, false)
+    } else {
+        (
+            addr.heap_firm_start as usize,
+            addr.heap_firm_end as usize,
+            false,
+        )
+    }
+}
+
+fn get_arena() -> &'static mut Arena {
+    let (start, _, _) = get_heap();
+    unsafe { &mut *(start as *mut Arena) }
+}
+
+#[alloc_error_handler]
+fn on_oom(layout: Layout) -> ! {
+    unsafe { get_arena().lock.force_unlock() };
+    uart::puts("memory allocation error: size = ");
+    uart::decimal(layout.size() as u64);
+    uart::puts(", align = ");
+    uart::decimal(layout.align() as u64);
     uart::puts("\n");
+    delays::forever()
+}
+
+/// initialize the heap of the current exception level
+///
+/// the arena takes the head pages of the heap, the buddy allocator takes the upper half
+/// of the rest and the slab allocator takes the lower half.
+/// The buddy allocator is divided into the DMA zone and the normal zone at DMA_LIMIT.
+pub fn init() {
+    let (start, end, user) = get_heap();
+    let page = PAGESIZE as usize;
+    let arena_size = ((size_of::<Arena>() - 1) / page + 1) * page;
+
+    let slab_start = start + arena_size;
+    if slab_start >= end {
+        panic!("no memory for heap");
+    }
+
+    let buddy_size = ((end - slab_start) >> 1) & !(page - 1);
+    let buddy_start = end - buddy_size;
+
+    // physical address of EL1's heap is start - EL1_ADDR_OFFSET
+    let dma_limit = if cpu::get_current_el() == 1 && !user {
+        DMA_LIMIT + mmu::EL1_ADDR_OFFSET as usize
+    } else {
+        DMA_LIMIT
+    };
+
+    let arena = start as *mut Arena;
+    unsafe {
+        arena.write(Arena::new());
+        let arena = &mut *arena;
+        arena.start = start;
+        arena.end = end;
+        arena.user = user;
 
-    allc.mem_free(addr3);
-    allc.print();
+        arena.slab.init(slab_start, buddy_start);
+
+        if buddy_start < dma_limit {
+            let end = cmp::min(end, dma_limit);
+            arena
+                .buddy
+                .add(buddy::ZoneKind::Dma, page, buddy_start, end);
+        }
+
+        if end > dma_limit {
+            let start = cmp::max(buddy_start, dma_limit);
+            arena.buddy.add(buddy::ZoneKind::Normal, page, start, end);
+        }
+    }
 }
@@ -0,0 +1,214 @@
+// statistics of the global allocator
+//
+// each core updates only its own counters, so the fast path of per-core caches
+// does not share cache lines with other cores, and the counters are summed by queries.
+// They are still atomic, because a task may be moved to another core
+// between reading the core position and updating the counter.
+// Bytes in use of a core wrap below 0 when it frees memory allocated by another core,
+// so only the sum is meaningful. The peak of the sum is sampled every SAMPLE_INTERVAL
+// allocations of each core and by queries.
+// EL1 reads them through copy_from_user by SYS_MEM_STATS,
+// so they are laid out as an array of u64.
+
+use crate::driver::topology::CORE_COUNT;
+use crate::driver::uart;
+
+use allocator::slab;
+use core::mem::size_of;
+use core::sync::atomic::{AtomicU64, Ordering};
+
+// queries of SYS_MEM_STATS
+pub const QUERY_PRINT: u64 = 0; // print every counter, and return bytes in use
+pub const QUERY_BYTES: u64 = 1; // bytes in use
+pub const QUERY_PEAK: u64 = 2; // the maximum bytes in use
+pub const QUERY_ALLOCS: u64 = 3; // the number of allocations
+pub const QUERY_FREES: u64 = 4; // the number of deallocations
+pub const QUERY_FAILED: u64 = 5; // the number of failed allocations
+
+// fields of a summed counter, bytes, peak, allocs, frees and failed
+const NUM_FIELDS: usize = 5;
+
+// total, buddy and slab classes in this order
+const NUM_COUNTERS: usize = slab::NUM_CLASSES + 2;
+const TOTAL: usize = 0;
+const BUDDY: usize = 1;
+
+const SAMPLE_INTERVAL: u64 = 64;
+
+#[repr(C)]
+struct Counter {
+    bytes: AtomicU64,
+    allocs: AtomicU64,
+    frees: AtomicU64,
+    failed: AtomicU64,
+}
+
+const COUNTER_WORDS: usize = size_of::<Counter>() / 8;
+const EMPTY_COUNTER: Counter = Counter::new();
+
+impl Counter {
+    const fn new() -> Counter {
+        Counter {
+            bytes: AtomicU64::new(0),
+            allocs: AtomicU64::new(0),
+            frees: AtomicU64::new(0),
+            failed: AtomicU64::new(0),
+        }
+    }
+}
+
+/// counters of a core, aligned to the cache line
+#[repr(C, align(64))]
+struct CpuStats {
+    counters: [Counter; NUM_COUNTERS],
+}
+
+const CPU_WORDS: usize = size_of::<CpuStats>() / 8;
+const EMPTY_CPU: CpuStats = CpuStats {
+    counters: [EMPTY_COUNTER; NUM_COUNTERS],
+};
+const ZERO: AtomicU64 = AtomicU64::new(0);
+
+/// counters of the cores followed by the sampled peaks
+#[repr(C)]
+pub struct Stats {
+    cpus: [CpuStats; CORE_COUNT],
+    peaks: [AtomicU64; NUM_COUNTERS],
+}
+
+/// the size of Stats in words
+pub const NUM_WORDS: usize = size_of::<Stats>() / 8;
+
+/// index of the counter of the slab class, or the buddy allocator if class is None
+fn get_index(class: Option<usize>) -> usize {
+    match class {
+        Some(c) => BUDDY + 1 + c,
+        None => BUDDY,
+    }
+}
+
+impl Stats {
+    pub const fn new() -> Stats {
+        Stats {
+            cpus: [EMPTY_CPU; CORE_COUNT],
+            peaks: [ZERO; NUM_COUNTERS],
+        }
+    }
+
+    /// the total counter and the counter of the class of the core
+    fn get_counters(&self, cpu: usize, class: Option<usize>) -> [&Counter; 2] {
+        let counters = &self.cpus[cpu].counters;
+        [&counters[TOTAL], &counters[get_index(class)]]
+    }
+
+    pub fn on_alloc(&self, cpu: usize, class: Option<usize>, size: usize) {
+        for c in self.get_counters(cpu, class).iter() {
+            c.allocs.fetch_add(1, Ordering::Relaxed);
+            c.bytes.fetch_add(size as u64, Ordering::Relaxed);
+        }
+
+        let allocs = self.cpus[cpu].counters[TOTAL]
+            .allocs
+            .load(Ordering::Relaxed);
+        if allocs % SAMPLE_INTERVAL == 0 {
+            self.sample();
+        }
+    }
+
+    pub fn on_free(&self, cpu: usize, class: Option<usize>, size: usize) {
+        for c in self.get_counters(cpu, class).iter() {
+            c.frees.fetch_add(1, Ordering::Relaxed);
+            c.bytes.fetch_sub(size as u64, Ordering::Relaxed);
+        }
+    }
+
+    pub fn on_fail(&self, cpu: usize, class: Option<usize>) {
+        for c in self.get_counters(cpu, class).iter() {
+            c.failed.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// an object is resized in place
+    pub fn on_resize(&self, cpu: usize, class: Option<usize>, old_size: usize, new_size: usize) {
+        for c in self.get_counters(cpu, class).iter() {
+            let diff = new_size.wrapping_sub(old_size) as u64;
+            c.bytes.fetch_add(diff, Ordering::Relaxed);
+        }
+    }
+
+    /// record the sums of bytes in use as peaks
+    fn sample(&self) {
+        for (i, peak) in self.peaks.iter().enumerate() {
+            let bytes = self.cpus.iter().fold(0u64, |sum, cpu| {
+                sum.wrapping_add(cpu.counters[i].bytes.load(Ordering::Relaxed))
+            });
+            peak.fetch_max(bytes, Ordering::Relaxed);
+        }
+    }
+}
+
+/// the address of the statistics, passed to SYS_MEM_STATS
+pub fn get_addr() -> u64 {
+    &super::get_arena().stats as *const Stats as u64
+}
+
+/// sum the counters of the cores in words copied from Stats,
+/// fields of each counter are in the order of queries
+fn sum(words: &[u64; NUM_WORDS]) -> [u64; NUM_FIELDS * NUM_COUNTERS] {
+    let peaks = &words[CPU_WORDS * CORE_COUNT..];
+    let mut sums = [0; NUM_FIELDS * NUM_COUNTERS];
+
+    for (i, s) in sums.chunks_mut(NUM_FIELDS).enumerate() {
+        let mut c = [0u64; COUNTER_WORDS];
+        for cpu in 0..CORE_COUNT {
+            let w = &words[CPU_WORDS * cpu + COUNTER_WORDS * i..];
+            for (c, w) in c.iter_mut().zip(w.iter()) {
+                *c = c.wrapping_add(*w);
+            }
+        }
+
+        let (bytes, allocs, frees, failed) = (c[0], c[1], c[2], c[3]);
+        s.copy_from_slice(&[bytes, peaks[i].max(bytes), allocs, frees, failed]);
+    }
+    sums
+}
+
+/// answer a query of SYS_MEM_STATS for words copied from Stats,
+/// return !0 if the query is unknown
+pub fn query(words: &[u64; NUM_WORDS], q: u64) -> u64 {
+    let sums = sum(words);
+    match q {
+        QUERY_PRINT => {
+            print(&sums);
+            sums[0]
+        }
+        QUERY_BYTES..=QUERY_FAILED => sums[(q - QUERY_BYTES) as usize],
+        _ => !0,
+    }
+}
+
+fn print_counter(name: &str, w: &[u64]) {
+    uart::puts(name);
+    for v in w.iter() {
+        uart::puts(" ");
+        uart::decimal(*v);
+    }
+    uart::puts("\n");
+}
+
+fn print(words: &[u64; NUM_FIELDS * NUM_COUNTERS]) {
+    uart::puts("memory allocator: bytes peak allocs frees failed\n");
+    print_counter("total", &words[0..NUM_FIELDS]);
+    print_counter("buddy", &words[NUM_FIELDS..NUM_FIELDS * 2]);
+
+    for (i, size) in slab::CLASS_SIZES.iter().enumerate() {
+        let w = &words[NUM_FIELDS * (i + 2)..NUM_FIELDS * (i + 3)];
+        if w[2] == 0 && w[4] == 0 {
+            continue; // never used
+        }
+
+        uart::puts("slab");
+        uart::decimal(*size as u64);
+        print_counter("", w);
+    }
+}
@@ -0,0 +1,134 @@
+// tracking of live allocations and their call sites, enabled by the mem_trace feature
+//
+// The call sites are return addresses found by walking the frame pointers,
+// so build with -C force-frame-pointers=yes (see Makefile).
+// Resolve them by addr2line or objdump of the kernel image.
+// EL1 reads the records through copy_from_user by SYS_MEM_LEAKS.
+
+use crate::aarch64::lock;
+use crate::driver::uart;
+
+use core::mem::size_of;
+
+/// the maximum number of traced allocations
+pub const MAX_RECORDS: usize = 1024;
+
+/// the number of return addresses of a record
+const DEPTH: usize = 6;
+
+/// the maximum size of a stack frame
+const MAX_FRAME: u64 = 1024 * 1024 * 2;
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+pub struct Record {
+    addr: u64, // 0 if empty
+    size: u64,
+    sites: [u64; DEPTH],
+}
+
+/// the size of Record in bytes
+pub const RECORD_SIZE: usize = size_of::<Record>();
+
+const EMPTY_RECORD: Record = Record {
+    addr: 0,
+    size: 0,
+    sites: [0; DEPTH],
+};
+
+static mut LOCK_VAR: lock::LockVar = lock::LockVar::new();
+static mut RECORDS: [Record; MAX_RECORDS] = [EMPTY_RECORD; MAX_RECORDS];
+static mut NUM_DROPPED: u64 = 0; // allocations not traced because of no space
+
+/// return addresses of the callers
+#[inline(always)]
+fn backtrace(sites: &mut [u64; DEPTH]) {
+    let mut fp: u64;
+    unsafe { asm!("mov {}, x29", out(reg) fp) };
+
+    for s in sites.iter_mut() {
+        if fp == 0 || fp & 0xf != 0 {
+            break;
+        }
+
+        let (next, lr) = unsafe { (*(fp as *const u64), *((fp + 8) as *const u64)) };
+        *s = lr;
+
+        // the stack grows down
+        if next <= fp || next - fp > MAX_FRAME {
+            break;
+        }
+        fp = next;
+    }
+}
+
+#[inline(always)]
+pub fn record(addr: *mut u8, size: usize) {
+    let mut r = Record {
+        addr: addr as u64,
+        size: size as u64,
+        sites: [0; DEPTH],
+    };
+    backtrace(&mut r.sites);
+
+    let _lock = unsafe { LOCK_VAR.lock() };
+    let records = unsafe { &mut RECORDS };
+    match records.iter_mut().find(|r| r.addr == 0) {
+        Some(e) => *e = r,
+        None => unsafe { NUM_DROPPED += 1 },
+    }
+}
+
+pub fn forget(addr: *mut u8) {
+    let _lock = unsafe { LOCK_VAR.lock() };
+    let records = unsafe { &mut RECORDS };
+    if let Some(e) = records.iter_mut().find(|r| r.addr == addr as u64) {
+        e.addr = 0;
+    }
+}
+
+/// the address of the records and the number of them, passed to SYS_MEM_LEAKS
+pub fn get_records() -> (u64, u64) {
+    unsafe {
+        if NUM_DROPPED > 0 {
+            uart::puts("mem_trace: ");
+            uart::decimal(NUM_DROPPED);
+            uart::puts(" allocations are not traced\n");
+        }
+        (RECORDS.as_ptr() as u64, MAX_RECORDS as u64)
+    }
+}
+
+/// print a record copied by EL1, return false if it is empty
+pub fn print(bytes: &[u8; RECORD_SIZE]) -> bool {
+    let r: Record = unsafe { core::ptr::read_unaligned(bytes.as_ptr() as *const Record) };
+    if r.addr == 0 {
+        return false;
+    }
+
+    print_record(&r);
+    true
+}
+
+/// print the call sites of the live allocation at addr
+pub fn print_site(addr: *mut u8) {
+    let _lock = unsafe { LOCK_VAR.lock() };
+    let records = unsafe { &RECORDS };
+    match records.iter().find(|r| r.addr == addr as u64) {
+        Some(r) => print_record(r),
+        None => uart::puts("mem_trace: no record\n"),
+    }
+}
+
+fn print_record(r: &Record) {
+    uart::puts("0x");
+    uart::hex(r.addr);
+    uart::puts(" ");
+    uart::decimal(r.size);
+    uart::puts(" bytes, called from");
+    for s in r.sites.iter().take_while(|s| **s != 0) {
+        uart::puts(" 0x");
+        uart::hex(*s);
+    }
+    uart::puts("\n");
+}
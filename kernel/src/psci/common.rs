@@ -1,6 +1,7 @@
 use super::data;
+use crate::aarch64::percpu;
 use crate::driver;
-use crate::driver::{defs, psci::PsciPowerState, topology};
+use crate::driver::{defs, psci::PsciPowerState};
 
 /// PSCI helper function to get the parent nodes corresponding to a cpu_index.
 pub(crate) fn get_parent_pwr_domain_nodes(cpu_idx: usize) -> [usize; defs::MAX_PWR_LVL as usize] {
@@ -21,7 +22,7 @@ pub(crate) fn get_parent_pwr_domain_nodes(cpu_idx: usize) -> [usize; defs::MAX_P
 /// each power domain has emerged from.
 pub(crate) fn get_target_local_pwr_states(end_pwrlvl: u8) -> PsciPowerState {
     let mut target_state = [0; (defs::MAX_PWR_LVL + 1) as usize];
-    let idx = topology::core_pos();
+    let idx = percpu::index();
     let mut parent_idx = data::get_cpu_pd_parent_node(idx);
 
     // Copy the local power state from node to state_info
@@ -44,7 +45,7 @@ pub(crate) fn get_target_local_pwr_states(end_pwrlvl: u8) -> PsciPowerState {
 /// enter. This function will be called after coordination of requested power
 /// states has been done for each power level.
 pub(crate) fn set_target_local_pwr_states(end_pwlvl: u8, target_state: &PsciPowerState) {
-    let idx = topology::core_pos();
+    let idx = percpu::index();
     data::set_cpu_local_state(idx, target_state[data::PSCI_CPU_PWR_LVL as usize]);
 
     let mut parent_idx = data::get_cpu_pd_parent_node(idx);
@@ -75,7 +76,7 @@ pub(crate) fn set_target_local_pwr_states(end_pwlvl: u8, target_state: &PsciPowe
 /// This function will only be invoked with data cache enabled and while
 /// powering down a core.
 pub(crate) fn do_state_coordination(end_pwrlvl: usize, state_info: &mut PsciPowerState) {
-    let cpu_idx = topology::core_pos();
+    let cpu_idx = percpu::index();
     let mut parent_idx = data::get_cpu_pd_parent_node(cpu_idx);
 
     let mut n = data::PSCI_CPU_PWR_LVL as usize + 1;
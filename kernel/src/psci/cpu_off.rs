@@ -1,8 +1,8 @@
 use super::common;
 use super::data;
-use crate::aarch64::{cortex, cpu};
+use crate::aarch64::{cortex, cpu, percpu};
 use crate::driver;
-use crate::driver::{defs, psci::PsciPowerState, topology};
+use crate::driver::{defs, psci::PsciPowerState};
 
 /// Top level handler which is called when a cpu wants to power itself down.
 /// It's assumed that along with turning the cpu power domain off, power
@@ -18,7 +18,7 @@ use crate::driver::{defs, psci::PsciPowerState, topology};
 pub(crate) fn start(end_pwrlvl: usize) {
     // TODO: buggy
 
-    let idx = topology::core_pos();
+    let idx = percpu::index();
     data::flush_cache_cpu_state(idx);
 
     // Construct the psci_power_state for CPU_OFF
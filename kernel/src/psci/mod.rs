@@ -8,7 +8,6 @@ mod suspend;
 
 use crate::aarch64::{context, cpu};
 use crate::driver;
-use crate::driver::topology;
 
 // Defines for runtime services function ids
 pub const PSCI_VERSION: u32 = 0x84000000;
@@ -88,7 +87,7 @@ pub fn is_psci_fid(fid: u32) -> bool {
 
 /// PSCI top level handler for servicing SMCs.
 pub fn smc_handler(smc_fid: u32, x1: usize, x2: usize, x3: usize) {
-    let ctx = context::get_ctx(topology::core_pos(), false);
+    let ctx = context::get_my_ctx(false);
     ctx.save_fpregs();
 
     let is_secure = cpu::is_secure();
@@ -0,0 +1,596 @@
+// preemptive round-robin scheduler of EL1
+//
+// Each core has a run queue of ready tasks, and the virtual timer of the core
+// ticks every TIME_SLICE_USEC to preempt the running task at the end of the IRQ.
+// A task is a kernel task running at EL1, or a user task running at EL0,
+// and every task has its own stack of EL1, so a task can block in a system call.
+// The stack is allocated from the heap of EL1, or given by the caller of spawn_on_stack,
+// in which case the dead task is kept until reap is called to free the stack safely.
+// The flow of each core started by el1_entry becomes the main task of the core.
+//
+// Tasks are switched by switch_regs, which saves the callee-saved registers
+// into GpRegs, and the other registers are saved by the caller or the exception vector.
+// FPRegs, SP_EL0, TPIDR_EL0 and the preemption counts are saved and restored for every switch.
+// A task is not preempted while preemption is disabled, see preempt.rs,
+// for example while it holds a lock or uses the per-core caches of the allocator.
+//
+// A core with no ready task takes a migratable task from the run queue of another core,
+// or waits for interrupts.
+// All of the tasks and the run queues are guarded by SCHED,
+// which is held with IRQs masked and released before switching.
+
+use crate::aarch64::context::{FPRegs, GpRegs};
+use crate::aarch64::lock::{IrqMask, Mutex};
+use crate::aarch64::{cpu, mmu, percpu, preempt};
+use crate::driver::timer::{self, Timer};
+use crate::driver::topology::CORE_COUNT;
+use crate::{ipi, ktimer};
+
+use alloc::alloc::{alloc, dealloc, Layout};
+use core::sync::atomic::{AtomicBool, Ordering};
+
+/// the maximum number of tasks
+pub const MAX_TASKS: usize = 32;
+
+/// the size of the stack of EL1 of a task
+pub const KSTACK_SIZE: usize = 64 * 1024;
+
+/// the length of a time slice
+pub const TIME_SLICE_USEC: u64 = 10_000;
+
+pub type TaskId = usize;
+
+#[derive(Copy, Clone, PartialEq, Eq)]
+enum State {
+    Free,
+    Ready,
+    Running,
+    Blocked,
+    Dead,
+}
+
+#[derive(Copy, Clone)]
+enum Entry {
+    Main, // the flow started by el1_entry
+    Kernel { func: fn(usize), arg: usize },
+    User { pc: u64, arg: u64, sp: u64 },
+}
+
+/// registers of a task switched out
+#[derive(Copy, Clone)]
+#[repr(C)]
+struct Context {
+    regs: GpRegs, // x19 to x30, and elr is the address to resume
+    sp: u64,
+    fpregs: FPRegs,
+    sp_el0: u64,
+    tpidr_el0: u64,
+    preempt: (usize, usize), // the preemption counts of EL1 and EL0
+}
+
+const EMPTY_CONTEXT: Context = Context {
+    regs: GpRegs::new(),
+    sp: 0,
+    fpregs: FPRegs::new(),
+    sp_el0: 0,
+    tpidr_el0: 0,
+    preempt: (0, 0),
+};
+
+#[derive(Copy, Clone)]
+struct Task {
+    state: State,
+    name: &'static str,
+    core: usize,   // the core of the run queue
+    migrate: bool, // may be moved to another core
+    on_cpu: bool,  // running, or its registers are not saved yet
+    entry: Entry,
+    kstack: *mut u8, // null for main tasks
+    own_stack: bool, // kstack was allocated by spawn_task
+    wakeup: bool,    // wake was called while not blocked
+    ctx: Context,
+}
+
+const EMPTY_TASK: Task = Task {
+    state: State::Free,
+    name: "",
+    core: 0,
+    migrate: false,
+    on_cpu: false,
+    entry: Entry::Main,
+    kstack: core::ptr::null_mut(),
+    own_stack: false,
+    wakeup: false,
+    ctx: EMPTY_CONTEXT,
+};
+
+struct RunQueue {
+    ids: [TaskId; MAX_TASKS], // ready tasks in FIFO order
+    len: usize,
+    current: TaskId,
+    prev: TaskId, // the task switched out last
+    ready: bool,  // init has been called
+}
+
+const EMPTY_QUEUE: RunQueue = RunQueue {
+    ids: [0; MAX_TASKS],
+    len: 0,
+    current: 0,
+    prev: 0,
+    ready: false,
+};
+
+impl RunQueue {
+    fn push(&mut self, id: TaskId) {
+        self.ids[self.len] = id;
+        self.len += 1;
+    }
+
+    fn remove(&mut self, i: usize) -> TaskId {
+        let id = self.ids[i];
+        self.ids.copy_within(i + 1..self.len, i);
+        self.len -= 1;
+        id
+    }
+
+    fn pop(&mut self) -> Option<TaskId> {
+        if self.len == 0 {
+            None
+        } else {
+            Some(self.remove(0))
+        }
+    }
+}
+
+struct Sched {
+    tasks: [Task; MAX_TASKS],
+    queues: [RunQueue; CORE_COUNT],
+}
+
+// tasks contain raw pointers to their stacks, which are accessed only under the lock
+unsafe impl Send for Sched {}
+
+static SCHED: Mutex<Sched> = Mutex::new(Sched {
+    tasks: [EMPTY_TASK; MAX_TASKS],
+    queues: [EMPTY_QUEUE; CORE_COUNT],
+});
+
+const NOT_SET: AtomicBool = AtomicBool::new(false);
+static NEED_RESCHED: [AtomicBool; CORE_COUNT] = [NOT_SET; CORE_COUNT];
+static IN_SCHEDULE: [AtomicBool; CORE_COUNT] = [NOT_SET; CORE_COUNT];
+
+fn kstack_layout() -> Layout {
+    Layout::from_size_align(KSTACK_SIZE, 16).unwrap()
+}
+
+impl Sched {
+    /// a free slot, and the stack of the dead task which had the slot,
+    /// which the caller frees after releasing SCHED
+    fn alloc_task(&mut self) -> Option<(TaskId, Option<*mut u8>)> {
+        let id = self.tasks.iter().position(|t| {
+            t.state == State::Free || (t.state == State::Dead && !t.on_cpu && t.own_stack)
+        })?;
+
+        let t = &mut self.tasks[id];
+        let dead = if t.own_stack { Some(t.kstack) } else { None };
+        *t = EMPTY_TASK;
+        Some((id, dead))
+    }
+
+    /// take a ready task from the run queue of another core
+    fn steal(&mut self, core: usize) -> Option<TaskId> {
+        for other in (0..CORE_COUNT).filter(|c| *c != core) {
+            let q = &self.queues[other];
+            let found = q.ids[..q.len].iter().position(|id| {
+                let t = &self.tasks[*id];
+                t.migrate && !t.on_cpu
+            });
+
+            if let Some(i) = found {
+                let id = self.queues[other].remove(i);
+                self.tasks[id].core = core;
+                return Some(id);
+            }
+        }
+        None
+    }
+}
+
+/// free the stack of a dead task taken by alloc_task,
+/// SCHED must not be held, because the heap of EL1 takes its lock with IRQs enabled
+fn free_dead_stack(dead: Option<*mut u8>) {
+    if let Some(kstack) = dead {
+        unsafe { dealloc(kstack, kstack_layout()) };
+    }
+}
+
+/// make the calling flow the main task of the calling core and start the tick,
+/// called on each core at EL1 after ktimer::init
+pub fn init() {
+    let core = percpu::index();
+    let dead = {
+        let mut s = SCHED.lock_irq();
+        let (id, dead) = match s.alloc_task() {
+            Some(slot) => slot,
+            None => panic!("too many tasks"),
+        };
+
+        s.tasks[id] = Task {
+            state: State::Running,
+            name: "main",
+            core,
+            on_cpu: true,
+            ..EMPTY_TASK
+        };
+
+        let q = &mut s.queues[core];
+        q.current = id;
+        q.prev = id;
+        q.ready = true;
+        dead
+    };
+    free_dead_stack(dead);
+
+    timer::set_periodic(Timer::Virtual, timer::usec_to_ticks(TIME_SLICE_USEC), tick);
+}
+
+/// whether tasks are scheduled on the calling core
+pub fn is_ready() -> bool {
+    if !ktimer::is_ready() {
+        return false;
+    }
+
+    let s = SCHED.lock_irq();
+    s.queues[percpu::index()].ready
+}
+
+fn spawn_task(name: &'static str, entry: Entry, migrate: bool) -> Option<TaskId> {
+    let kstack = unsafe { alloc(kstack_layout()) };
+    if kstack.is_null() {
+        return None;
+    }
+
+    let id = add_task(name, entry, migrate, kstack, KSTACK_SIZE, true);
+    if id.is_none() {
+        unsafe { dealloc(kstack, kstack_layout()) };
+    }
+    id
+}
+
+fn add_task(
+    name: &'static str,
+    entry: Entry,
+    migrate: bool,
+    kstack: *mut u8,
+    size: usize,
+    own_stack: bool,
+) -> Option<TaskId> {
+    let core = percpu::index();
+    let mut s = SCHED.lock_irq();
+    let (id, dead) = s.alloc_task()?;
+
+    let mut ctx = EMPTY_CONTEXT;
+    ctx.regs.elr = task_start as *const () as u64;
+    ctx.sp = kstack as u64 + size as u64;
+
+    s.tasks[id] = Task {
+        state: State::Ready,
+        name,
+        core,
+        migrate,
+        on_cpu: false,
+        entry,
+        kstack,
+        own_stack,
+        wakeup: false,
+        ctx,
+    };
+    s.queues[core].push(id);
+    drop(s);
+
+    free_dead_stack(dead);
+    Some(id)
+}
+
+/// run func(arg) at EL1 as a new task on the calling core,
+/// None if there is no free slot or memory
+pub fn spawn(name: &'static str, func: fn(usize), arg: usize, migrate: bool) -> Option<TaskId> {
+    spawn_task(name, Entry::Kernel { func, arg }, migrate)
+}
+
+/// run func(arg) at EL1 as a new task on the stack [kstack, kstack + size),
+/// which must not be freed until reap succeeds
+pub fn spawn_on_stack(
+    name: &'static str,
+    func: fn(usize),
+    arg: usize,
+    migrate: bool,
+    kstack: *mut u8,
+    size: usize,
+) -> Option<TaskId> {
+    add_task(
+        name,
+        Entry::Kernel { func, arg },
+        migrate,
+        kstack,
+        size,
+        false,
+    )
+}
+
+/// release the slot of the dead task spawned by spawn_on_stack,
+/// return false if it has not finished running on its stack yet
+pub fn reap(id: TaskId) -> bool {
+    let mut s = SCHED.lock_irq();
+    let t = &mut s.tasks[id];
+    if t.state != State::Dead || t.on_cpu || t.own_stack {
+        return false;
+    }
+
+    *t = EMPTY_TASK;
+    true
+}
+
+/// run pc with x0 = arg at EL0 on the stack sp as a new task, see SYS_SPAWN
+pub fn spawn_user(name: &'static str, pc: u64, arg: u64, sp: u64) -> Option<TaskId> {
+    spawn_task(name, Entry::User { pc, arg, sp }, true)
+}
+
+/// the first code of a new task, switched from schedule
+fn task_start() -> ! {
+    finish_switch();
+
+    let (entry, kstack) = {
+        let s = SCHED.lock();
+        let t = &s.tasks[s.queues[percpu::index()].current];
+        (t.entry, t.kstack as u64)
+    };
+
+    match entry {
+        Entry::Kernel { func, arg } => {
+            unsafe { asm!("msr daifclr, #2") };
+            func(arg);
+            exit();
+        }
+        Entry::User { pc, arg, sp } => {
+            cpu::sp_el0::set(sp);
+            cpu::spsr_el1::set(0); // EL0t
+            cpu::elr_el1::set(pc);
+
+            // exceptions from EL0 start from the top of the stack again
+            mmu::enter_el0(kstack + KSTACK_SIZE as u64, arg)
+        }
+        Entry::Main => panic!("main task is started"),
+    }
+}
+
+/// the ID of the running task of the calling core
+pub fn current() -> TaskId {
+    let s = SCHED.lock_irq();
+    s.queues[percpu::index()].current
+}
+
+/// the name of the task
+pub fn name(id: TaskId) -> &'static str {
+    let s = SCHED.lock_irq();
+    s.tasks[id].name
+}
+
+/// called by the virtual timer every time slice
+fn tick() {
+    set_need_resched();
+}
+
+/// switch tasks of the calling core at the end of the IRQ, see ipi::resched
+pub fn set_need_resched() {
+    NEED_RESCHED[percpu::index()].store(true, Ordering::Relaxed);
+}
+
+/// switch to another task if the time slice is over,
+/// called by the IRQ handlers of EL1 at the end,
+/// the interrupted task keeps running while it disables preemption
+pub fn preempt() {
+    let core = percpu::index();
+    if preempt::is_enabled() && NEED_RESCHED[core].swap(false, Ordering::Relaxed) {
+        schedule(State::Ready);
+    }
+}
+
+/// give the core to another ready task
+pub fn yield_now() {
+    schedule(State::Ready);
+}
+
+/// stop the calling task until wake is called,
+/// return at once if wake has been called since the last block,
+/// so callers check their condition again after it returns
+pub fn block() {
+    schedule(State::Blocked);
+}
+
+/// make the blocked task ready, return false if it is not blocked,
+/// in which case the next block of a running or ready task returns at once
+pub fn wake(id: TaskId) -> bool {
+    let mut s = SCHED.lock_irq();
+    match s.tasks[id].state {
+        State::Blocked => (),
+        State::Running | State::Ready => {
+            s.tasks[id].wakeup = true;
+            return false;
+        }
+        _ => return false,
+    }
+
+    s.tasks[id].state = State::Ready;
+    let core = s.tasks[id].core;
+    s.queues[core].push(id);
+    drop(s);
+
+    // the core may be idle until the next tick
+    if core != percpu::index() {
+        ipi::resched(1 << core);
+    }
+    true
+}
+
+fn wake_timer(id: usize) {
+    wake(id);
+}
+
+/// sleep the calling task for usec microseconds
+pub fn sleep_usec(usec: u64) {
+    let deadline = ktimer::now() + timer::usec_to_ticks(usec);
+    let _mask = IrqMask::new();
+
+    // the timer cannot fire before blocking, because IRQs are masked
+    if ktimer::add_timer(deadline, ktimer::Context::Irq, wake_timer, current()).is_none() {
+        ktimer::sleep_until(deadline);
+        return;
+    }
+
+    // block returns early if the task has been woken for another reason
+    while ktimer::now() < deadline {
+        block();
+    }
+}
+
+/// finish the calling task
+pub fn exit() -> ! {
+    schedule(State::Dead);
+    panic!("dead task is scheduled");
+}
+
+/// switch the running task to state, and run the next ready task
+fn schedule(state: State) {
+    let core = percpu::index();
+    let _mask = IrqMask::new();
+    if IN_SCHEDULE[core].swap(true, Ordering::Relaxed) {
+        return; // an interrupt while waiting for a ready task
+    }
+
+    let mut s = SCHED.lock();
+    if !s.queues[core].ready {
+        IN_SCHEDULE[core].store(false, Ordering::Relaxed);
+        return;
+    }
+
+    let prev = s.queues[core].current;
+    if state == State::Blocked && s.tasks[prev].wakeup {
+        s.tasks[prev].wakeup = false;
+        IN_SCHEDULE[core].store(false, Ordering::Relaxed);
+        return;
+    }
+
+    s.tasks[prev].state = state;
+    if state == State::Ready {
+        s.queues[core].push(prev);
+    }
+
+    let next = loop {
+        if let Some(id) = s.queues[core].pop() {
+            break id;
+        }
+        if let Some(id) = s.steal(core) {
+            break id;
+        }
+
+        // no ready task, wait for interrupts to wake the previous one
+        drop(s);
+        unsafe {
+            asm!(
+                "wfi
+                 msr daifclr, #2
+                 isb
+                 msr daifset, #2"
+            )
+        };
+        s = SCHED.lock();
+    };
+
+    s.tasks[next].state = State::Running;
+    if next == prev {
+        IN_SCHEDULE[core].store(false, Ordering::Relaxed);
+        return;
+    }
+
+    s.tasks[next].on_cpu = true;
+    s.queues[core].current = next;
+    s.queues[core].prev = prev;
+
+    let prev_ctx = &mut s.tasks[prev].ctx as *mut Context;
+    let next_ctx = &s.tasks[next].ctx as *const Context;
+    drop(s);
+
+    unsafe {
+        (*prev_ctx).fpregs.save();
+        (*prev_ctx).sp_el0 = cpu::sp_el0::get();
+        (*prev_ctx).tpidr_el0 = cpu::tpidr_el0::get();
+        (*prev_ctx).preempt = preempt::get_count();
+
+        switch_regs(prev_ctx, next_ctx);
+    }
+
+    // switched back from another task, possibly on another core
+    finish_switch();
+}
+
+/// called by the task switched to, after the registers of the previous task are saved
+fn finish_switch() {
+    let core = percpu::index();
+    let ctx = {
+        let mut s = SCHED.lock();
+        let q = &s.queues[core];
+        let (prev, current) = (q.prev, q.current);
+        s.tasks[prev].on_cpu = false;
+        &s.tasks[current].ctx as *const Context
+    };
+
+    unsafe {
+        cpu::sp_el0::set((*ctx).sp_el0);
+        cpu::tpidr_el0::set((*ctx).tpidr_el0);
+        (*ctx).fpregs.restore();
+        preempt::set_count((*ctx).preempt);
+    }
+
+    IN_SCHEDULE[core].store(false, Ordering::Relaxed);
+}
+
+/// save the callee-saved registers and SP to prev, and resume next
+#[inline(never)]
+unsafe fn switch_regs(prev: *mut Context, next: *const Context) {
+    asm!(
+        "stp x19, x20, [x0, #8 * 19]
+         stp x21, x22, [x0, #8 * 21]
+         stp x23, x24, [x0, #8 * 23]
+         stp x25, x26, [x0, #8 * 25]
+         stp x27, x28, [x0, #8 * 27]
+         stp x29, x30, [x0, #8 * 29]
+         adr x9, 1f
+         str x9, [x0, #8 * 31]
+         mov x9, sp
+         str x9, [x0, #8 * 34]
+
+         ldp x19, x20, [x1, #8 * 19]
+         ldp x21, x22, [x1, #8 * 21]
+         ldp x23, x24, [x1, #8 * 23]
+         ldp x25, x26, [x1, #8 * 25]
+         ldp x27, x28, [x1, #8 * 27]
+         ldp x29, x30, [x1, #8 * 29]
+         ldr x9, [x1, #8 * 34]
+         mov sp, x9
+         ldr x9, [x1, #8 * 31]
+         br x9
+     1:",
+        inout("x0") prev => _,
+        inout("x1") next => _,
+        out("x2") _, out("x3") _, out("x4") _, out("x5") _, out("x6") _, out("x7") _,
+        out("x8") _, out("x9") _, out("x10") _, out("x11") _, out("x12") _, out("x13") _,
+        out("x14") _, out("x15") _, out("x16") _, out("x17") _, out("x18") _, out("lr") _,
+        out("v0") _, out("v1") _, out("v2") _, out("v3") _, out("v4") _, out("v5") _,
+        out("v6") _, out("v7") _, out("v8") _, out("v9") _, out("v10") _, out("v11") _,
+        out("v12") _, out("v13") _, out("v14") _, out("v15") _, out("v16") _, out("v17") _,
+        out("v18") _, out("v19") _, out("v20") _, out("v21") _, out("v22") _, out("v23") _,
+        out("v24") _, out("v25") _, out("v26") _, out("v27") _, out("v28") _, out("v29") _,
+        out("v30") _, out("v31") _,
+    );
+}